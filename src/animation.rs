@@ -0,0 +1,351 @@
+use std::{convert::Infallible, io::Write};
+
+use crate::{
+    parsers, render_to_rgba, Buffer, BufferParser, CallbackAction, Caret, EngineResult,
+    ParserError, RenderOptions, RgbaImage,
+};
+
+/// Options for [`AnsiAnimation`].
+#[derive(Clone, Debug)]
+pub struct AnimationOptions {
+    /// Baud rate used until the stream changes it, 0 is unlimited.
+    pub baud_rate: u32,
+    /// Time between two captured frames.
+    pub frame_interval_ms: u32,
+    /// Duration of one blink phase.
+    pub blink_interval_ms: u32,
+    pub render_options: RenderOptions,
+}
+
+impl AnimationOptions {
+    pub fn new() -> Self {
+        AnimationOptions {
+            baud_rate: 14400,
+            frame_interval_ms: 100,
+            blink_interval_ms: 500,
+            render_options: RenderOptions::default(),
+        }
+    }
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnimationFrame {
+    pub image: RgbaImage,
+    pub delay_ms: u32,
+}
+
+/// Plays an ANSI byte stream (`ANSiMation`) at an emulated baud rate and captures frames.
+///
+/// A byte is counted as 10 bits (8N1). Baud rate changes in the stream
+/// (`ESC[0;<n>*r`) apply immediately, the remaining budget of the current frame is rescaled.
+pub struct AnsiAnimation {
+    buffer: Buffer,
+    caret: Caret,
+    parser: parsers::ansi::Parser,
+    data: Vec<u8>,
+    pos: usize,
+    options: AnimationOptions,
+    time_ms: u64,
+    budget: f64,
+}
+
+impl AnsiAnimation {
+    /// Creates a player for a file, the SAUCE record is used for the screen width & ice colors.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the SAUCE record is invalid.
+    pub fn new(bytes: &[u8], options: AnimationOptions) -> EngineResult<Self> {
+        let mut buffer = Buffer::new();
+        let (_, file_size) = buffer.read_sauce_info(bytes)?;
+        if buffer.get_buffer_width() == 0 {
+            buffer.set_buffer_width(80);
+        }
+        buffer.set_buffer_height(25);
+        buffer.is_terminal_buffer = true;
+        buffer.terminal_state.set_baud_rate(options.baud_rate);
+
+        Ok(Self {
+            buffer,
+            caret: Caret::default(),
+            parser: parsers::ansi::Parser::default(),
+            data: bytes[..file_size.min(bytes.len())].to_vec(),
+            pos: 0,
+            options,
+            time_ms: 0,
+            budget: 0.0,
+        })
+    }
+
+    pub fn get_buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes_per_ms(&self) -> Option<f64> {
+        match self.buffer.terminal_state.get_baud_rate() {
+            0 => None,
+            baud_rate => Some(baud_rate as f64 / 10.0 / 1000.0),
+        }
+    }
+
+    /// Plays one frame interval and renders the screen.
+    pub fn next_frame(&mut self) -> AnimationFrame {
+        let interval = self.options.frame_interval_ms.max(1);
+        self.budget += self.bytes_per_ms().unwrap_or(0.0) * interval as f64;
+
+        while self.pos < self.data.len() {
+            let Some(old_rate) = self.bytes_per_ms() else {
+                self.feed_byte();
+                continue;
+            };
+            if self.budget < 1.0 {
+                break;
+            }
+            self.budget -= 1.0;
+            if let CallbackAction::ChangeBaudRate(_) = self.feed_byte() {
+                if let Some(new_rate) = self.bytes_per_ms() {
+                    self.budget *= new_rate / old_rate;
+                }
+            }
+        }
+        if self.is_finished() {
+            self.budget = 0.0;
+        }
+
+        let blink_interval = self.options.blink_interval_ms.max(1) as u64;
+        let mut render_options = self.options.render_options.clone();
+        render_options.blink_on = (self.time_ms / blink_interval).is_multiple_of(2);
+        self.time_ms += interval as u64;
+
+        AnimationFrame {
+            image: render_to_rgba(&self.buffer, &render_options),
+            delay_ms: interval,
+        }
+    }
+
+    /// Unsupported or invalid sequences are skipped, the animation keeps playing.
+    fn feed_byte(&mut self) -> CallbackAction {
+        let ch = char::from(self.data[self.pos]);
+        self.pos += 1;
+        self.parser
+            .print_char(&mut self.buffer, &mut self.caret, ch)
+            .unwrap_or(CallbackAction::None)
+    }
+
+    /// Plays the whole stream, identical consecutive frames are merged.
+    /// The final screen is captured for at least one full blink cycle, the animation
+    /// ends on a blink cycle boundary so it loops seamlessly.
+    ///
+    /// All frames are kept in memory, use [`AnsiAnimation::write_gif`] for long animations.
+    pub fn render_frames(&mut self) -> Vec<AnimationFrame> {
+        let mut frames = Vec::new();
+        self.play(|frame| {
+            frames.push(frame);
+            Ok::<(), Infallible>(())
+        })
+        .unwrap_or_else(|never| match never {});
+        frames
+    }
+
+    /// Plays the whole stream like [`AnsiAnimation::render_frames`] and encodes the frames as animated gif
+    /// while they're captured, only the previous frame is kept in memory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the frames are too large or the encoder fails.
+    pub fn write_gif<W: Write>(&mut self, writer: W, repeat: bool) -> EngineResult<()> {
+        let mut gif_writer = GifWriter::new(writer, repeat);
+        self.play(|frame| gif_writer.write_frame(&frame))?;
+        gif_writer.finish()
+    }
+
+    /// Plays the whole stream and hands out the merged frames, a frame is handed out
+    /// once the next different frame is captured.
+    fn play<E>(&mut self, mut emit: impl FnMut(AnimationFrame) -> Result<(), E>) -> Result<(), E> {
+        let mut pending: Option<AnimationFrame> = None;
+        let mut end_ms = None;
+        while end_ms.is_none_or(|end_ms| self.time_ms < end_ms) {
+            let start_ms = self.time_ms;
+            let frame = self.next_frame();
+            match &mut pending {
+                Some(last) if last.image == frame.image => last.delay_ms += frame.delay_ms,
+                _ => {
+                    if let Some(last) = pending.replace(frame) {
+                        emit(last)?;
+                    }
+                }
+            }
+            if end_ms.is_none() && self.is_finished() {
+                let cycle_ms = 2 * self.options.blink_interval_ms.max(1) as u64;
+                end_ms = Some((start_ms + cycle_ms).div_ceil(cycle_ms) * cycle_ms);
+            }
+        }
+        match pending {
+            Some(last) => emit(last),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Encodes frames as animated gif.
+///
+/// # Errors
+///
+/// This function will return an error if there are no frames, they are too large or the encoder fails.
+pub fn convert_to_gif(frames: &[AnimationFrame], repeat: bool) -> EngineResult<Vec<u8>> {
+    if frames.is_empty() {
+        return Err(Box::new(ParserError::Error(
+            "animation has no frames".to_string(),
+        )));
+    }
+    let mut result = Vec::new();
+    let mut gif_writer = GifWriter::new(&mut result, repeat);
+    for frame in frames {
+        gif_writer.write_frame(frame)?;
+    }
+    gif_writer.finish()?;
+    Ok(result)
+}
+
+/// Gif encoder that is created with the size of the first frame.
+struct GifWriter<W: Write> {
+    writer: Option<W>,
+    encoder: Option<gif::Encoder<W>>,
+    repeat: bool,
+}
+
+impl<W: Write> GifWriter<W> {
+    fn new(writer: W, repeat: bool) -> Self {
+        Self {
+            writer: Some(writer),
+            encoder: None,
+            repeat,
+        }
+    }
+
+    fn write_frame(&mut self, frame: &AnimationFrame) -> EngineResult<()> {
+        let (Ok(width), Ok(height)) = (
+            u16::try_from(frame.image.width),
+            u16::try_from(frame.image.height),
+        ) else {
+            return Err(Box::new(ParserError::Error(
+                "image is too large for gif".to_string(),
+            )));
+        };
+        let encoder = match (&mut self.encoder, self.writer.take()) {
+            (Some(encoder), _) => encoder,
+            (None, Some(writer)) => {
+                let mut encoder = gif::Encoder::new(writer, width, height, &[])?;
+                encoder.set_repeat(if self.repeat {
+                    gif::Repeat::Infinite
+                } else {
+                    gif::Repeat::Finite(0)
+                })?;
+                self.encoder.insert(encoder)
+            }
+            (None, None) => return Ok(()),
+        };
+        let mut pixels = frame.image.pixels.clone();
+        let mut gif_frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, 10);
+        gif_frame.delay = (frame.delay_ms / 10).min(u16::MAX as u32) as u16;
+        encoder.write_frame(&gif_frame)?;
+        Ok(())
+    }
+
+    /// Writes the gif trailer.
+    fn finish(self) -> EngineResult<()> {
+        if let Some(encoder) = self.encoder {
+            encoder.into_inner()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{convert_to_gif, AnimationOptions, AnsiAnimation, Position};
+
+    fn options(baud_rate: u32) -> AnimationOptions {
+        AnimationOptions {
+            baud_rate,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_baud_rate() {
+        // 9600 baud = 96 bytes per 100ms frame
+        let data = vec![b'#'; 200];
+        let mut animation = AnsiAnimation::new(&data, options(9600)).unwrap();
+        animation.next_frame();
+        assert_eq!(96, animation.pos);
+        animation.next_frame();
+        assert_eq!(192, animation.pos);
+        animation.next_frame();
+        assert!(animation.is_finished());
+    }
+
+    #[test]
+    fn test_unlimited_baud_rate() {
+        let data = vec![b'#'; 2000];
+        let mut animation = AnsiAnimation::new(&data, options(0)).unwrap();
+        animation.next_frame();
+        assert!(animation.is_finished());
+    }
+
+    #[test]
+    fn test_change_baud_rate() {
+        // switches to 300 baud (3 bytes per frame) after the sequence
+        let mut data = b"\x1B[0;1*r".to_vec();
+        data.extend(vec![b'#'; 10]);
+        let mut animation = AnsiAnimation::new(&data, options(0)).unwrap();
+        animation.next_frame();
+        assert_eq!(7, animation.pos);
+        animation.next_frame();
+        assert_eq!(10, animation.pos);
+        assert_eq!(300, animation.get_buffer().terminal_state.get_baud_rate());
+    }
+
+    #[test]
+    fn test_unsupported_sequence() {
+        let mut animation = AnsiAnimation::new(b"A\x1B[1;2;3\x7FB", options(0)).unwrap();
+        animation.next_frame();
+        assert!(animation.is_finished());
+        assert_eq!(
+            'B',
+            animation
+                .get_buffer()
+                .get_char(Position::new(1, 0))
+                .unwrap()
+                .ch
+        );
+    }
+
+    #[test]
+    fn test_render_frames() {
+        let mut animation = AnsiAnimation::new(b"A\x1B[5mB", options(0)).unwrap();
+        let frames = animation.render_frames();
+        // blink on & off
+        assert_eq!(2, frames.len());
+        assert_eq!(500, frames[0].delay_ms);
+        assert_eq!(500, frames[1].delay_ms);
+
+        let gif = convert_to_gif(&frames, true).unwrap();
+        assert_eq!(b"GIF89a", &gif[0..6]);
+
+        let mut animation = AnsiAnimation::new(b"A\x1B[5mB", options(0)).unwrap();
+        let mut streamed = Vec::new();
+        animation.write_gif(&mut streamed, true).unwrap();
+        assert_eq!(gif, streamed);
+    }
+}
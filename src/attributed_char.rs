@@ -5,6 +5,7 @@ pub struct AttributedChar {
     pub ch: char,
     pub attribute: TextAttribute,
     font_page: usize,
+    hyperlink_id: usize,
 }
 
 impl Default for AttributedChar {
@@ -13,6 +14,7 @@ impl Default for AttributedChar {
             ch: ' ',
             attribute: super::TextAttribute::default(),
             font_page: 0,
+            hyperlink_id: 0,
         }
     }
 }
@@ -24,6 +26,7 @@ impl AttributedChar {
             ch,
             attribute,
             font_page: 0,
+            hyperlink_id: 0,
         }
     }
 
@@ -40,6 +43,16 @@ impl AttributedChar {
     pub fn set_font_page(&mut self, page: usize) {
         self.font_page = page;
     }
+
+    /// Gets the id of the hyperlink in the buffer link table, 0 is no link.
+    #[must_use]
+    pub fn get_hyperlink_id(&self) -> usize {
+        self.hyperlink_id
+    }
+
+    pub fn set_hyperlink_id(&mut self, link_id: usize) {
+        self.hyperlink_id = link_id;
+    }
 }
 
 impl PartialEq for AttributedChar {
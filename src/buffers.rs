@@ -1,4 +1,4 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ffi::OsStr;
 use std::{
     cmp::max,
@@ -9,11 +9,15 @@ use std::{
 
 use num::NumCast;
 
-use crate::{parsers, BufferParser, Caret, EngineResult, Glyph, Sixel, TerminalState};
+use crate::{
+    parsers, AtomicUndo, BufferParser, Caret, EngineResult, FileFormat, Glyph, HyperLinkTable,
+    KittyImage, KittyPlacement, Scrollback, Sixel, SixelDecoder, SixelDecoding, SixelJob,
+    TerminalState, UndoError, UndoOperation, DEFAULT_KITTY_IMAGE_QUOTA, DEFAULT_UNDO_HISTORY,
+};
 
 use super::{
-    read_binary, read_xb, AttributedChar, BitFont, Layer, Palette, Position, SauceString,
-    SaveOptions, Size,
+    detect_format, read_binary, read_xb, AttributedChar, BitFont, Layer, Line, LineAttribute,
+    Palette, Position, SauceString, SaveOptions, Size,
 };
 
 #[repr(u8)]
@@ -26,14 +30,6 @@ pub enum BufferType {
     NoLimits = 0b_0111,   // free colors, blink + extended font
 }
 
-enum CharInterpreter {
-    Ascii,
-    Ansi,
-    Avatar,
-    Pcb,
-    Petscii,
-}
-
 impl BufferType {
     #[must_use]
     pub fn use_ice_colors(self) -> bool {
@@ -87,8 +83,30 @@ pub struct Buffer {
 
     pub layers: Vec<Layer>,
 
-    pub sixel_threads: VecDeque<std::thread::JoinHandle<Sixel>>, // pub undo_stack: Vec<Box<dyn UndoOperation>>,
-                                                                 // pub redo_stack: Vec<Box<dyn UndoOperation>>,
+    /// OSC 8 link table, see [`Buffer::add_hyperlink`].
+    pub hyperlinks: HyperLinkTable,
+
+    /// Images transmitted with the kitty graphics protocol, the layers hold their placements.
+    pub kitty_images: Vec<KittyImage>,
+    kitty_image_quota: usize,
+
+    /// Lines scrolled off the top of the screen, only filled in terminal mode.
+    pub scrollback: Scrollback,
+
+    is_alternate_screen: bool,
+    /// Content of the screen that isn't shown, swapped with layer 0 when the screen is switched.
+    inactive_screen: (Vec<Line>, Vec<Sixel>, Vec<KittyPlacement>),
+    saved_primary_caret: Option<Caret>,
+
+    /// `RIPscrip` graphics surface, created on demand by the rip parser.
+    pub rip_surface: Option<crate::rip::Bgi>,
+
+    sixel_decoder: SixelDecoder,
+
+    undo_stack: VecDeque<Box<dyn UndoOperation>>,
+    redo_stack: Vec<Box<dyn UndoOperation>>,
+    atomic_undo_stack: Vec<AtomicUndo>,
+    max_undo_history: usize,
 }
 
 #[allow(clippy::missing_fields_in_debug)]
@@ -142,47 +160,212 @@ impl Buffer {
             is_font_table_dirty: false,
             overlay_layer: None,
             layers: vec![Layer::new()],
-            sixel_threads: VecDeque::new(), // file_name_changed: Box::new(|| {}),
-                                            // undo_stack: Vec::new(),
-                                            // redo_stack: Vec::new()
+            hyperlinks: HyperLinkTable::default(),
+            kitty_images: Vec::new(),
+            kitty_image_quota: DEFAULT_KITTY_IMAGE_QUOTA,
+            scrollback: Scrollback::default(),
+            is_alternate_screen: false,
+            inactive_screen: (Vec::new(), Vec::new(), Vec::new()),
+            saved_primary_caret: None,
+            rip_surface: None,
+            sixel_decoder: SixelDecoder::default(), // file_name_changed: Box::new(|| {}),
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            atomic_undo_stack: Vec::new(),
+            max_undo_history: DEFAULT_UNDO_HISTORY,
         }
     }
 
-    /// Returns the update sixel threads of this [`Buffer`].
+    pub fn get_sixel_decoding(&self) -> SixelDecoding {
+        self.sixel_decoder.get_decoding()
+    }
+
+    pub fn set_sixel_decoding(&mut self, decoding: SixelDecoding) {
+        self.sixel_decoder.set_decoding(decoding);
+    }
+
+    /// Returns `true` if sixels are still decoded in the background, see [`Buffer::poll_sixels`].
+    pub fn has_pending_sixels(&self) -> bool {
+        self.sixel_decoder.pending() > 0
+    }
+
+    /// Number of sixels decoded in the background that weren't added yet.
+    pub fn get_pending_sixel_count(&self) -> usize {
+        self.sixel_decoder.pending()
+    }
+
+    /// Adds the sixels that were decoded in the background, doesn't block.
+    /// Returns `true` if a picture was added.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if .
-    pub fn update_sixel_threads(&mut self) -> bool {
+    /// This function will return an error if a picture failed to decode, the pictures after it are added on the next call.
+    pub fn poll_sixels(&mut self) -> EngineResult<bool> {
         let mut updated_sixel = false;
-        while let Some(handle) = self.sixel_threads.front() {
-            if !handle.is_finished() {
-                return false;
+        while let Some(result) = self.sixel_decoder.try_next() {
+            self.add_sixel(result?);
+            updated_sixel = true;
+        }
+        Ok(updated_sixel)
+    }
+
+    /// Waits until all pending sixels are decoded and adds them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the first error of the pictures that failed to decode.
+    pub fn finish_sixels(&mut self) -> EngineResult<()> {
+        let mut result = Ok(());
+        while let Some(decoded) = self.sixel_decoder.wait_next() {
+            match decoded {
+                Ok(sixel) => self.add_sixel(sixel),
+                Err(err) if result.is_ok() => result = Err(err),
+                Err(_) => {}
+            }
+        }
+        Ok(result?)
+    }
+
+    /// Adds the sixels still decoding in the background before the screen moves or is cleared,
+    /// so they end up where the text they belong to was. Decoding errors are returned by the next [`Buffer::poll_sixels`].
+    pub(crate) fn settle_sixels(&mut self) {
+        if !self.has_pending_sixels() {
+            return;
+        }
+        for sixel in self.sixel_decoder.take_decoded() {
+            self.add_sixel(sixel);
+        }
+    }
+
+    /// Decodes a sixel according to the [`SixelDecoding`], pictures are added in the order they're passed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a synchronously decoded picture is invalid,
+    /// or one of the pictures the parser had to wait for.
+    pub(crate) fn decode_sixel(&mut self, job: SixelJob) -> EngineResult<()> {
+        match self.sixel_decoder.get_decoding() {
+            SixelDecoding::Synchronous => {
+                self.finish_sixels()?;
+                self.add_sixel(job.decode()?);
+                Ok(())
             }
-            let handle = self.sixel_threads.pop_front().unwrap();
-            let sixel = handle.join().unwrap();
-            {
-                updated_sixel = true;
-
-                let screen_rect = sixel.get_screen_rect();
-
-                let vec = &mut self.layers[0].sixels;
-                let mut sixel_count = vec.len();
-                // remove old sixel that are shadowed by the new one
-                let mut i = 0;
-                while i < sixel_count {
-                    let old_rect = vec[i].get_screen_rect();
-                    if screen_rect.contains_rect(&old_rect) {
-                        vec.remove(i);
-                        sixel_count -= 1;
-                    } else {
-                        i += 1;
+            SixelDecoding::WorkerPool { queue_size, .. } => {
+                let mut result = Ok(());
+                while self.sixel_decoder.pending() >= queue_size.max(1) {
+                    match self.sixel_decoder.wait_next() {
+                        Some(Ok(sixel)) => self.add_sixel(sixel),
+                        Some(Err(err)) if result.is_ok() => result = Err(err),
+                        _ => {}
                     }
                 }
-                vec.push(sixel);
+                self.sixel_decoder.submit(job);
+                Ok(result?)
+            }
+        }
+    }
+
+    /// Adds a decoded sixel to the first layer, older sixels it covers completely are removed.
+    pub fn add_sixel(&mut self, sixel: Sixel) {
+        let screen_rect = sixel.get_screen_rect();
+
+        let vec = &mut self.layers[0].sixels;
+        let mut sixel_count = vec.len();
+        // remove old sixel that are shadowed by the new one
+        let mut i = 0;
+        while i < sixel_count {
+            let old_rect = vec[i].get_screen_rect();
+            if screen_rect.contains_rect(&old_rect) {
+                vec.remove(i);
+                sixel_count -= 1;
+            } else {
+                i += 1;
+            }
+        }
+        vec.push(sixel);
+    }
+
+    /// Removes an image if none of the layers or the inactive screen show it.
+    pub fn free_kitty_image(&mut self, id: u32) {
+        if !self.is_kitty_image_shown(id) {
+            self.kitty_images.retain(|image| image.id != id);
+        }
+    }
+
+    /// Removes the links no char on the screen, the inactive screen or in the scrollback refers to.
+    /// `keep` stays, it's the link the parser writes with.
+    pub fn prune_hyperlinks(&mut self, keep: usize) {
+        if self.hyperlinks.is_empty() {
+            return;
+        }
+        let mut used = HashSet::from([keep]);
+        for line in self
+            .layers
+            .iter()
+            .flat_map(|layer| &layer.lines)
+            .chain(&self.inactive_screen.0)
+            .chain(self.scrollback.lines())
+        {
+            used.extend(
+                line.chars
+                    .iter()
+                    .flatten()
+                    .map(AttributedChar::get_hyperlink_id),
+            );
+        }
+        self.hyperlinks.retain(|id| used.contains(&id));
+    }
+
+    fn is_kitty_image_shown(&self, id: u32) -> bool {
+        self.layers
+            .iter()
+            .flat_map(|layer| &layer.kitty_placements)
+            .chain(&self.inactive_screen.2)
+            .any(|placement| placement.image_id == id)
+    }
+
+    pub fn get_kitty_image_quota(&self) -> usize {
+        self.kitty_image_quota
+    }
+
+    /// Sets the number of pixel bytes the kitty images may use, see [`Buffer::evict_kitty_images`].
+    pub fn set_kitty_image_quota(&mut self, kitty_image_quota: usize) {
+        self.kitty_image_quota = kitty_image_quota;
+        self.evict_kitty_images(None);
+    }
+
+    /// Removes the oldest images until they fit into the quota, images that aren't shown go first.
+    /// Removed images take their placements with them, the image `keep` stays.
+    pub fn evict_kitty_images(&mut self, keep: Option<u32>) {
+        let mut size: usize = self
+            .kitty_images
+            .iter()
+            .map(|image| image.pixels.len())
+            .sum();
+        while size > self.kitty_image_quota {
+            let candidates = self
+                .kitty_images
+                .iter()
+                .map(|image| image.id)
+                .filter(|id| Some(*id) != keep);
+            let unplaced = candidates
+                .clone()
+                .find(|id| !self.is_kitty_image_shown(*id));
+            let Some(id) = unplaced.or_else(|| candidates.clone().next()) else {
+                break;
+            };
+            for layer in &mut self.layers {
+                layer
+                    .kitty_placements
+                    .retain(|placement| placement.image_id != id);
+            }
+            self.inactive_screen
+                .2
+                .retain(|placement| placement.image_id != id);
+            if let Some(i) = self.kitty_images.iter().position(|image| image.id == id) {
+                size -= self.kitty_images.remove(i).pixels.len();
             }
         }
-        updated_sixel
     }
 
     pub fn clear_font_table(&mut self) {
@@ -225,6 +408,14 @@ impl Buffer {
         self.is_font_table_dirty = true;
     }
 
+    pub fn remove_font(&mut self, font_number: usize) -> Option<BitFont> {
+        let res = self.font_table.remove(&font_number);
+        if res.is_some() {
+            self.is_font_table_dirty = true;
+        }
+        res
+    }
+
     pub fn font_count(&self) -> usize {
         self.font_table.len()
     }
@@ -300,9 +491,149 @@ impl Buffer {
     ///
     /// Panics if .
     pub fn clear(&mut self) {
+        self.settle_sixels();
         self.layers[0].clear();
         self.layers[0].sixels.clear();
-        self.sixel_threads.clear();
+        self.layers[0].kitty_placements.clear();
+    }
+
+    /// Applies an operation and records it in the undo history.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the operation fails, in that case it's not recorded.
+    pub fn execute(&mut self, mut operation: Box<dyn UndoOperation>) -> EngineResult<()> {
+        operation.redo(self)?;
+        self.push_undo(operation);
+        Ok(())
+    }
+
+    /// Records an already applied operation in the undo history, the redo history is cleared.
+    pub fn push_undo(&mut self, operation: Box<dyn UndoOperation>) {
+        if let Some(atomic_undo) = self.atomic_undo_stack.last_mut() {
+            atomic_undo.push(operation);
+            return;
+        }
+        self.redo_stack.clear();
+        self.undo_stack.push_back(operation);
+        while self.undo_stack.len() > self.max_undo_history {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    /// Starts grouping all following operations into one undo step until [`Buffer::end_atomic_undo`] is called.
+    /// Atomic undos can be nested.
+    pub fn begin_atomic_undo(&mut self, description: impl Into<String>) {
+        self.atomic_undo_stack.push(AtomicUndo::new(description));
+    }
+
+    /// Finishes the current atomic undo, empty groups aren't recorded.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no atomic undo was started.
+    pub fn end_atomic_undo(&mut self) -> EngineResult<()> {
+        let Some(atomic_undo) = self.atomic_undo_stack.pop() else {
+            return Err(Box::new(UndoError::NoAtomicUndo));
+        };
+        if !atomic_undo.is_empty() {
+            self.push_undo(Box::new(atomic_undo));
+        }
+        Ok(())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn get_undo_description(&self) -> Option<String> {
+        self.undo_stack.back().map(|op| op.get_description())
+    }
+
+    pub fn get_redo_description(&self) -> Option<String> {
+        self.redo_stack.last().map(|op| op.get_description())
+    }
+
+    /// Reverts the last operation. Returns false if there was nothing to undo.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the operation fails, it's removed from the history then.
+    pub fn undo(&mut self) -> EngineResult<bool> {
+        let Some(mut operation) = self.undo_stack.pop_back() else {
+            return Ok(false);
+        };
+        operation.undo(self)?;
+        self.redo_stack.push(operation);
+        Ok(true)
+    }
+
+    /// Applies the last undone operation again. Returns false if there was nothing to redo.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the operation fails, it's removed from the history then.
+    pub fn redo(&mut self) -> EngineResult<bool> {
+        let Some(mut operation) = self.redo_stack.pop() else {
+            return Ok(false);
+        };
+        operation.redo(self)?;
+        self.undo_stack.push_back(operation);
+        Ok(true)
+    }
+
+    pub fn get_max_undo_history(&self) -> usize {
+        self.max_undo_history
+    }
+
+    /// Sets the number of undo steps kept, the oldest steps are dropped first.
+    pub fn set_max_undo_history(&mut self, max_undo_history: usize) {
+        self.max_undo_history = max_undo_history;
+        while self.undo_stack.len() > self.max_undo_history {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    pub fn clear_undo_history(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    pub fn is_alternate_screen(&self) -> bool {
+        self.is_alternate_screen
+    }
+
+    /// Shows the alternate screen, the primary screen and the caret are kept until
+    /// [`Buffer::switch_to_primary_screen`] is called. Scrolling on the alternate screen doesn't feed the scrollback.
+    pub fn switch_to_alternate_screen(&mut self, caret: &Caret) {
+        if self.is_alternate_screen {
+            return;
+        }
+        self.saved_primary_caret = Some(caret.clone());
+        self.swap_screens();
+    }
+
+    /// Shows the primary screen again and gives back the caret saved when the alternate screen was entered.
+    pub fn switch_to_primary_screen(&mut self) -> Option<Caret> {
+        if !self.is_alternate_screen {
+            return None;
+        }
+        self.swap_screens();
+        self.saved_primary_caret.take()
+    }
+
+    fn swap_screens(&mut self) {
+        // pictures still decoding belong to the screen that's left
+        self.settle_sixels();
+        let layer = &mut self.layers[0];
+        std::mem::swap(&mut layer.lines, &mut self.inactive_screen.0);
+        std::mem::swap(&mut layer.sixels, &mut self.inactive_screen.1);
+        std::mem::swap(&mut layer.kitty_placements, &mut self.inactive_screen.2);
+        self.is_alternate_screen = !self.is_alternate_screen;
     }
 
     /// terminal buffers have a viewport on the bottom of the buffer
@@ -539,141 +870,143 @@ impl Buffer {
         let mut result = Buffer::new();
         result.is_terminal_buffer = false;
         result.file_name = Some(file_name.to_path_buf());
-        let ext = file_name.extension();
-
-        if let Some(ext) = ext {
-            // mdf doesn't need sauce info.
-            let ext = OsStr::to_str(ext).unwrap().to_lowercase();
-            if ext.as_str() == "mdf" {
-                super::read_mdf(&mut result, bytes)?;
-                return Ok(result);
-            }
+        let ext = file_name
+            .extension()
+            .map(|ext| OsStr::to_str(ext).unwrap().to_lowercase());
+
+        // mdf doesn't need sauce info.
+        if ext.as_deref() == Some("mdf") {
+            result.load_format(FileFormat::Mdf, bytes, bytes.len(), skip_errors)?;
+            return Ok(result);
         }
 
         let (sauce_type, file_size) = result.read_sauce_info(bytes)?;
-        let mut check_extension = false;
-        let mut interpreter = CharInterpreter::Ascii;
-
-        match sauce_type {
-            super::SauceFileType::Ascii => {
-                interpreter = CharInterpreter::Ansi; /* There are files that are marked as Ascii but contain ansi codes. */
-            }
-            super::SauceFileType::Ansi => {
-                interpreter = CharInterpreter::Ansi;
-                check_extension = true;
+        let (mut format, check_extension) = match sauce_type {
+            /* There are files that are marked as Ascii but contain ansi codes.
+             * ANSiMations load the final screen, use AnsiAnimation for playback. */
+            super::SauceFileType::Ascii | super::SauceFileType::ANSiMation => {
+                (FileFormat::Ansi, false)
             }
-            super::SauceFileType::ANSiMation => {
-                interpreter = CharInterpreter::Ansi;
-                eprintln!("WARNING: ANSiMations are not fully supported.");
-            }
-            super::SauceFileType::PCBoard => {
-                interpreter = CharInterpreter::Pcb;
+            super::SauceFileType::Ansi => (FileFormat::Ansi, true),
+            super::SauceFileType::PCBoard => (FileFormat::PCBoard, false),
+            super::SauceFileType::Avatar => (FileFormat::Avatar, false),
+            super::SauceFileType::TundraDraw => (FileFormat::TundraDraw, false),
+            super::SauceFileType::Bin => (FileFormat::Bin, false),
+            super::SauceFileType::XBin => (FileFormat::XBin, false),
+            super::SauceFileType::Undefined => (FileFormat::Ascii, true),
+        };
+
+        if check_extension {
+            if let Some(ext) = &ext {
+                if let Some(ext_format) = FileFormat::from_extension(ext) {
+                    format = ext_format;
+                }
+                if ext == "ice" {
+                    result.buffer_type = BufferType::LegacyIce;
+                }
             }
-            super::SauceFileType::Avatar => {
-                interpreter = CharInterpreter::Avatar;
+        }
+
+        result.load_format(format, bytes, file_size, skip_errors)?;
+        Ok(result)
+    }
+
+    /// Loads a buffer without using the file extension, the format is guessed by [`detect_format`]
+    /// unless the SAUCE record specifies a binary format.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the data is invalid for the detected format.
+    pub fn from_bytes_autodetect(
+        bytes: &[u8],
+        skip_errors: bool,
+    ) -> EngineResult<(Buffer, FileFormat)> {
+        let mut result = Buffer::new();
+        result.is_terminal_buffer = false;
+
+        if detect_format(bytes) == FileFormat::Mdf {
+            result.load_format(FileFormat::Mdf, bytes, bytes.len(), skip_errors)?;
+            return Ok((result, FileFormat::Mdf));
+        }
+
+        let (sauce_type, file_size) = result.read_sauce_info(bytes)?;
+        let format = match sauce_type {
+            super::SauceFileType::Bin => FileFormat::Bin,
+            super::SauceFileType::XBin => FileFormat::XBin,
+            super::SauceFileType::TundraDraw => FileFormat::TundraDraw,
+            _ => detect_format(&bytes[..file_size.min(bytes.len())]),
+        };
+        result.load_format(format, bytes, file_size, skip_errors)?;
+        Ok((result, format))
+    }
+
+    fn load_format(
+        &mut self,
+        format: FileFormat,
+        bytes: &[u8],
+        file_size: usize,
+        skip_errors: bool,
+    ) -> EngineResult<()> {
+        let mut interpreter: Box<dyn BufferParser> = match format {
+            FileFormat::Mdf => {
+                super::read_mdf(self, bytes)?;
+                return Ok(());
             }
-            super::SauceFileType::TundraDraw => {
-                if result.get_buffer_width() == 0 {
-                    result.set_buffer_width(80);
+            FileFormat::Bin => {
+                if self.get_buffer_width() == 0 {
+                    self.set_buffer_width(160);
                 }
-                super::read_tnd(&mut result, bytes, file_size)?;
-                return Ok(result);
+                read_binary(self, bytes, file_size)?;
+                return Ok(());
             }
-            super::SauceFileType::Bin => {
-                if result.get_buffer_width() == 0 {
-                    result.set_buffer_width(160);
-                }
-                read_binary(&mut result, bytes, file_size)?;
-                return Ok(result);
+            FileFormat::XBin => {
+                read_xb(self, bytes, file_size)?;
+                return Ok(());
             }
-            super::SauceFileType::XBin => {
-                read_xb(&mut result, bytes, file_size)?;
-                return Ok(result);
+            FileFormat::Adf => {
+                if self.get_buffer_width() == 0 {
+                    self.set_buffer_width(80);
+                }
+                super::read_adf(self, bytes, file_size)?;
+                return Ok(());
             }
-            super::SauceFileType::Undefined => {
-                check_extension = true;
+            FileFormat::Idf => {
+                super::read_idf(self, bytes, file_size)?;
+                return Ok(());
             }
-        }
-
-        if check_extension {
-            if let Some(ext) = ext {
-                let ext = OsStr::to_str(ext).unwrap().to_lowercase();
-                match ext.as_str() {
-                    "bin" => {
-                        if result.get_buffer_width() == 0 {
-                            result.set_buffer_width(160);
-                        }
-                        read_binary(&mut result, bytes, file_size)?;
-                        return Ok(result);
-                    }
-                    "xb" => {
-                        read_xb(&mut result, bytes, file_size)?;
-                        return Ok(result);
-                    }
-                    "adf" => {
-                        if result.get_buffer_width() == 0 {
-                            result.set_buffer_width(80);
-                        }
-                        super::read_adf(&mut result, bytes, file_size)?;
-                        return Ok(result);
-                    }
-                    "idf" => {
-                        super::read_idf(&mut result, bytes, file_size)?;
-                        return Ok(result);
-                    }
-                    "tnd" => {
-                        if result.get_buffer_width() == 0 {
-                            result.set_buffer_width(80);
-                        }
-                        super::read_tnd(&mut result, bytes, file_size)?;
-                        return Ok(result);
-                    }
-                    "ans" => {
-                        interpreter = CharInterpreter::Ansi;
-                    }
-                    "ice" => {
-                        interpreter = CharInterpreter::Ansi;
-                        result.buffer_type = BufferType::LegacyIce;
-                    }
-                    "avt" => {
-                        interpreter = CharInterpreter::Avatar;
-                    }
-                    "pcb" => {
-                        interpreter = CharInterpreter::Pcb;
-                    }
-                    "seq" => {
-                        interpreter = CharInterpreter::Petscii;
-                    }
-                    _ => {}
+            FileFormat::TundraDraw => {
+                if self.get_buffer_width() == 0 {
+                    self.set_buffer_width(80);
                 }
+                super::read_tnd(self, bytes, file_size)?;
+                return Ok(());
             }
-        }
+            FileFormat::Ascii => Box::<parsers::ascii::Parser>::default(),
+            FileFormat::Ansi => Box::<parsers::ansi::Parser>::default(),
+            FileFormat::Avatar => Box::<parsers::avatar::Parser>::default(),
+            FileFormat::PCBoard => Box::<parsers::pcboard::Parser>::default(),
+            FileFormat::Petscii => Box::<parsers::petscii::Parser>::default(),
+            FileFormat::Atascii => Box::<parsers::atascii::Parser>::default(),
+            FileFormat::Rip => Box::<parsers::rip::Parser>::default(),
+        };
 
-        if result.get_buffer_width() == 0 {
-            result.set_buffer_width(80);
+        if self.get_buffer_width() == 0 {
+            self.set_buffer_width(80);
         }
-        result.set_buffer_height(25);
-
-        let mut interpreter: Box<dyn BufferParser> = match interpreter {
-            CharInterpreter::Ascii => Box::<parsers::ascii::Parser>::default(),
-            CharInterpreter::Ansi => Box::<parsers::ansi::Parser>::default(),
-            CharInterpreter::Avatar => Box::<parsers::avatar::Parser>::default(),
-            CharInterpreter::Pcb => Box::<parsers::pcboard::Parser>::default(),
-            CharInterpreter::Petscii => Box::<parsers::petscii::Parser>::default(),
-        };
+        self.set_buffer_height(25);
 
         let mut caret = Caret::default();
-        for b in bytes.iter().take(file_size) {
-            let res = interpreter.as_mut().print_char(
-                &mut result,
-                &mut caret,
-                char::from_u32(*b as u32).unwrap(),
-            );
-            if !skip_errors && res.is_err() {
-                res?;
+        let bytes = &bytes[..file_size.min(bytes.len())];
+        if skip_errors {
+            for b in bytes {
+                let _ = interpreter
+                    .as_mut()
+                    .print_char(self, &mut caret, char::from(*b));
             }
+        } else {
+            interpreter.as_mut().print_bytes(self, &mut caret, bytes)?;
         }
-        Ok(result)
+        Ok(())
     }
 
     pub fn to_screenx(&self, x: i32) -> f64 {
@@ -686,6 +1019,43 @@ impl Buffer {
         y as f64 * font_dimensions.height as f64
     }
 
+    pub fn get_line_attribute(&self, line: i32) -> LineAttribute {
+        if line < 0 {
+            return LineAttribute::Single;
+        }
+        self.layers[0]
+            .lines
+            .get(line as usize)
+            .map_or(LineAttribute::Single, |l| l.line_attribute)
+    }
+
+    /// Sets the attribute of a line of the first layer. The chars of the right half are
+    /// removed when the line gets double width.
+    pub fn set_line_attribute(&mut self, line: i32, line_attribute: LineAttribute) {
+        if line < 0 {
+            return;
+        }
+        let width = self.get_buffer_width();
+        let lines = &mut self.layers[0].lines;
+        if lines.len() <= line as usize {
+            lines.resize(line as usize + 1, Line::new());
+        }
+        let line = &mut lines[line as usize];
+        if line_attribute.is_double_width() {
+            line.chars.truncate(((width + 1) / 2) as usize);
+        }
+        line.line_attribute = line_attribute;
+    }
+
+    /// Gets the number of columns of a line, double width lines have half the columns.
+    pub fn get_line_width(&self, line: i32) -> i32 {
+        if self.get_line_attribute(line).is_double_width() {
+            (self.get_buffer_width() + 1) / 2
+        } else {
+            self.get_buffer_width()
+        }
+    }
+
     pub fn get_line_length(&self, line: i32) -> i32 {
         let mut length = 0;
         let mut pos = Position::new(0, line);
@@ -0,0 +1,477 @@
+use std::io;
+
+use crate::{
+    ascii::CP437_TO_UNICODE, convert_to_ans, AttributedChar, BitFont, Buffer, BufferType,
+    EngineResult, HyperLink, Palette, Position, SaveOptions, Selection, Size, UndoError,
+    UndoSetChar, UndoSetFont, UndoSetPalette,
+};
+
+/// Content of a [`Selection`] copied from a buffer.
+///
+/// Block selections are stored as rectangle. Stream selections are stored line by line,
+/// the first line starts at the selection start column and the following lines at column 0.
+/// `None` cells are transparent, they don't change the target on paste.
+#[derive(Debug, Clone)]
+pub struct ClipboardData {
+    pub width: i32,
+    pub height: i32,
+    pub block_selection: bool,
+    pub chars: Vec<Option<AttributedChar>>,
+    /// Fonts used by the copied chars with their font page.
+    pub fonts: Vec<(usize, BitFont)>,
+    /// Links used by the copied chars with their link id.
+    pub hyperlinks: Vec<(usize, HyperLink)>,
+    pub buffer_type: BufferType,
+    pub palette: Palette,
+}
+
+impl ClipboardData {
+    /// Copies the visible content of the selection.
+    pub fn copy(buf: &Buffer, selection: &Selection) -> Self {
+        let rows: Vec<Vec<Option<AttributedChar>>> = if selection.block_selection {
+            let rect = selection.as_rectangle();
+            (0..rect.size.height)
+                .map(|y| {
+                    (0..rect.size.width)
+                        .map(|x| buf.get_char(rect.start + Position::new(x, y)))
+                        .collect()
+                })
+                .collect()
+        } else {
+            let (start, end) = selection.get_stream_range();
+            (start.y..=end.y)
+                .map(|y| {
+                    let from = if y == start.y { start.x } else { 0 };
+                    let to = if y == end.y {
+                        end.x
+                    } else {
+                        buf.get_buffer_width() - 1
+                    };
+                    (from..=to)
+                        .map(|x| buf.get_char(Position::new(x, y)))
+                        .collect()
+                })
+                .collect()
+        };
+
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut chars = Vec::with_capacity(width * rows.len());
+        let mut fonts: Vec<(usize, BitFont)> = Vec::new();
+        let mut hyperlinks: Vec<(usize, HyperLink)> = Vec::new();
+        for row in &rows {
+            for ch in row.iter().flatten() {
+                let page = ch.get_font_page();
+                if !fonts.iter().any(|(p, _)| *p == page) {
+                    if let Some(font) = buf.get_font(page) {
+                        fonts.push((page, font.clone()));
+                    }
+                }
+                let link_id = ch.get_hyperlink_id();
+                if !hyperlinks.iter().any(|(id, _)| *id == link_id) {
+                    if let Some(link) = buf.get_hyperlink(link_id) {
+                        hyperlinks.push((link_id, link.clone()));
+                    }
+                }
+            }
+            chars.extend(row.iter().copied());
+            chars.resize(chars.len() + width - row.len(), None);
+        }
+        fonts.sort_by_key(|(page, _)| *page);
+
+        Self {
+            width: width as i32,
+            height: rows.len() as i32,
+            block_selection: selection.block_selection,
+            chars,
+            fonts,
+            hyperlinks,
+            buffer_type: buf.buffer_type,
+            palette: buf.palette.clone(),
+        }
+    }
+
+    pub fn get_char(&self, pos: Position) -> Option<AttributedChar> {
+        if pos.x < 0 || pos.y < 0 || pos.x >= self.width || pos.y >= self.height {
+            return None;
+        }
+        self.chars[(pos.y * self.width + pos.x) as usize]
+    }
+
+    /// Converts the content to unicode text, transparent cells are exported as space.
+    /// Trailing spaces are removed, lines are separated by `\n`.
+    pub fn to_unicode(&self) -> String {
+        let mut lines = Vec::new();
+        for y in 0..self.height {
+            let mut line = String::new();
+            for x in 0..self.width {
+                let ch = self.get_char(Position::new(x, y)).map_or(' ', |ch| ch.ch);
+                line.push(match ch {
+                    '\0' => ' ',
+                    ch if (ch as u32) < 256 => CP437_TO_UNICODE[ch as usize],
+                    ch => ch,
+                });
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+
+    /// Converts the content to ANSI using [`convert_to_ans`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the conversion fails.
+    pub fn to_ansi(&self, options: &SaveOptions) -> io::Result<Vec<u8>> {
+        let mut buf = Buffer::new();
+        buf.buffer_type = self.buffer_type;
+        buf.palette = self.palette.clone();
+        buf.set_buffer_size(Size::new(self.width, self.height));
+        let link_map = self.add_hyperlinks(&mut buf);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pos = Position::new(x, y);
+                if let Some(ch) = self.get_char(pos) {
+                    buf.set_char(0, pos, Some(map_hyperlink(ch, &link_map)));
+                }
+            }
+        }
+        convert_to_ans(&buf, options)
+    }
+
+    /// Pastes the content into `layer` at `pos` as one undo step.
+    ///
+    /// Transparent cells are skipped, with `skip_blanks` blank cells (space on black) are treated
+    /// as transparent as well. Colors are mapped to the target palette, missing colors are added to it.
+    /// Fonts the target buffer doesn't have are added, on a free font page if theirs is taken by another font.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the layer doesn't exist.
+    pub fn paste(
+        &self,
+        buf: &mut Buffer,
+        layer: usize,
+        pos: Position,
+        skip_blanks: bool,
+    ) -> EngineResult<()> {
+        if layer >= buf.layers.len() {
+            return Err(Box::new(UndoError::LayerOutOfRange(layer)));
+        }
+        buf.begin_atomic_undo("Paste");
+        let res = self.paste_chars(buf, layer, pos, skip_blanks);
+        buf.end_atomic_undo()?;
+        res
+    }
+
+    fn paste_chars(
+        &self,
+        buf: &mut Buffer,
+        layer: usize,
+        pos: Position,
+        skip_blanks: bool,
+    ) -> EngineResult<()> {
+        let font_map = self.add_fonts(buf)?;
+        let color_map = self.add_colors(buf)?;
+        let link_map = self.add_hyperlinks(buf);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let Some(ch) = self.get_char(Position::new(x, y)) else {
+                    continue;
+                };
+                if skip_blanks && ch.is_transparent() {
+                    continue;
+                }
+                let target = if self.block_selection || y == 0 {
+                    pos + Position::new(x, y)
+                } else {
+                    Position::new(x, pos.y + y)
+                };
+                let mut ch = map_hyperlink(ch, &link_map);
+                if let Some((_, page)) = font_map.iter().find(|(old, _)| *old == ch.get_font_page())
+                {
+                    ch.set_font_page(*page);
+                }
+                if let Some(color_map) = &color_map {
+                    let map = |color: u32| color_map.get(color as usize).copied().unwrap_or(color);
+                    ch.attribute
+                        .set_foreground(map(ch.attribute.get_foreground()));
+                    ch.attribute
+                        .set_background(map(ch.attribute.get_background()));
+                }
+                buf.execute(Box::new(UndoSetChar {
+                    layer,
+                    pos: target,
+                    ch: Some(ch),
+                }))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the fonts to `buf`, returns the mapping of the old to the new font pages.
+    /// A font that `buf` already has on any page is reused.
+    fn add_fonts(&self, buf: &mut Buffer) -> EngineResult<Vec<(usize, usize)>> {
+        let mut font_map = Vec::new();
+        for (page, font) in &self.fonts {
+            if buf
+                .get_font(*page)
+                .is_some_and(|old| old.has_same_glyphs(font))
+            {
+                font_map.push((*page, *page));
+                continue;
+            }
+            if let Some((new_page, _)) = buf.font_iter().find(|(_, old)| old.has_same_glyphs(font))
+            {
+                font_map.push((*page, *new_page));
+                continue;
+            }
+            let new_page = if buf.get_font(*page).is_none() {
+                *page
+            } else {
+                (0..=buf.font_count())
+                    .find(|i| buf.get_font(*i).is_none())
+                    .unwrap_or_default()
+            };
+            buf.execute(Box::new(UndoSetFont {
+                font_number: new_page,
+                font: Some(font.clone()),
+            }))?;
+            font_map.push((*page, new_page));
+        }
+        Ok(font_map)
+    }
+
+    /// Adds the colors missing in the palette of `buf`, returns the target index for each color of
+    /// the clipboard palette or `None` if both palettes are the same.
+    fn add_colors(&self, buf: &mut Buffer) -> EngineResult<Option<Vec<u32>>> {
+        if self.palette.colors == buf.palette.colors {
+            return Ok(None);
+        }
+        let mut palette = buf.palette.clone();
+        let color_map = self
+            .palette
+            .colors
+            .iter()
+            .map(|color| palette.insert_color(*color))
+            .collect();
+        if palette.colors.len() != buf.palette.colors.len() {
+            buf.execute(Box::new(UndoSetPalette { palette }))?;
+        }
+        Ok(Some(color_map))
+    }
+
+    /// Adds the links to the link table of `buf`, returns the mapping of the old to the new link ids.
+    fn add_hyperlinks(&self, buf: &mut Buffer) -> Vec<(usize, usize)> {
+        self.hyperlinks
+            .iter()
+            .map(|(link_id, link)| (*link_id, buf.add_hyperlink(link.clone())))
+            .collect()
+    }
+}
+
+fn map_hyperlink(mut ch: AttributedChar, link_map: &[(usize, usize)]) -> AttributedChar {
+    let link_id = link_map
+        .iter()
+        .find(|(old, _)| *old == ch.get_hyperlink_id())
+        .map_or(0, |(_, new)| *new);
+    ch.set_hyperlink_id(link_id);
+    ch
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AttributedChar, BitFont, Buffer, ClipboardData, Color, HyperLink, Palette, Position,
+        SaveOptions, Selection, TextAttribute,
+    };
+
+    fn create_buffer() -> Buffer {
+        let mut buf = Buffer::create(10, 3);
+        for (y, line) in ["abcdefghij", "klmnopqrst", "uvwxyz"].iter().enumerate() {
+            for (x, ch) in line.chars().enumerate() {
+                buf.set_char(
+                    0,
+                    Position::new(x as i32, y as i32),
+                    Some(AttributedChar::new(ch, TextAttribute::default())),
+                );
+            }
+        }
+        buf
+    }
+
+    fn select(anchor: (i32, i32), lead: (i32, i32), block_selection: bool) -> Selection {
+        let mut selection = Selection::new((anchor.0 as f32, anchor.1 as f32));
+        selection.set_lead((lead.0 as f32, lead.1 as f32));
+        selection.block_selection = block_selection;
+        selection
+    }
+
+    #[test]
+    fn test_block_copy() {
+        let buf = create_buffer();
+        let data = ClipboardData::copy(&buf, &select((3, 1), (1, 0), true));
+        assert_eq!(3, data.width);
+        assert_eq!(2, data.height);
+        assert_eq!("bcd\nlmn", data.to_unicode());
+    }
+
+    #[test]
+    fn test_stream_copy() {
+        let buf = create_buffer();
+        let data = ClipboardData::copy(&buf, &select((2, 2), (8, 0), false));
+        assert_eq!("ij\nklmnopqrst\nuvw", data.to_unicode());
+    }
+
+    #[test]
+    fn test_cp437_to_unicode() {
+        let mut buf = create_buffer();
+        buf.set_char(
+            0,
+            Position::new(0, 0),
+            Some(AttributedChar::new('\u{DB}', TextAttribute::default())),
+        );
+        let data = ClipboardData::copy(&buf, &select((0, 0), (1, 0), true));
+        assert_eq!("█b", data.to_unicode());
+    }
+
+    #[test]
+    fn test_to_ansi() {
+        let mut buf = create_buffer();
+        let mut attr = TextAttribute::default();
+        attr.set_foreground(4);
+        buf.set_char(0, Position::new(1, 0), Some(AttributedChar::new('B', attr)));
+        let data = ClipboardData::copy(&buf, &select((0, 0), (2, 0), true));
+        let ansi = data.to_ansi(&SaveOptions::new()).unwrap();
+        assert_eq!(b"\x1B[0ma\x1B[31mB\x1B[37mc".to_vec(), ansi);
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut buf = create_buffer();
+        let data = ClipboardData::copy(&buf, &select((0, 0), (1, 1), true));
+        data.paste(&mut buf, 0, Position::new(5, 1), false).unwrap();
+        assert_eq!('a', buf.get_char_xy(5, 1).unwrap().ch);
+        assert_eq!('l', buf.get_char_xy(6, 2).unwrap().ch);
+
+        // one undo step reverts the whole paste
+        buf.undo().unwrap();
+        assert_eq!('p', buf.get_char_xy(5, 1).unwrap().ch);
+        assert_eq!(' ', buf.get_char_xy(6, 2).unwrap().ch);
+    }
+
+    #[test]
+    fn test_paste_stream() {
+        let mut buf = create_buffer();
+        let data = ClipboardData::copy(&buf, &select((8, 0), (1, 1), false));
+        let mut target = Buffer::create(10, 3);
+        data.paste(&mut target, 0, Position::new(4, 0), false)
+            .unwrap();
+        assert_eq!('i', target.get_char_xy(4, 0).unwrap().ch);
+        assert_eq!('j', target.get_char_xy(5, 0).unwrap().ch);
+        assert_eq!('k', target.get_char_xy(0, 1).unwrap().ch);
+        assert_eq!('l', target.get_char_xy(1, 1).unwrap().ch);
+
+        assert!(data.paste(&mut buf, 5, Position::default(), false).is_err());
+    }
+
+    #[test]
+    fn test_paste_transparent() {
+        let mut buf = create_buffer();
+        let mut source = Buffer::new();
+        source.set_char(
+            0,
+            Position::new(0, 0),
+            Some(AttributedChar::new('X', TextAttribute::default())),
+        );
+        source.set_char(
+            0,
+            Position::new(1, 0),
+            Some(AttributedChar::new(' ', TextAttribute::default())),
+        );
+        let data = ClipboardData::copy(&source, &select((0, 0), (2, 0), true));
+        data.paste(&mut buf, 0, Position::new(0, 0), true).unwrap();
+        assert_eq!('X', buf.get_char_xy(0, 0).unwrap().ch);
+        assert_eq!('b', buf.get_char_xy(1, 0).unwrap().ch);
+        assert_eq!('c', buf.get_char_xy(2, 0).unwrap().ch);
+
+        data.paste(&mut buf, 0, Position::new(0, 0), false).unwrap();
+        assert_eq!(' ', buf.get_char_xy(1, 0).unwrap().ch);
+        assert_eq!('c', buf.get_char_xy(2, 0).unwrap().ch);
+    }
+
+    #[test]
+    fn test_paste_palette() {
+        let mut source = create_buffer();
+        source.palette = Palette {
+            colors: vec![Color::new(0, 0, 0), Color::new(1, 2, 3)],
+        };
+        source.set_char(
+            0,
+            Position::new(0, 0),
+            Some(AttributedChar::new('X', TextAttribute::new(1, 0))),
+        );
+        let data = ClipboardData::copy(&source, &select((0, 0), (0, 0), true));
+        let mut target = Buffer::create(10, 3);
+        let color_count = target.palette.colors.len();
+        data.paste(&mut target, 0, Position::new(0, 0), false)
+            .unwrap();
+        let attr = target.get_char_xy(0, 0).unwrap().attribute;
+        assert_eq!(color_count as u32, attr.get_foreground());
+        assert_eq!(0, attr.get_background());
+        assert_eq!(
+            Color::new(1, 2, 3),
+            target.palette.colors[attr.get_foreground() as usize]
+        );
+
+        target.undo().unwrap();
+        assert_eq!(color_count, target.palette.colors.len());
+    }
+
+    #[test]
+    fn test_paste_font() {
+        let mut source = create_buffer();
+        source.set_font(1, BitFont::from_name("Amiga Topaz 1").unwrap());
+        let mut ch = AttributedChar::new('X', TextAttribute::default());
+        ch.set_font_page(1);
+        source.set_char(0, Position::new(0, 0), Some(ch));
+        let data = ClipboardData::copy(&source, &select((0, 0), (0, 0), true));
+
+        // the target uses page 1 for another font
+        let mut target = Buffer::create(10, 3);
+        target.set_font(1, BitFont::from_name("Amiga P0T-NOoDLE").unwrap());
+        data.paste(&mut target, 0, Position::new(0, 0), false)
+            .unwrap();
+        let page = target.get_char_xy(0, 0).unwrap().get_font_page();
+        assert_eq!(2, page);
+        assert_eq!(
+            "Amiga Topaz 1",
+            target.get_font(page).unwrap().name.to_string()
+        );
+
+        // a font the target already has is reused
+        data.paste(&mut target, 0, Position::new(1, 0), false)
+            .unwrap();
+        assert_eq!(2, target.get_char_xy(1, 0).unwrap().get_font_page());
+        assert_eq!(3, target.font_count());
+    }
+
+    #[test]
+    fn test_paste_hyperlink() {
+        let mut source = create_buffer();
+        source.add_hyperlink(HyperLink::new("http://a"));
+        let link_id = source.add_hyperlink(HyperLink::new("http://b"));
+        let mut ch = AttributedChar::new('L', TextAttribute::default());
+        ch.set_hyperlink_id(link_id);
+        source.set_char(0, Position::new(0, 0), Some(ch));
+
+        let data = ClipboardData::copy(&source, &select((0, 0), (1, 0), true));
+        let mut target = Buffer::create(10, 3);
+        data.paste(&mut target, 0, Position::new(2, 1), false)
+            .unwrap();
+        assert_eq!(
+            "http://b",
+            target.get_hyperlink_at(Position::new(2, 1)).unwrap().uri
+        );
+        assert!(target.get_hyperlink_at(Position::new(3, 1)).is_none());
+    }
+}
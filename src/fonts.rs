@@ -86,6 +86,18 @@ fn load_fonts()
 }*/
 
 impl BitFont {
+    /// Returns `true` if both fonts draw the same glyphs, names & font types aren't compared.
+    pub fn has_same_glyphs(&self, other: &BitFont) -> bool {
+        self.size == other.size
+            && self.glyphs.len() == other.glyphs.len()
+            && self.glyphs.iter().all(|(ch, glyph)| {
+                other
+                    .glyphs
+                    .get(ch)
+                    .is_some_and(|other| other.data == glyph.data)
+            })
+    }
+
     pub fn get_font_list() -> &'static Vec<String> {
         unsafe {
             if ALL_FONTS.is_empty() {
@@ -18,6 +18,7 @@ pub fn convert_to_ans(buf: &Buffer, options: &SaveOptions) -> std::io::Result<Ve
     let mut pos = Position::default();
     let height = buf.get_real_buffer_height();
     let mut first_char = true;
+    let mut last_link = 0;
     match options.screen_preparation {
         super::ScreenPreperation::None => {}
         super::ScreenPreperation::ClearScreen => {
@@ -142,6 +143,11 @@ pub fn convert_to_ans(buf: &Buffer, options: &SaveOptions) -> std::io::Result<Ve
 
             first_char = false;
 
+            if options.modern_terminal_output && ch.get_hyperlink_id() != last_link {
+                last_link = ch.get_hyperlink_id();
+                push_hyperlink(&mut result, buf, last_link);
+            }
+
             if space_count > 0 {
                 if space_count < 5 {
                     result.resize(result.len() + space_count, b' ');
@@ -166,6 +172,10 @@ pub fn convert_to_ans(buf: &Buffer, options: &SaveOptions) -> std::io::Result<Ve
         }
         // do not end with eol except for terminal support.
         if options.modern_terminal_output {
+            if last_link != 0 {
+                push_hyperlink(&mut result, buf, 0);
+                last_link = 0;
+            }
             result.extend_from_slice(b"\x1b[0m");
             result.push(10);
             first_char = true;
@@ -184,6 +194,19 @@ pub fn convert_to_ans(buf: &Buffer, options: &SaveOptions) -> std::io::Result<Ve
     Ok(result)
 }
 
+/// Opens the link with OSC 8, an unknown link or 0 closes the current link.
+fn push_hyperlink(result: &mut Vec<u8>, buf: &Buffer, link_id: usize) {
+    result.extend_from_slice(b"\x1b]8;");
+    if let Some(link) = buf.get_hyperlink(link_id) {
+        result.extend_from_slice(link.get_params().as_bytes());
+        result.push(b';');
+        result.extend_from_slice(link.uri.as_bytes());
+    } else {
+        result.push(b';');
+    }
+    result.extend_from_slice(b"\x1b\\");
+}
+
 fn push_int(result: &mut Vec<u8>, number: usize) {
     result.extend_from_slice(number.to_string().as_bytes());
 }
@@ -0,0 +1,183 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Ascii,
+    Ansi,
+    Avatar,
+    PCBoard,
+    Petscii,
+    Atascii,
+    Rip,
+    Bin,
+    XBin,
+    Mdf,
+    Adf,
+    Idf,
+    TundraDraw,
+}
+
+impl FileFormat {
+    /// Gets the format for a lower case file extension.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "ans" | "ice" => Some(FileFormat::Ansi),
+            "avt" => Some(FileFormat::Avatar),
+            "pcb" => Some(FileFormat::PCBoard),
+            "seq" => Some(FileFormat::Petscii),
+            "rip" => Some(FileFormat::Rip),
+            "bin" => Some(FileFormat::Bin),
+            "xb" => Some(FileFormat::XBin),
+            "mdf" => Some(FileFormat::Mdf),
+            "adf" => Some(FileFormat::Adf),
+            "idf" => Some(FileFormat::Idf),
+            "tnd" => Some(FileFormat::TundraDraw),
+            _ => None,
+        }
+    }
+}
+
+const ADF_MIN_SIZE: usize = 1 + 3 * 64 + 4096;
+
+/// PETSCII color, cursor, reverse & charset codes that are rare in other encodings.
+fn is_petscii_control(b: u8) -> bool {
+    matches!(
+        b,
+        0x05 | 0x0E | 0x11 | 0x12 | 0x81 | 0x8E | 0x90 | 0x91 | 0x92 | 0x93 | 0x95..=0x9A
+    )
+}
+
+/// Guesses the file format from the content. SAUCE records should be stripped beforehand.
+///
+/// Binary formats are detected by their magic, text formats by scoring the control
+/// sequences they use. Raw `.bin` files have no signature and can't be detected.
+pub fn detect_format(bytes: &[u8]) -> FileFormat {
+    if bytes.starts_with(b"XBIN\x1A") {
+        return FileFormat::XBin;
+    }
+    if bytes.starts_with(b"MDf") {
+        return FileFormat::Mdf;
+    }
+    if bytes.starts_with(b"\x041.3") || bytes.starts_with(b"\x041.4") {
+        return FileFormat::Idf;
+    }
+    if bytes.len() > 8 && &bytes[1..9] == b"TUNDRA24" {
+        return FileFormat::TundraDraw;
+    }
+    // version byte followed by a 6 bit EGA palette
+    if bytes.len() >= ADF_MIN_SIZE && bytes[0] == 1 && bytes[1..=3 * 64].iter().all(|b| *b < 64) {
+        return FileFormat::Adf;
+    }
+
+    let mut csi = 0;
+    let mut pcboard = 0;
+    let mut avatar = 0;
+    let mut rip = 0;
+    let mut petscii = 0;
+    let mut atascii_eol = 0;
+    let mut cr = 0;
+    let mut lf = 0;
+
+    for (i, b) in bytes.iter().enumerate() {
+        let next = bytes.get(i + 1).copied().unwrap_or_default();
+        match *b {
+            0x1B if next == b'[' => csi += 1,
+            b'@' if next == b'X'
+                && bytes.len() > i + 3
+                && bytes[i + 2].is_ascii_hexdigit()
+                && bytes[i + 3].is_ascii_hexdigit() =>
+            {
+                pcboard += 1;
+            }
+            0x16 if (0x01..=0x19).contains(&next) => avatar += 1,
+            b'!' if next == b'|' && (i == 0 || bytes[i - 1] == b'\n' || bytes[i - 1] == b'\r') => {
+                rip += 1;
+            }
+            0x0D => cr += 1,
+            0x0A => lf += 1,
+            0x9B => atascii_eol += 1,
+            b if is_petscii_control(b) => petscii += 1,
+            _ => {}
+        }
+    }
+
+    if rip > 0 {
+        return FileFormat::Rip;
+    }
+    if pcboard > 0 {
+        return FileFormat::PCBoard;
+    }
+    if avatar > 0 && avatar >= csi {
+        return FileFormat::Avatar;
+    }
+    if csi > 0 {
+        return FileFormat::Ansi;
+    }
+    if lf == 0 {
+        if atascii_eol > 0 && cr == 0 && atascii_eol >= petscii {
+            return FileFormat::Atascii;
+        }
+        if petscii > 0 {
+            return FileFormat::Petscii;
+        }
+    }
+    FileFormat::Ascii
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_format, FileFormat};
+    use crate::Buffer;
+
+    #[test]
+    fn test_magic() {
+        assert_eq!(
+            FileFormat::XBin,
+            detect_format(b"XBIN\x1A\x50\x00\x19\x00\x10\x00")
+        );
+        assert_eq!(FileFormat::Mdf, detect_format(b"MDf\x00"));
+        assert_eq!(FileFormat::Idf, detect_format(b"\x041.4\x00\x00"));
+        assert_eq!(FileFormat::TundraDraw, detect_format(b"\x18TUNDRA24"));
+
+        let mut adf = vec![1];
+        adf.extend(vec![63; 3 * 64]);
+        adf.extend(vec![0xFF; 4096]);
+        assert_eq!(FileFormat::Adf, detect_format(&adf));
+    }
+
+    #[test]
+    fn test_text_formats() {
+        assert_eq!(FileFormat::Ascii, detect_format(b"Hello World\r\n"));
+        assert_eq!(
+            FileFormat::Ansi,
+            detect_format(b"\x1B[0;1;33mHello\x1B[0m\r\n")
+        );
+        assert_eq!(FileFormat::PCBoard, detect_format(b"@X1FHello @X07World"));
+        assert_eq!(
+            FileFormat::Avatar,
+            detect_format(b"\x16\x01\x1FHello\x19 \x05")
+        );
+        assert_eq!(
+            FileFormat::Rip,
+            detect_format(b"\x1B[2J!|1K|*\r\n!|c0F|L00002020\r\n")
+        );
+        assert_eq!(
+            FileFormat::Petscii,
+            detect_format(b"\x93\x05HELLO\x0D\x1CWORLD\x0D")
+        );
+        assert_eq!(
+            FileFormat::Atascii,
+            detect_format(b"\x7DHELLO\x9BWORLD\x9B")
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_autodetect() {
+        let (buf, format) = Buffer::from_bytes_autodetect(b"@X1FA\x1B[1mB", false).unwrap();
+        assert_eq!(FileFormat::PCBoard, format);
+        let ch = buf.get_char_xy(0, 0).unwrap();
+        assert_eq!('A', ch.ch);
+        assert_eq!(7, ch.attribute.get_foreground());
+        assert!(ch.attribute.is_bold());
+        assert_eq!(1, ch.attribute.get_background());
+        assert!(buf.get_char_xy(1, 0).unwrap().attribute.is_bold());
+    }
+}
@@ -29,6 +29,9 @@ pub use tundra::*;
 mod mystic_draw;
 pub use mystic_draw::*;
 
+mod detect;
+pub use detect::*;
+
 use super::{Position, TextAttribute};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
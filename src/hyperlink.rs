@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use crate::{Buffer, Position};
+
+/// The link table isn't pruned automatically before it has this many links.
+const MIN_PRUNE_LEN: usize = 64;
+
+/// A hyperlink set with OSC 8, chars refer to it with their hyperlink id.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HyperLink {
+    /// The optional `id` parameter, it groups cells of a link that is split up on screen.
+    pub id: Option<String>,
+    pub uri: String,
+}
+
+impl HyperLink {
+    pub fn new(uri: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            uri: uri.into(),
+        }
+    }
+
+    /// Gets the parameter part of the OSC 8 sequence.
+    pub fn get_params(&self) -> String {
+        match &self.id {
+            Some(id) => format!("id={id}"),
+            None => String::new(),
+        }
+    }
+}
+
+/// Links of a buffer, the ids are 1 based so 0 is no link. Ids of removed links are reused.
+#[derive(Clone, Debug, Default)]
+pub struct HyperLinkTable {
+    links: Vec<Option<HyperLink>>,
+    ids: HashMap<HyperLink, usize>,
+    free_ids: Vec<usize>,
+    /// Number of links after the last prune.
+    pruned_len: usize,
+}
+
+impl HyperLinkTable {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn get(&self, link_id: usize) -> Option<&HyperLink> {
+        if link_id == 0 {
+            return None;
+        }
+        self.links.get(link_id - 1)?.as_ref()
+    }
+
+    /// Adds a link and returns its id, equal links share one id.
+    pub fn add(&mut self, link: HyperLink) -> usize {
+        if let Some(id) = self.ids.get(&link) {
+            return *id;
+        }
+        let id = if let Some(id) = self.free_ids.pop() {
+            self.links[id - 1] = Some(link.clone());
+            id
+        } else {
+            self.links.push(Some(link.clone()));
+            self.links.len()
+        };
+        self.ids.insert(link, id);
+        id
+    }
+
+    pub fn clear(&mut self) {
+        self.links.clear();
+        self.ids.clear();
+        self.free_ids.clear();
+        self.pruned_len = 0;
+    }
+
+    /// Returns `true` if the table doubled in size since it was pruned the last time.
+    pub fn needs_prune(&self) -> bool {
+        self.len() >= (2 * self.pruned_len).max(MIN_PRUNE_LEN)
+    }
+
+    /// Removes the links `is_used` returns `false` for.
+    pub fn retain(&mut self, is_used: impl Fn(usize) -> bool) {
+        for (i, slot) in self.links.iter_mut().enumerate() {
+            let id = i + 1;
+            if slot.is_some() && !is_used(id) {
+                if let Some(link) = slot.take() {
+                    self.ids.remove(&link);
+                }
+                self.free_ids.push(id);
+            }
+        }
+        while let Some(None) = self.links.last() {
+            self.links.pop();
+        }
+        let len = self.links.len();
+        self.free_ids.retain(|id| *id <= len);
+        self.pruned_len = self.len();
+    }
+}
+
+impl Buffer {
+    /// Adds a link to the link table and returns its id, equal links share one id.
+    pub fn add_hyperlink(&mut self, link: HyperLink) -> usize {
+        self.hyperlinks.add(link)
+    }
+
+    pub fn get_hyperlink(&self, link_id: usize) -> Option<&HyperLink> {
+        self.hyperlinks.get(link_id)
+    }
+
+    /// Gets the link of the visible char at `pos`.
+    pub fn get_hyperlink_at(&self, pos: Position) -> Option<&HyperLink> {
+        self.get_char(pos)
+            .and_then(|ch| self.get_hyperlink(ch.get_hyperlink_id()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AttributedChar, Buffer, HyperLink, Position, TextAttribute};
+
+    #[test]
+    fn test_link_table() {
+        let mut buf = Buffer::new();
+        let a = buf.add_hyperlink(HyperLink::new("https://a.example"));
+        let b = buf.add_hyperlink(HyperLink::new("https://b.example"));
+        assert_eq!(1, a);
+        assert_eq!(2, b);
+        assert_eq!(a, buf.add_hyperlink(HyperLink::new("https://a.example")));
+        assert_eq!(
+            "https://b.example",
+            buf.get_hyperlink(b).unwrap().uri.as_str()
+        );
+        assert!(buf.get_hyperlink(0).is_none());
+        assert!(buf.get_hyperlink(3).is_none());
+    }
+
+    #[test]
+    fn test_prune_link_table() {
+        let mut buf = Buffer::new();
+        let a = buf.add_hyperlink(HyperLink::new("https://a.example"));
+        let b = buf.add_hyperlink(HyperLink::new("https://b.example"));
+        let c = buf.add_hyperlink(HyperLink::new("https://c.example"));
+        let mut ch = AttributedChar::new('A', TextAttribute::default());
+        ch.set_hyperlink_id(c);
+        buf.set_char(0, Position::new(0, 0), Some(ch));
+
+        buf.prune_hyperlinks(b);
+        assert_eq!(2, buf.hyperlinks.len());
+        assert!(buf.get_hyperlink(a).is_none());
+        // the id of a removed link is reused
+        assert_eq!(a, buf.add_hyperlink(HyperLink::new("https://d.example")));
+        assert_eq!(
+            "https://c.example",
+            buf.get_hyperlink_at(Position::new(0, 0)).unwrap().uri
+        );
+
+        buf.clear();
+        buf.prune_hyperlinks(0);
+        assert!(buf.hyperlinks.is_empty());
+    }
+
+    #[test]
+    fn test_get_hyperlink_at() {
+        let mut buf = Buffer::new();
+        let link_id = buf.add_hyperlink(HyperLink {
+            id: Some("1".to_string()),
+            uri: "https://a.example".to_string(),
+        });
+        let mut ch = AttributedChar::new('A', TextAttribute::default());
+        ch.set_hyperlink_id(link_id);
+        buf.set_char(0, Position::new(1, 0), Some(ch));
+
+        assert!(buf.get_hyperlink_at(Position::new(0, 0)).is_none());
+        let link = buf.get_hyperlink_at(Position::new(1, 0)).unwrap();
+        assert_eq!("id=1", link.get_params());
+    }
+}
@@ -0,0 +1,450 @@
+use crate::{
+    parsers::{ascii, atascii, petscii, viewdata},
+    Buffer, BufferParser,
+};
+
+/// Keyboard conventions of the supported terminal emulations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardEmulation {
+    /// ANSI / VT100 with xterm style function & modifier keys
+    Ansi,
+    Vt52,
+    Petscii,
+    Atascii,
+    Viewdata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A printable char, it's converted to the code page of the emulation.
+    Char(char),
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    /// F1 - F12
+    Function(u8),
+    /// Numeric keypad key: `0`-`9`, `.`, `,`, `+`, `-`, `*` or `/`
+    Keypad(char),
+    KeypadEnter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyModifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub control: bool,
+}
+
+impl KeyModifiers {
+    fn is_empty(self) -> bool {
+        !self.shift && !self.alt && !self.control
+    }
+
+    /// xterm modifier parameter, 1 + shift (1) + alt (2) + control (4)
+    fn xterm_param(self) -> u8 {
+        1 + u8::from(self.shift) + 2 * u8::from(self.alt) + 4 * u8::from(self.control)
+    }
+}
+
+/// Encodes a key press for the given emulation. The cursor key & keypad modes
+/// set by the host (DECCKM & DECKPAM) are taken from the terminal state.
+/// Returns `None` for keys the emulation can't send.
+pub fn encode_key(
+    buf: &Buffer,
+    emulation: KeyboardEmulation,
+    key: Key,
+    modifiers: KeyModifiers,
+) -> Option<Vec<u8>> {
+    if let Key::Char(ch) = key {
+        return encode_char(emulation, ch, modifiers);
+    }
+    match emulation {
+        KeyboardEmulation::Ansi => encode_ansi_key(buf, key, modifiers),
+        KeyboardEmulation::Vt52 => encode_vt52_key(buf, key, modifiers),
+        KeyboardEmulation::Petscii => encode_petscii_key(key, modifiers),
+        KeyboardEmulation::Atascii => encode_atascii_key(key),
+        KeyboardEmulation::Viewdata => encode_viewdata_key(key),
+    }
+}
+
+fn encode_char(emulation: KeyboardEmulation, ch: char, modifiers: KeyModifiers) -> Option<Vec<u8>> {
+    let byte = if modifiers.control {
+        control_code(ch)?
+    } else {
+        let converted = match emulation {
+            KeyboardEmulation::Ansi | KeyboardEmulation::Vt52 => {
+                ascii::Parser::default().convert_from_unicode(ch)
+            }
+            KeyboardEmulation::Petscii => petscii::Parser::default().convert_from_unicode(ch),
+            KeyboardEmulation::Atascii => atascii::Parser::default().convert_from_unicode(ch),
+            KeyboardEmulation::Viewdata => viewdata::Parser::default().convert_from_unicode(ch),
+        };
+        u8::try_from(converted).ok()?
+    };
+    let meta =
+        modifiers.alt && matches!(emulation, KeyboardEmulation::Ansi | KeyboardEmulation::Vt52);
+    Some(if meta { vec![0x1B, byte] } else { vec![byte] })
+}
+
+/// Control + key, `@` - `_` and letters map to `0x00` - `0x1F`.
+fn control_code(ch: char) -> Option<u8> {
+    match ch {
+        ' ' => Some(0x00),
+        '?' => Some(0x7F),
+        '@'..='_' | 'a'..='z' => Some(ch.to_ascii_uppercase() as u8 & 0x1F),
+        _ => None,
+    }
+}
+
+fn encode_ansi_key(buf: &Buffer, key: Key, modifiers: KeyModifiers) -> Option<Vec<u8>> {
+    let state = &buf.terminal_state;
+    let result = match key {
+        Key::KeypadEnter if state.application_keypad => "\x1BOM".to_string(),
+        Key::Enter | Key::KeypadEnter => "\r".to_string(),
+        Key::Backspace => "\x08".to_string(),
+        Key::Tab if modifiers.shift => "\x1B[Z".to_string(),
+        Key::Tab => "\t".to_string(),
+        Key::Escape => "\x1B".to_string(),
+        Key::Up => cursor_key(state.application_cursor_keys, 'A', modifiers),
+        Key::Down => cursor_key(state.application_cursor_keys, 'B', modifiers),
+        Key::Right => cursor_key(state.application_cursor_keys, 'C', modifiers),
+        Key::Left => cursor_key(state.application_cursor_keys, 'D', modifiers),
+        Key::Home => cursor_key(state.application_cursor_keys, 'H', modifiers),
+        Key::End => cursor_key(state.application_cursor_keys, 'F', modifiers),
+        Key::Insert => tilde_key(2, modifiers),
+        Key::Delete => tilde_key(3, modifiers),
+        Key::PageUp => tilde_key(5, modifiers),
+        Key::PageDown => tilde_key(6, modifiers),
+        Key::Function(n @ 1..=4) => {
+            let final_char = char::from(b'P' + n - 1);
+            if modifiers.is_empty() {
+                format!("\x1BO{final_char}")
+            } else {
+                format!("\x1B[1;{}{final_char}", modifiers.xterm_param())
+            }
+        }
+        Key::Function(n @ 5..=12) => {
+            const CODES: [u8; 8] = [15, 17, 18, 19, 20, 21, 23, 24];
+            tilde_key(CODES[n as usize - 5], modifiers)
+        }
+        Key::Keypad(ch) if state.application_keypad => format!("\x1BO{}", keypad_final(ch)?),
+        Key::Keypad(ch) => ch.to_string(),
+        Key::Char(_) | Key::Function(_) => return None,
+    };
+    Some(result.into_bytes())
+}
+
+fn cursor_key(application_mode: bool, final_char: char, modifiers: KeyModifiers) -> String {
+    if !modifiers.is_empty() {
+        format!("\x1B[1;{}{final_char}", modifiers.xterm_param())
+    } else if application_mode {
+        format!("\x1BO{final_char}")
+    } else {
+        format!("\x1B[{final_char}")
+    }
+}
+
+fn tilde_key(code: u8, modifiers: KeyModifiers) -> String {
+    if modifiers.is_empty() {
+        format!("\x1B[{code}~")
+    } else {
+        format!("\x1B[{code};{}~", modifiers.xterm_param())
+    }
+}
+
+/// Final char of the application keypad sequences, shared by VT100 & VT52.
+fn keypad_final(ch: char) -> Option<char> {
+    match ch {
+        '0'..='9' => Some(char::from(b'p' + (ch as u8 - b'0'))),
+        '*' => Some('j'),
+        '+' => Some('k'),
+        ',' => Some('l'),
+        '-' => Some('m'),
+        '.' => Some('n'),
+        '/' => Some('o'),
+        _ => None,
+    }
+}
+
+fn encode_vt52_key(buf: &Buffer, key: Key, modifiers: KeyModifiers) -> Option<Vec<u8>> {
+    let application_keypad = buf.terminal_state.application_keypad;
+    let result = match key {
+        Key::KeypadEnter if application_keypad => "\x1B?M".to_string(),
+        Key::Enter | Key::KeypadEnter => "\r".to_string(),
+        Key::Backspace => "\x08".to_string(),
+        Key::Tab => "\t".to_string(),
+        Key::Escape => "\x1B".to_string(),
+        Key::Delete => "\x7F".to_string(),
+        Key::Up => "\x1BA".to_string(),
+        Key::Down => "\x1BB".to_string(),
+        Key::Right => "\x1BC".to_string(),
+        Key::Left => "\x1BD".to_string(),
+        // PF1 - PF4
+        Key::Function(n @ 1..=4) => format!("\x1B{}", char::from(b'P' + n - 1)),
+        Key::Keypad(ch) if application_keypad => format!("\x1B?{}", keypad_final(ch)?),
+        Key::Keypad(ch) => ch.to_string(),
+        _ => return None,
+    };
+    let mut result = result.into_bytes();
+    if modifiers.alt && result.len() == 1 {
+        result.insert(0, 0x1B);
+    }
+    Some(result)
+}
+
+fn encode_petscii_key(key: Key, modifiers: KeyModifiers) -> Option<Vec<u8>> {
+    let byte = match key {
+        Key::Enter | Key::KeypadEnter => 0x0D,
+        Key::Backspace | Key::Delete => 0x14,
+        Key::Insert => 0x94,
+        // RUN/STOP
+        Key::Escape => 0x03,
+        // shift + CLR/HOME clears the screen
+        Key::Home if modifiers.shift => 0x93,
+        Key::Home => 0x13,
+        Key::Up => 0x91,
+        Key::Down => 0x11,
+        Key::Left => 0x9D,
+        Key::Right => 0x1D,
+        // F1 - F8, the even keys are the shifted odd ones on the C64
+        Key::Function(n @ 1..=8) => {
+            [0x85, 0x89, 0x86, 0x8A, 0x87, 0x8B, 0x88, 0x8C][n as usize - 1]
+        }
+        Key::Keypad(ch) => {
+            return encode_char(KeyboardEmulation::Petscii, ch, KeyModifiers::default())
+        }
+        _ => return None,
+    };
+    Some(vec![byte])
+}
+
+fn encode_atascii_key(key: Key) -> Option<Vec<u8>> {
+    let byte = match key {
+        Key::Enter | Key::KeypadEnter => 0x9B,
+        Key::Backspace => 0x7E,
+        Key::Tab => 0x7F,
+        Key::Escape => 0x1B,
+        Key::Delete => 0xFE,
+        Key::Insert => 0xFF,
+        // CLEAR
+        Key::Home => 0x7D,
+        Key::Up => 0x1C,
+        Key::Down => 0x1D,
+        Key::Left => 0x1E,
+        Key::Right => 0x1F,
+        Key::Keypad(ch) => {
+            return encode_char(KeyboardEmulation::Atascii, ch, KeyModifiers::default())
+        }
+        _ => return None,
+    };
+    Some(vec![byte])
+}
+
+fn encode_viewdata_key(key: Key) -> Option<Vec<u8>> {
+    let byte = match key {
+        // the `#` key sends the page request, it's encoded as `_`
+        Key::Enter | Key::KeypadEnter => b'_',
+        Key::Backspace | Key::Left => 0x08,
+        Key::Right => 0x09,
+        Key::Down => 0x0A,
+        Key::Up => 0x0B,
+        Key::Keypad(ch) => {
+            return encode_char(KeyboardEmulation::Viewdata, ch, KeyModifiers::default())
+        }
+        _ => return None,
+    };
+    Some(vec![byte])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_key, Key, KeyModifiers, KeyboardEmulation};
+    use crate::Buffer;
+
+    const NONE: KeyModifiers = KeyModifiers {
+        shift: false,
+        alt: false,
+        control: false,
+    };
+
+    fn encode(buf: &Buffer, emulation: KeyboardEmulation, key: Key) -> Vec<u8> {
+        encode_key(buf, emulation, key, NONE).unwrap()
+    }
+
+    #[test]
+    fn test_ansi_cursor_keys() {
+        let mut buf = Buffer::new();
+        assert_eq!(
+            b"\x1B[A",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Up)[..]
+        );
+        assert_eq!(
+            b"\x1B[H",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Home)[..]
+        );
+
+        buf.terminal_state.application_cursor_keys = true;
+        assert_eq!(
+            b"\x1BOA",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Up)[..]
+        );
+        let ctrl_shift = KeyModifiers {
+            shift: true,
+            control: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(b"\x1B[1;6D".to_vec()),
+            encode_key(&buf, KeyboardEmulation::Ansi, Key::Left, ctrl_shift)
+        );
+    }
+
+    #[test]
+    fn test_ansi_function_keys() {
+        let buf = Buffer::new();
+        assert_eq!(
+            b"\x1BOP",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Function(1))[..]
+        );
+        assert_eq!(
+            b"\x1B[15~",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Function(5))[..]
+        );
+        assert_eq!(
+            b"\x1B[24~",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Function(12))[..]
+        );
+        assert_eq!(
+            b"\x1B[3~",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Delete)[..]
+        );
+        let shift = KeyModifiers {
+            shift: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(b"\x1B[5;2~".to_vec()),
+            encode_key(&buf, KeyboardEmulation::Ansi, Key::PageUp, shift)
+        );
+        assert_eq!(
+            None,
+            encode_key(&buf, KeyboardEmulation::Ansi, Key::Function(13), NONE)
+        );
+    }
+
+    #[test]
+    fn test_keypad_mode() {
+        let mut buf = Buffer::new();
+        buf.terminal_state.application_keypad = true;
+        assert_eq!(
+            b"\x1BOq",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Keypad('1'))[..]
+        );
+        assert_eq!(
+            b"\x1BOM",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::KeypadEnter)[..]
+        );
+        assert_eq!(
+            b"\x1B?q",
+            &encode(&buf, KeyboardEmulation::Vt52, Key::Keypad('1'))[..]
+        );
+
+        buf.terminal_state.application_keypad = false;
+        assert_eq!(
+            b"1",
+            &encode(&buf, KeyboardEmulation::Ansi, Key::Keypad('1'))[..]
+        );
+    }
+
+    #[test]
+    fn test_chars() {
+        let buf = Buffer::new();
+        // CP437
+        assert_eq!(
+            vec![0x84],
+            encode(&buf, KeyboardEmulation::Ansi, Key::Char('ä'))
+        );
+        let control = KeyModifiers {
+            control: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(vec![0x03]),
+            encode_key(&buf, KeyboardEmulation::Ansi, Key::Char('c'), control)
+        );
+        let alt = KeyModifiers {
+            alt: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(b"\x1Bx".to_vec()),
+            encode_key(&buf, KeyboardEmulation::Ansi, Key::Char('x'), alt)
+        );
+        // PETSCII swaps the case in the unshifted charset
+        assert_eq!(
+            vec![0x61],
+            encode(&buf, KeyboardEmulation::Petscii, Key::Char('A'))
+        );
+    }
+
+    #[test]
+    fn test_vt52() {
+        let buf = Buffer::new();
+        assert_eq!(
+            b"\x1BA",
+            &encode(&buf, KeyboardEmulation::Vt52, Key::Up)[..]
+        );
+        assert_eq!(
+            b"\x1BP",
+            &encode(&buf, KeyboardEmulation::Vt52, Key::Function(1))[..]
+        );
+        assert_eq!(
+            None,
+            encode_key(&buf, KeyboardEmulation::Vt52, Key::PageUp, NONE)
+        );
+    }
+
+    #[test]
+    fn test_8bit_emulations() {
+        let buf = Buffer::new();
+        assert_eq!(
+            vec![0x91],
+            encode(&buf, KeyboardEmulation::Petscii, Key::Up)
+        );
+        assert_eq!(
+            vec![0x14],
+            encode(&buf, KeyboardEmulation::Petscii, Key::Backspace)
+        );
+        assert_eq!(
+            vec![0x86],
+            encode(&buf, KeyboardEmulation::Petscii, Key::Function(3))
+        );
+        assert_eq!(
+            vec![0x9B],
+            encode(&buf, KeyboardEmulation::Atascii, Key::Enter)
+        );
+        assert_eq!(
+            vec![0x1C],
+            encode(&buf, KeyboardEmulation::Atascii, Key::Up)
+        );
+        assert_eq!(
+            vec![b'_'],
+            encode(&buf, KeyboardEmulation::Viewdata, Key::Enter)
+        );
+        assert_eq!(
+            vec![0x0B],
+            encode(&buf, KeyboardEmulation::Viewdata, Key::Up)
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{Buffer, Position, Rectangle, Size};
+
+/// Pixel bytes the kitty images of a buffer may use, the same limit kitty has.
+pub const DEFAULT_KITTY_IMAGE_QUOTA: usize = 320 * 1024 * 1024;
+
+/// Largest number of columns & rows an image can be scaled to (`c`, `r`).
+pub const MAX_KITTY_PLACEMENT_CELLS: u32 = 4096;
+
+/// An image transmitted with the kitty graphics protocol, the pixels are stored as RGBA.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KittyImage {
+    /// The image id (`i`), assigned by the terminal if the client used an image number.
+    pub id: u32,
+    /// The image number (`I`) the client transmitted the image with, 0 if it used an id.
+    pub number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl KittyImage {
+    pub fn new(id: u32, width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        Self {
+            id,
+            number: 0,
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// An image shown on the screen, one image can be placed several times.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KittyPlacement {
+    pub image_id: u32,
+    /// Tells placements of the same image apart, 0 if the client didn't give one.
+    pub placement_id: u32,
+    /// Top left cell of the placement.
+    pub position: Position,
+    /// Offset of the image within the top left cell in pixels (`X`, `Y`).
+    pub cell_offset: Position,
+    /// Part of the image that is shown in pixels (`x`, `y`, `w`, `h`),
+    /// a size of 0 extends it to the image border.
+    pub source_x: u32,
+    pub source_y: u32,
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Number of cells the image is scaled to (`c`, `r`), 0 keeps the size of the source rectangle.
+    /// If only one is given the other one keeps the aspect ratio.
+    pub columns: u32,
+    pub rows: u32,
+    /// Placements with a negative z-index are drawn below the text, below -1073741824
+    /// also below cells that don't have the default background color.
+    pub z_index: i32,
+}
+
+/// Placements with a z-index below this are drawn below non default cell backgrounds.
+pub const KITTY_BELOW_BACKGROUND_Z_INDEX: i32 = -1_073_741_824;
+
+impl KittyPlacement {
+    pub fn new(image_id: u32, position: Position) -> Self {
+        Self {
+            image_id,
+            position,
+            ..Default::default()
+        }
+    }
+
+    /// Part of the image that is shown in pixels, clipped at the image border.
+    pub fn get_source_rect(&self, image: &KittyImage) -> Rectangle {
+        let x = self.source_x.min(image.width);
+        let y = self.source_y.min(image.height);
+        let clip = |size: u32, max: u32| if size == 0 { max } else { size.min(max) };
+        Rectangle::from(
+            x as i32,
+            y as i32,
+            clip(self.source_width, image.width - x) as i32,
+            clip(self.source_height, image.height - y) as i32,
+        )
+    }
+
+    /// Size of the image on screen in pixels, without the cell offset.
+    pub fn get_display_size(&self, image: &KittyImage, font_size: Size<u8>) -> Size<i32> {
+        let source = self.get_source_rect(image).size;
+        let scale = |cells: u32, font_size: u8| {
+            i32::try_from(cells)
+                .unwrap_or(i32::MAX)
+                .saturating_mul(i32::from(font_size))
+        };
+        let width = scale(self.columns, font_size.width);
+        let height = scale(self.rows, font_size.height);
+        match (self.columns, self.rows) {
+            (0, 0) => source,
+            (_, 0) => Size::new(width, height_for_width(source, width)),
+            (0, _) => Size::new(height_for_width(transpose(source), height), height),
+            _ => Size::new(width, height),
+        }
+    }
+}
+
+fn height_for_width(size: Size<i32>, width: i32) -> i32 {
+    if size.width == 0 {
+        return 0;
+    }
+    let height = i64::from(size.height) * i64::from(width) / i64::from(size.width);
+    height.clamp(0, i64::from(i32::MAX)) as i32
+}
+
+fn transpose(size: Size<i32>) -> Size<i32> {
+    Size::new(size.height, size.width)
+}
+
+impl Buffer {
+    pub fn get_kitty_image(&self, id: u32) -> Option<&KittyImage> {
+        self.kitty_images.iter().find(|image| image.id == id)
+    }
+
+    /// Gets the newest image transmitted with an image number.
+    pub fn get_kitty_image_by_number(&self, number: u32) -> Option<&KittyImage> {
+        self.kitty_images
+            .iter()
+            .rev()
+            .find(|image| image.number == number)
+    }
+
+    /// Adds an image, an image with the same id is replaced. Its placements stay and show the new image.
+    /// Older images are evicted if the images exceed the quota.
+    pub fn add_kitty_image(&mut self, image: KittyImage) {
+        let id = image.id;
+        self.kitty_images.retain(|old| old.id != id);
+        self.kitty_images.push(image);
+        self.evict_kitty_images(Some(id));
+    }
+
+    /// Gets an id that isn't used by any image, ids wrap around after `u32::MAX`.
+    pub fn get_free_kitty_image_id(&self) -> u32 {
+        let mut id = self
+            .kitty_images
+            .iter()
+            .map(|image| image.id)
+            .max()
+            .unwrap_or_default();
+        loop {
+            id = id.wrapping_add(1);
+            if id != 0 && self.get_kitty_image(id).is_none() {
+                return id;
+            }
+        }
+    }
+
+    /// Places an image on the first layer, a placement with the same image & placement id is replaced.
+    pub fn add_kitty_placement(&mut self, placement: KittyPlacement) {
+        let placements = &mut self.layers[0].kitty_placements;
+        if placement.placement_id != 0 {
+            placements.retain(|old| {
+                old.image_id != placement.image_id || old.placement_id != placement.placement_id
+            });
+        }
+        placements.push(placement);
+    }
+
+    /// Removes the placements on the first layer `predicate` matches.
+    /// With `free_images` the images of the removed placements are removed as well if nothing else shows them.
+    pub fn delete_kitty_placements(
+        &mut self,
+        predicate: impl Fn(&Buffer, &KittyPlacement) -> bool,
+        free_images: bool,
+    ) {
+        let mut removed = Vec::new();
+        for placement in std::mem::take(&mut self.layers[0].kitty_placements) {
+            if predicate(self, &placement) {
+                removed.push(placement.image_id);
+            } else {
+                self.layers[0].kitty_placements.push(placement);
+            }
+        }
+        if free_images {
+            for id in removed {
+                self.free_kitty_image(id);
+            }
+        }
+    }
+
+    /// Cells covered by a placement, partially covered cells count.
+    pub fn get_kitty_placement_cells(&self, placement: &KittyPlacement) -> Rectangle {
+        let font_size = self.get_font_dimensions();
+        let size = self.get_kitty_image(placement.image_id).map_or_else(
+            || Size::new(0, 0),
+            |image| placement.get_display_size(image, font_size),
+        );
+        let font_width = i32::from(font_size.width.max(1));
+        let font_height = i32::from(font_size.height.max(1));
+        Rectangle::from(
+            placement.position.x,
+            placement.position.y,
+            (placement
+                .cell_offset
+                .x
+                .saturating_add(size.width)
+                .saturating_add(font_width - 1)
+                / font_width)
+                .max(1),
+            (placement
+                .cell_offset
+                .y
+                .saturating_add(size.height)
+                .saturating_add(font_height - 1)
+                / font_height)
+                .max(1),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Buffer, KittyImage, KittyPlacement, Position, Size};
+
+    #[test]
+    fn test_display_size() {
+        let image = KittyImage::new(1, 20, 10, vec![0; 20 * 10 * 4]);
+        let font_size = Size::new(8, 16);
+        let mut placement = KittyPlacement::new(1, Position::default());
+        assert_eq!(
+            Size::new(20, 10),
+            placement.get_display_size(&image, font_size)
+        );
+        placement.columns = 4;
+        assert_eq!(
+            Size::new(32, 16),
+            placement.get_display_size(&image, font_size)
+        );
+        placement.rows = 2;
+        assert_eq!(
+            Size::new(32, 32),
+            placement.get_display_size(&image, font_size)
+        );
+        placement.columns = 0;
+        placement.source_x = 10;
+        placement.source_width = 100;
+        assert_eq!(
+            Size::new(32, 32),
+            placement.get_display_size(&image, font_size)
+        );
+        assert_eq!(10, placement.get_source_rect(&image).size.width);
+
+        // huge sizes saturate instead of overflowing
+        placement.rows = u32::MAX;
+        assert_eq!(
+            Size::new(i32::MAX, i32::MAX),
+            placement.get_display_size(&image, font_size)
+        );
+    }
+
+    #[test]
+    fn test_delete_frees_images() {
+        let mut buf = Buffer::new();
+        buf.add_kitty_image(KittyImage::new(1, 1, 1, vec![0; 4]));
+        buf.add_kitty_image(KittyImage::new(2, 1, 1, vec![0; 4]));
+        buf.add_kitty_placement(KittyPlacement::new(1, Position::new(0, 0)));
+        buf.add_kitty_placement(KittyPlacement::new(1, Position::new(5, 0)));
+        buf.add_kitty_placement(KittyPlacement::new(2, Position::new(0, 1)));
+
+        buf.delete_kitty_placements(|_, p| p.position.y == 0 && p.position.x == 0, true);
+        assert!(buf.get_kitty_image(1).is_some());
+        buf.delete_kitty_placements(|_, p| p.position.y == 0, true);
+        assert!(buf.get_kitty_image(1).is_none());
+        buf.delete_kitty_placements(|_, _| true, false);
+        assert!(buf.get_kitty_image(2).is_some());
+        assert!(buf.layers[0].kitty_placements.is_empty());
+    }
+
+    #[test]
+    fn test_image_quota() {
+        let mut buf = Buffer::new();
+        buf.set_kitty_image_quota(12);
+        for id in 1..=3 {
+            buf.add_kitty_image(KittyImage::new(id, 1, 1, vec![0; 4]));
+        }
+        buf.add_kitty_placement(KittyPlacement::new(1, Position::new(0, 0)));
+
+        // unplaced images are evicted first
+        buf.add_kitty_image(KittyImage::new(4, 1, 1, vec![0; 4]));
+        assert!(buf.get_kitty_image(1).is_some());
+        assert!(buf.get_kitty_image(2).is_none());
+        buf.add_kitty_image(KittyImage::new(5, 2, 1, vec![0; 8]));
+        assert!(buf.get_kitty_image(1).is_some());
+        assert!(buf.get_kitty_image(3).is_none());
+        assert!(buf.get_kitty_image(4).is_none());
+
+        buf.add_kitty_image(KittyImage::new(6, 3, 1, vec![0; 12]));
+        assert_eq!(1, buf.kitty_images.len());
+        assert!(buf.layers[0].kitty_placements.is_empty());
+    }
+
+    #[test]
+    fn test_free_image_id() {
+        let mut buf = Buffer::new();
+        assert_eq!(1, buf.get_free_kitty_image_id());
+        buf.add_kitty_image(KittyImage::new(u32::MAX, 1, 1, vec![0; 4]));
+        assert_eq!(1, buf.get_free_kitty_image_id());
+        buf.add_kitty_image(KittyImage::new(1, 1, 1, vec![0; 4]));
+        assert_eq!(2, buf.get_free_kitty_image_id());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Line, Sixel};
+use crate::{KittyPlacement, Line, Sixel};
 
 use super::{AttributedChar, Position};
 
@@ -14,6 +14,8 @@ pub struct Layer {
     pub lines: Vec<Line>,
 
     pub sixels: Vec<Sixel>,
+    /// Images shown with the kitty graphics protocol, see [`crate::Buffer::kitty_images`].
+    pub kitty_placements: Vec<KittyPlacement>,
 }
 
 impl Layer {
@@ -26,6 +28,7 @@ impl Layer {
             is_transparent: true,
             lines: Vec::new(),
             sixels: Vec::new(),
+            kitty_placements: Vec::new(),
             offset: Position::default(),
         }
     }
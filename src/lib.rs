@@ -64,6 +64,36 @@ pub use sixel_mod::*;
 mod selection;
 pub use selection::*;
 
+mod scrollback;
+pub use scrollback::*;
+
+mod undo_stack;
+pub use undo_stack::*;
+
+mod clipboard;
+pub use clipboard::*;
+
+mod hyperlink;
+pub use hyperlink::*;
+
+mod kitty_graphics;
+pub use kitty_graphics::*;
+
+mod mouse;
+pub use mouse::*;
+
+mod keyboard;
+pub use keyboard::*;
+
+mod render;
+pub use render::*;
+
+mod animation;
+pub use animation::*;
+
+mod music;
+pub use music::*;
+
 pub type EngineResult<T> = Result<T, Box<dyn Error>>;
 
 #[derive(Copy, Clone, Debug, Default)]
@@ -1,18 +1,52 @@
 use super::AttributedChar;
+
+/// Size of the chars of a line, set with DECDWL/DECDHL (`ESC # 3/4/5/6`).
+/// Double height lines are double width as well, the top & bottom line show the
+/// upper & lower half of their chars.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineAttribute {
+    #[default]
+    Single,
+    DoubleWidth,
+    DoubleHeightTop,
+    DoubleHeightBottom,
+}
+
+impl LineAttribute {
+    /// Double width lines only show half of the columns.
+    pub fn is_double_width(self) -> bool {
+        self != LineAttribute::Single
+    }
+
+    pub fn is_double_height(self) -> bool {
+        matches!(
+            self,
+            LineAttribute::DoubleHeightTop | LineAttribute::DoubleHeightBottom
+        )
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Line {
     pub chars: Vec<Option<AttributedChar>>,
+    pub line_attribute: LineAttribute,
 }
 
 impl Line {
     pub fn new() -> Self {
-        Line { chars: Vec::new() }
+        Line {
+            chars: Vec::new(),
+            line_attribute: LineAttribute::Single,
+        }
     }
 
     pub fn create(width: u16) -> Self {
         let mut chars = Vec::new();
         chars.resize(width as usize, Some(AttributedChar::default()));
-        Line { chars }
+        Line {
+            chars,
+            line_attribute: LineAttribute::Single,
+        }
     }
 
     pub fn get_line_length(&self) -> usize {
@@ -0,0 +1,378 @@
+use crate::{Buffer, MouseEncoding, MouseMode, Position};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    /// No button is pressed, used for motion events.
+    None,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+}
+
+impl MouseButton {
+    pub fn is_wheel(self) -> bool {
+        matches!(
+            self,
+            MouseButton::WheelUp
+                | MouseButton::WheelDown
+                | MouseButton::WheelLeft
+                | MouseButton::WheelRight
+        )
+    }
+
+    fn code(self) -> u32 {
+        match self {
+            MouseButton::Left => 0,
+            MouseButton::Middle => 1,
+            MouseButton::Right => 2,
+            MouseButton::None => 3,
+            MouseButton::WheelUp => 64,
+            MouseButton::WheelDown => 65,
+            MouseButton::WheelLeft => 66,
+            MouseButton::WheelRight => 67,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press,
+    Release,
+    Motion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseModifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub control: bool,
+}
+
+impl MouseModifiers {
+    fn code(self) -> u32 {
+        let mut code = 0;
+        if self.shift {
+            code |= 4;
+        }
+        if self.alt {
+            code |= 8;
+        }
+        if self.control {
+            code |= 16;
+        }
+        code
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    pub button: MouseButton,
+    pub modifiers: MouseModifiers,
+    /// 0 based cell position.
+    pub position: Position,
+    /// 0 based pixel position, used by the SGR pixel encoding (1016).
+    /// If not set it's derived from the cell position & font size.
+    pub pixel_position: Option<Position>,
+}
+
+impl MouseEvent {
+    pub fn new(kind: MouseEventKind, button: MouseButton, position: Position) -> Self {
+        Self {
+            kind,
+            button,
+            modifiers: MouseModifiers::default(),
+            position,
+            pixel_position: None,
+        }
+    }
+}
+
+/// Largest coordinate the default encoding can transmit (255 - 32).
+const MAX_DEFAULT_COORD: u32 = 223;
+/// Largest coordinate the UTF-8 encoding can transmit (2 byte sequences - 32).
+const MAX_UTF8_COORD: u32 = 2015;
+
+/// Encodes a mouse event for the mouse mode & encoding the host enabled.
+/// Returns `None` if the event isn't reported in the active mode or
+/// the position can't be represented by the encoding.
+/// See <https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking>
+pub fn encode_mouse_event(buf: &Buffer, event: &MouseEvent) -> Option<Vec<u8>> {
+    let state = &buf.terminal_state;
+    let reported = match state.mouse_mode {
+        MouseMode::X10 => event.kind == MouseEventKind::Press,
+        MouseMode::VT200 | MouseMode::VT200_Highlight => event.kind != MouseEventKind::Motion,
+        MouseMode::ButtonEvents => {
+            event.kind != MouseEventKind::Motion || event.button != MouseButton::None
+        }
+        MouseMode::AnyEvents => true,
+        // Default and the deprecated modes don't track the mouse
+        _ => return encode_alternate_scroll(buf, event),
+    };
+    // wheel buttons have no release
+    if !reported || event.kind == MouseEventKind::Release && event.button.is_wheel() {
+        return None;
+    }
+
+    let sgr = matches!(
+        state.mouse_encoding,
+        MouseEncoding::SGR | MouseEncoding::SGRPixels
+    );
+    let mut cb = if event.kind == MouseEventKind::Release && !sgr {
+        3
+    } else {
+        event.button.code()
+    };
+    if state.mouse_mode != MouseMode::X10 {
+        cb |= event.modifiers.code();
+    }
+    if event.kind == MouseEventKind::Motion {
+        cb |= 32;
+    }
+
+    let position = if state.mouse_encoding == MouseEncoding::SGRPixels {
+        event.pixel_position.unwrap_or_else(|| {
+            let font_size = buf.get_font_dimensions();
+            Position::new(
+                event.position.x * font_size.width as i32,
+                event.position.y * font_size.height as i32,
+            )
+        })
+    } else {
+        event.position
+    };
+    let x = u32::try_from(position.x).ok()? + 1;
+    let y = u32::try_from(position.y).ok()? + 1;
+
+    match state.mouse_encoding {
+        MouseEncoding::Default => {
+            if x > MAX_DEFAULT_COORD || y > MAX_DEFAULT_COORD {
+                return None;
+            }
+            Some(vec![
+                0x1B,
+                b'[',
+                b'M',
+                (32 + cb) as u8,
+                (32 + x) as u8,
+                (32 + y) as u8,
+            ])
+        }
+        MouseEncoding::Utf8 => {
+            if x > MAX_UTF8_COORD || y > MAX_UTF8_COORD {
+                return None;
+            }
+            let mut result = "\x1B[M".to_string();
+            for value in [cb, x, y] {
+                result.push(char::from_u32(32 + value)?);
+            }
+            Some(result.into_bytes())
+        }
+        MouseEncoding::URXVT => Some(format!("\x1B[{};{x};{y}M", 32 + cb).into_bytes()),
+        MouseEncoding::SGR | MouseEncoding::SGRPixels => {
+            let final_char = if event.kind == MouseEventKind::Release {
+                'm'
+            } else {
+                'M'
+            };
+            Some(format!("\x1B[<{cb};{x};{y}{final_char}").into_bytes())
+        }
+    }
+}
+
+/// Wheel events are translated to cursor up/down on the alternate screen (1007)
+/// when mouse tracking is off, in application cursor key mode (DECCKM) as `ESC O A/B`.
+fn encode_alternate_scroll(buf: &Buffer, event: &MouseEvent) -> Option<Vec<u8>> {
+    if !buf.terminal_state.alternate_scroll
+        || !buf.is_alternate_screen()
+        || event.kind != MouseEventKind::Press
+    {
+        return None;
+    }
+    let final_char = match event.button {
+        MouseButton::WheelUp => b'A',
+        MouseButton::WheelDown => b'B',
+        _ => return None,
+    };
+    let prefix = if buf.terminal_state.application_cursor_keys {
+        b'O'
+    } else {
+        b'['
+    };
+    Some(vec![0x1B, prefix, final_char])
+}
+
+/// Encodes a focus change, returns `None` unless the host enabled focus events (1004).
+pub fn encode_focus_event(buf: &Buffer, focused: bool) -> Option<Vec<u8>> {
+    if !buf.terminal_state.focus_events {
+        return None;
+    }
+    Some(if focused {
+        b"\x1B[I".to_vec()
+    } else {
+        b"\x1B[O".to_vec()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode_focus_event, encode_mouse_event, MouseButton, MouseEvent, MouseEventKind,
+        MouseModifiers,
+    };
+    use crate::{Buffer, Caret, MouseEncoding, MouseMode, Position};
+
+    fn create_buffer(mode: MouseMode, encoding: MouseEncoding) -> Buffer {
+        let mut buf = Buffer::new();
+        buf.terminal_state.mouse_mode = mode;
+        buf.terminal_state.mouse_encoding = encoding;
+        buf
+    }
+
+    fn event(kind: MouseEventKind, button: MouseButton, x: i32, y: i32) -> MouseEvent {
+        MouseEvent::new(kind, button, Position::new(x, y))
+    }
+
+    #[test]
+    fn test_default_encoding() {
+        let buf = create_buffer(MouseMode::VT200, MouseEncoding::Default);
+        let press = event(MouseEventKind::Press, MouseButton::Left, 0, 0);
+        assert_eq!(
+            Some(b"\x1B[M !!".to_vec()),
+            encode_mouse_event(&buf, &press)
+        );
+        let release = event(MouseEventKind::Release, MouseButton::Right, 9, 4);
+        assert_eq!(
+            Some(b"\x1B[M#*%".to_vec()),
+            encode_mouse_event(&buf, &release)
+        );
+        // not representable
+        let far = event(MouseEventKind::Press, MouseButton::Left, 223, 0);
+        assert_eq!(None, encode_mouse_event(&buf, &far));
+        // no motion in VT200 mode
+        let motion = event(MouseEventKind::Motion, MouseButton::Left, 1, 1);
+        assert_eq!(None, encode_mouse_event(&buf, &motion));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let mut press = event(MouseEventKind::Press, MouseButton::Middle, 0, 0);
+        press.modifiers = MouseModifiers {
+            shift: true,
+            alt: false,
+            control: true,
+        };
+        let buf = create_buffer(MouseMode::VT200, MouseEncoding::SGR);
+        assert_eq!(
+            Some(b"\x1B[<21;1;1M".to_vec()),
+            encode_mouse_event(&buf, &press)
+        );
+        // X10 doesn't report modifiers
+        let buf = create_buffer(MouseMode::X10, MouseEncoding::SGR);
+        assert_eq!(
+            Some(b"\x1B[<1;1;1M".to_vec()),
+            encode_mouse_event(&buf, &press)
+        );
+    }
+
+    #[test]
+    fn test_x10_press_only() {
+        let buf = create_buffer(MouseMode::X10, MouseEncoding::Default);
+        let release = event(MouseEventKind::Release, MouseButton::Left, 0, 0);
+        assert_eq!(None, encode_mouse_event(&buf, &release));
+    }
+
+    #[test]
+    fn test_motion_modes() {
+        let drag = event(MouseEventKind::Motion, MouseButton::Left, 2, 3);
+        let motion = event(MouseEventKind::Motion, MouseButton::None, 2, 3);
+
+        let buf = create_buffer(MouseMode::ButtonEvents, MouseEncoding::SGR);
+        assert_eq!(
+            Some(b"\x1B[<32;3;4M".to_vec()),
+            encode_mouse_event(&buf, &drag)
+        );
+        assert_eq!(None, encode_mouse_event(&buf, &motion));
+
+        let buf = create_buffer(MouseMode::AnyEvents, MouseEncoding::SGR);
+        assert_eq!(
+            Some(b"\x1B[<35;3;4M".to_vec()),
+            encode_mouse_event(&buf, &motion)
+        );
+    }
+
+    #[test]
+    fn test_sgr_release_and_wheel() {
+        let buf = create_buffer(MouseMode::VT200, MouseEncoding::SGR);
+        let release = event(MouseEventKind::Release, MouseButton::Right, 0, 0);
+        assert_eq!(
+            Some(b"\x1B[<2;1;1m".to_vec()),
+            encode_mouse_event(&buf, &release)
+        );
+        let wheel = event(MouseEventKind::Press, MouseButton::WheelDown, 0, 0);
+        assert_eq!(
+            Some(b"\x1B[<65;1;1M".to_vec()),
+            encode_mouse_event(&buf, &wheel)
+        );
+        let wheel_release = event(MouseEventKind::Release, MouseButton::WheelDown, 0, 0);
+        assert_eq!(None, encode_mouse_event(&buf, &wheel_release));
+    }
+
+    #[test]
+    fn test_extended_encodings() {
+        let press = event(MouseEventKind::Press, MouseButton::Left, 299, 0);
+        let buf = create_buffer(MouseMode::VT200, MouseEncoding::Utf8);
+        // x = 300 is sent as U+014C
+        assert_eq!(
+            Some("\x1B[M \u{14C}!".as_bytes().to_vec()),
+            encode_mouse_event(&buf, &press)
+        );
+
+        let buf = create_buffer(MouseMode::VT200, MouseEncoding::URXVT);
+        assert_eq!(
+            Some(b"\x1B[32;300;1M".to_vec()),
+            encode_mouse_event(&buf, &press)
+        );
+    }
+
+    #[test]
+    fn test_pixel_position() {
+        let buf = create_buffer(MouseMode::VT200, MouseEncoding::SGRPixels);
+        let mut press = event(MouseEventKind::Press, MouseButton::Left, 2, 1);
+        // derived from the 8x16 font
+        assert_eq!(
+            Some(b"\x1B[<0;17;17M".to_vec()),
+            encode_mouse_event(&buf, &press)
+        );
+        press.pixel_position = Some(Position::new(20, 21));
+        assert_eq!(
+            Some(b"\x1B[<0;21;22M".to_vec()),
+            encode_mouse_event(&buf, &press)
+        );
+    }
+
+    #[test]
+    fn test_alternate_scroll() {
+        let mut buf = create_buffer(MouseMode::Default, MouseEncoding::Default);
+        let wheel = event(MouseEventKind::Press, MouseButton::WheelUp, 0, 0);
+        buf.terminal_state.alternate_scroll = true;
+        assert_eq!(None, encode_mouse_event(&buf, &wheel));
+        buf.switch_to_alternate_screen(&Caret::default());
+        assert_eq!(Some(b"\x1B[A".to_vec()), encode_mouse_event(&buf, &wheel));
+        buf.terminal_state.application_cursor_keys = true;
+        assert_eq!(Some(b"\x1BOA".to_vec()), encode_mouse_event(&buf, &wheel));
+    }
+
+    #[test]
+    fn test_focus_events() {
+        let mut buf = Buffer::new();
+        assert_eq!(None, encode_focus_event(&buf, true));
+        buf.terminal_state.focus_events = true;
+        assert_eq!(Some(b"\x1B[I".to_vec()), encode_focus_event(&buf, true));
+        assert_eq!(Some(b"\x1B[O".to_vec()), encode_focus_event(&buf, false));
+    }
+}
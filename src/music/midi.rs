@@ -0,0 +1,372 @@
+use std::io;
+
+use crate::{ansi::FREQ, AnsiMusic, MusicAction, MusicStyle};
+
+use super::get_note_duration_ms;
+
+/// Ticks per quarter note.
+const DIVISION: u16 = 960;
+/// µs per quarter note (120 bpm).
+const TEMPO: u32 = 500_000;
+/// MIDI note of `FREQ[0]` (C2).
+const FIRST_NOTE: usize = 36;
+/// General MIDI "Lead 1 (square)".
+const SQUARE_PROGRAM: u8 = 80;
+/// Timing difference in ms that's still taken as the same point in time.
+const TOLERANCE_MS: f64 = 1.0;
+
+fn ms_to_ticks(ms: f64) -> f64 {
+    ms * f64::from(DIVISION) * 1000.0 / f64::from(TEMPO)
+}
+
+fn get_articulation(style: MusicStyle) -> f64 {
+    match style {
+        MusicStyle::Legato => 1.0,
+        MusicStyle::Staccato => 3.0 / 4.0,
+        _ => 7.0 / 8.0,
+    }
+}
+
+fn freq_to_note(freq: f32) -> u8 {
+    let note = 69.0 + 12.0 * (f64::from(freq) / 440.0).log2();
+    note.round().clamp(0.0, 127.0) as u8
+}
+
+fn note_to_freq(note: u8) -> f32 {
+    match FREQ.get((note as usize).wrapping_sub(FIRST_NOTE)) {
+        Some(freq) => *freq,
+        None => (440.0 * 2f64.powf((f64::from(note) - 69.0) / 12.0)) as f32,
+    }
+}
+
+/// Inverse of [`get_note_duration_ms`], gives the `tempo * length` value the parser produces.
+fn get_tempo_length(duration_ms: f64) -> u32 {
+    ((240_000.0 / duration_ms.max(f64::EPSILON)).round() as u32).max(1)
+}
+
+fn write_var_len(result: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    result.extend(bytes.iter().rev());
+}
+
+/// Converts music to a type 0 Standard MIDI File. Notes are played by a square lead,
+/// the note off events follow the articulation of the current [`MusicStyle`]
+/// and pauses become rests.
+pub fn convert_to_midi(music: &AnsiMusic) -> Vec<u8> {
+    let mut track = Vec::new();
+    // tempo & program
+    track.extend([0x00, 0xFF, 0x51, 0x03]);
+    track.extend(&TEMPO.to_be_bytes()[1..]);
+    track.extend([0x00, 0xC0, SQUARE_PROGRAM]);
+
+    let mut style = MusicStyle::Normal;
+    // fractional ticks are carried over so the total length doesn't drift
+    let mut time = 0.0;
+    let mut last_tick = 0;
+    let mut write_event = |track: &mut Vec<u8>, tick: f64, event: &[u8]| {
+        let tick = tick.round() as u32;
+        write_var_len(track, tick - last_tick);
+        track.extend(event);
+        last_tick = tick;
+    };
+
+    for action in &music.music_actions {
+        match action {
+            MusicAction::SetStyle(new_style) => {
+                if matches!(
+                    new_style,
+                    MusicStyle::Normal | MusicStyle::Legato | MusicStyle::Staccato
+                ) {
+                    style = *new_style;
+                }
+            }
+            MusicAction::Pause(len) => time += ms_to_ticks(get_note_duration_ms(*len)),
+            MusicAction::PlayNote(freq, len) => {
+                let duration = ms_to_ticks(get_note_duration_ms(*len));
+                if *freq > 0.0 {
+                    let note = freq_to_note(*freq);
+                    write_event(&mut track, time, &[0x90, note, 0x64]);
+                    let note_end = time + duration * get_articulation(style);
+                    write_event(&mut track, note_end, &[0x80, note, 0x00]);
+                }
+                time += duration;
+            }
+        }
+    }
+    write_event(&mut track, time, &[0xFF, 0x2F, 0x00]);
+
+    let mut result = Vec::new();
+    result.extend(b"MThd");
+    result.extend(6u32.to_be_bytes());
+    result.extend(0u16.to_be_bytes()); // format 0
+    result.extend(1u16.to_be_bytes()); // 1 track
+    result.extend(DIVISION.to_be_bytes());
+    result.extend(b"MTrk");
+    result.extend((track.len() as u32).to_be_bytes());
+    result.extend(track);
+    result
+}
+
+struct MidiReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl MidiReader<'_> {
+    fn read_bytes(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.pos + len > self.bytes.len() {
+            return Err(invalid_data("Invalid MIDI - unexpected end of file"));
+        }
+        let result = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(result)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_var_len(&mut self) -> io::Result<u32> {
+        let mut result = 0;
+        for _ in 0..4 {
+            let b = self.read_u8()?;
+            result = (result << 7) | u32::from(b & 0x7F);
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(invalid_data(
+            "Invalid MIDI - variable length value too long",
+        ))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+struct Note {
+    on_ms: f64,
+    off_ms: f64,
+    key: u8,
+}
+
+/// Reads a type 0 Standard MIDI File as produced by [`convert_to_midi`].
+/// Notes are taken as monophonic melody, the articulation is guessed from the
+/// note lengths.
+///
+/// # Errors
+///
+/// This function will return an error if the file isn't a valid type 0 MIDI file.
+pub fn read_midi(bytes: &[u8]) -> io::Result<AnsiMusic> {
+    let mut reader = MidiReader { bytes, pos: 0 };
+    if reader.read_bytes(4)? != b"MThd" {
+        return Err(invalid_data("Invalid MIDI - header not found"));
+    }
+    let header_len = reader.read_u32()? as usize;
+    let format = reader.read_u16()?;
+    if format != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported MIDI format {format}"),
+        ));
+    }
+    reader.read_u16()?;
+    let division = reader.read_u16()?;
+    if division == 0 || division & 0x8000 != 0 {
+        return Err(invalid_data("Unsupported MIDI time division"));
+    }
+    reader.read_bytes(header_len.saturating_sub(6))?;
+    if reader.read_bytes(4)? != b"MTrk" {
+        return Err(invalid_data("Invalid MIDI - track not found"));
+    }
+    let track_len = reader.read_u32()? as usize;
+    let track_end = reader.pos + track_len;
+
+    let mut tempo = TEMPO;
+    let mut time_ms = 0.0;
+    let mut running_status = 0;
+    let mut active: Vec<(u8, f64)> = Vec::new();
+    let mut notes = Vec::new();
+
+    while reader.pos < track_end {
+        let delta = reader.read_var_len()?;
+        time_ms += f64::from(delta) * f64::from(tempo) / f64::from(division) / 1000.0;
+        let mut status = reader.read_u8()?;
+        match status {
+            0xFF => {
+                let kind = reader.read_u8()?;
+                let len = reader.read_var_len()? as usize;
+                let data = reader.read_bytes(len)?;
+                match kind {
+                    0x51 if len == 3 => {
+                        tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                    }
+                    0x2F => break,
+                    _ => {}
+                }
+                continue;
+            }
+            0xF0 | 0xF7 => {
+                let len = reader.read_var_len()? as usize;
+                reader.read_bytes(len)?;
+                continue;
+            }
+            0x80..=0xEF => running_status = status,
+            _ => {
+                if running_status == 0 {
+                    return Err(invalid_data("Invalid MIDI - missing status byte"));
+                }
+                reader.pos -= 1;
+                status = running_status;
+            }
+        }
+        let data1 = reader.read_u8()?;
+        if matches!(status & 0xF0, 0xC0 | 0xD0) {
+            continue;
+        }
+        let data2 = reader.read_u8()?;
+        match status & 0xF0 {
+            0x90 if data2 > 0 => active.push((data1, time_ms)),
+            0x80 | 0x90 => {
+                if let Some(i) = active.iter().position(|(note, _)| *note == data1) {
+                    let (key, on_ms) = active.remove(i);
+                    notes.push(Note {
+                        on_ms,
+                        off_ms: time_ms,
+                        key,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    notes.sort_by(|a, b| a.on_ms.total_cmp(&b.on_ms));
+
+    let mut music = AnsiMusic::default();
+    let mut style = MusicStyle::Normal;
+    let mut cursor = 0.0;
+    for (i, note) in notes.iter().enumerate() {
+        if note.on_ms - cursor > TOLERANCE_MS {
+            music
+                .music_actions
+                .push(MusicAction::Pause(get_tempo_length(note.on_ms - cursor)));
+        }
+        let next_ms = notes
+            .get(i + 1)
+            .map_or(time_ms.max(note.off_ms), |next| next.on_ms);
+        let sounding = note.off_ms - note.on_ms;
+        let slot = next_ms - note.on_ms;
+
+        // prefer the current style, switch only if another one fits the gap to the next note
+        let new_style = [
+            style,
+            MusicStyle::Legato,
+            MusicStyle::Normal,
+            MusicStyle::Staccato,
+        ]
+        .into_iter()
+        .find(|style| (slot - sounding / get_articulation(*style)).abs() <= TOLERANCE_MS)
+        .unwrap_or(style);
+        if new_style != style {
+            style = new_style;
+            music.music_actions.push(MusicAction::SetStyle(style));
+        }
+        let duration = sounding / get_articulation(style);
+        music.music_actions.push(MusicAction::PlayNote(
+            note_to_freq(note.key),
+            get_tempo_length(duration),
+        ));
+        cursor = note.on_ms + duration;
+    }
+    if time_ms - cursor > TOLERANCE_MS {
+        music
+            .music_actions
+            .push(MusicAction::Pause(get_tempo_length(time_ms - cursor)));
+    }
+    Ok(music)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_to_midi, freq_to_note, note_to_freq, read_midi, write_var_len};
+    use crate::{ansi::FREQ, AnsiMusic, MusicAction, MusicStyle};
+
+    #[test]
+    fn test_var_len() {
+        let mut result = Vec::new();
+        write_var_len(&mut result, 0);
+        write_var_len(&mut result, 0x7F);
+        write_var_len(&mut result, 0x80);
+        write_var_len(&mut result, 0x3FFF);
+        assert_eq!(vec![0x00, 0x7F, 0x81, 0x00, 0xFF, 0x7F], result);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_note_numbers() {
+        // FREQ starts at C2
+        assert_eq!(36, freq_to_note(FREQ[0]));
+        assert_eq!(69, freq_to_note(440.0));
+        assert_eq!(69, freq_to_note(441.0));
+        for (i, freq) in FREQ.iter().enumerate() {
+            assert_eq!(*freq, note_to_freq(freq_to_note(*freq)), "{i}");
+        }
+    }
+
+    #[test]
+    fn test_midi_file() {
+        let music = AnsiMusic {
+            music_actions: vec![MusicAction::PlayNote(440.0, 4 * 120)],
+        };
+        let midi = convert_to_midi(&music);
+        assert_eq!(b"MThd", &midi[0..4]);
+        // format 0, 1 track, 960 ticks per quarter
+        assert_eq!(&[0, 0, 0, 1, 0x03, 0xC0], &midi[8..14]);
+        assert_eq!(b"MTrk", &midi[14..18]);
+        // normal notes sound 7/8 of a quarter note
+        let note_off = [0x86, 0x48, 0x80, 69, 0x00];
+        assert!(midi.windows(5).any(|w| w == note_off));
+        // followed by 1/8 rest
+        assert!(midi.ends_with(&[0x78, 0xFF, 0x2F, 0x00]));
+    }
+
+    #[test]
+    fn test_read_midi() {
+        let music = AnsiMusic {
+            music_actions: vec![
+                MusicAction::PlayNote(440.0, 4 * 120),
+                MusicAction::Pause(8 * 120),
+                MusicAction::SetStyle(MusicStyle::Staccato),
+                MusicAction::PlayNote(FREQ[0], 2 * 120),
+                MusicAction::SetStyle(MusicStyle::Legato),
+                MusicAction::PlayNote(FREQ[1], 8 * 120),
+            ],
+        };
+        assert_eq!(music, read_midi(&convert_to_midi(&music)).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_midi() {
+        assert!(read_midi(b"RIFF").is_err());
+        let mut midi = convert_to_midi(&AnsiMusic::default());
+        // format 1
+        midi[9] = 1;
+        assert!(read_midi(&midi).is_err());
+    }
+}
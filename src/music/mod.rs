@@ -0,0 +1,193 @@
+use crate::{AnsiMusic, MusicAction, MusicStyle};
+
+mod midi;
+pub use midi::*;
+
+/// Options for [`render_music`].
+#[derive(Clone, Debug)]
+pub struct AudioOptions {
+    pub sample_rate: u32,
+    /// Amplitude of the square wave, 0.0 - 1.0.
+    pub volume: f32,
+}
+
+impl AudioOptions {
+    pub fn new() -> Self {
+        AudioOptions {
+            sample_rate: 44100,
+            volume: 0.25,
+        }
+    }
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Gets the duration of a note or pause in ms. The parser passes `tempo * length`,
+/// a whole note (length 1) lasts 4 quarter notes at `tempo` quarter notes per minute.
+pub fn get_note_duration_ms(tempo_length: u32) -> f64 {
+    240_000.0 / tempo_length.max(1) as f64
+}
+
+/// Renders music to signed 16 bit mono PCM samples. Notes are played as square wave
+/// like the PC speaker does. Normal notes sound 7/8 & staccato notes 3/4 of their length,
+/// the rest is silence. Legato notes sound for their full length.
+pub fn render_music(music: &AnsiMusic, options: &AudioOptions) -> Vec<i16> {
+    let sample_rate = options.sample_rate as f64;
+    let amplitude = (options.volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16;
+    let mut samples = Vec::new();
+    let mut style = MusicStyle::Normal;
+    // fractional samples are carried over so the total length doesn't drift
+    let mut time = 0.0;
+    let mut phase = 0.0;
+
+    for action in &music.music_actions {
+        match action {
+            MusicAction::SetStyle(new_style) => {
+                if matches!(
+                    new_style,
+                    MusicStyle::Normal | MusicStyle::Legato | MusicStyle::Staccato
+                ) {
+                    style = *new_style;
+                }
+            }
+            MusicAction::Pause(len) => {
+                time += get_note_duration_ms(*len) / 1000.0 * sample_rate;
+                samples.resize(time as usize, 0);
+            }
+            MusicAction::PlayNote(freq, len) => {
+                let duration = get_note_duration_ms(*len) / 1000.0 * sample_rate;
+                let sounding = match style {
+                    MusicStyle::Legato => duration,
+                    MusicStyle::Staccato => duration * 3.0 / 4.0,
+                    _ => duration * 7.0 / 8.0,
+                };
+                let start = samples.len();
+                let note_end = (time + sounding) as usize;
+                let step = f64::from(*freq) / sample_rate;
+                for _ in start..note_end {
+                    samples.push(if *freq <= 0.0 {
+                        0
+                    } else if phase < 0.5 {
+                        amplitude
+                    } else {
+                        -amplitude
+                    });
+                    phase = (phase + step).fract();
+                }
+                time += duration;
+                samples.resize(time as usize, 0);
+            }
+        }
+    }
+    samples
+}
+
+/// Encodes signed 16 bit mono PCM samples as WAV file.
+pub fn convert_to_wav(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut result = Vec::with_capacity(44 + data_len as usize);
+    result.extend(b"RIFF");
+    result.extend((36 + data_len).to_le_bytes());
+    result.extend(b"WAVE");
+
+    result.extend(b"fmt ");
+    result.extend(16u32.to_le_bytes());
+    result.extend(1u16.to_le_bytes()); // PCM
+    result.extend(1u16.to_le_bytes()); // mono
+    result.extend(sample_rate.to_le_bytes());
+    result.extend((sample_rate * 2).to_le_bytes()); // byte rate
+    result.extend(2u16.to_le_bytes()); // block align
+    result.extend(16u16.to_le_bytes()); // bits per sample
+
+    result.extend(b"data");
+    result.extend(data_len.to_le_bytes());
+    for sample in samples {
+        result.extend(sample.to_le_bytes());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_to_wav, render_music, AudioOptions};
+    use crate::{AnsiMusic, MusicAction, MusicStyle};
+
+    fn options() -> AudioOptions {
+        AudioOptions {
+            sample_rate: 8000,
+            volume: 0.5,
+        }
+    }
+
+    fn sounding_samples(samples: &[i16]) -> usize {
+        samples.iter().filter(|s| **s != 0).count()
+    }
+
+    #[test]
+    fn test_note_length() {
+        // quarter note at tempo 120 = 500ms
+        let music = AnsiMusic {
+            music_actions: vec![MusicAction::PlayNote(440.0, 4 * 120)],
+        };
+        let samples = render_music(&music, &options());
+        assert_eq!(4000, samples.len());
+        assert_eq!(3500, sounding_samples(&samples));
+        assert_eq!(i16::MAX / 2, samples[0]);
+    }
+
+    #[test]
+    fn test_articulation() {
+        let note = MusicAction::PlayNote(440.0, 4 * 120);
+        let music = AnsiMusic {
+            music_actions: vec![MusicAction::SetStyle(MusicStyle::Legato), note],
+        };
+        assert_eq!(4000, sounding_samples(&render_music(&music, &options())));
+
+        let music = AnsiMusic {
+            music_actions: vec![MusicAction::SetStyle(MusicStyle::Staccato), note],
+        };
+        assert_eq!(3000, sounding_samples(&render_music(&music, &options())));
+    }
+
+    #[test]
+    fn test_square_wave() {
+        // 1000 Hz at 8000 samples/s = 4 high & 4 low samples
+        let music = AnsiMusic {
+            music_actions: vec![
+                MusicAction::SetStyle(MusicStyle::Legato),
+                MusicAction::PlayNote(1000.0, 4 * 120),
+            ],
+        };
+        let samples = render_music(&music, &options());
+        let high = i16::MAX / 2;
+        assert_eq!(
+            vec![high, high, high, high, -high, -high, -high, -high],
+            samples[0..8].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_pause() {
+        let music = AnsiMusic {
+            music_actions: vec![MusicAction::Pause(8 * 120)],
+        };
+        let samples = render_music(&music, &options());
+        assert_eq!(2000, samples.len());
+        assert_eq!(0, sounding_samples(&samples));
+    }
+
+    #[test]
+    fn test_wav() {
+        let wav = convert_to_wav(&[1, -1], 8000);
+        assert_eq!(b"RIFF", &wav[0..4]);
+        assert_eq!(40, u32::from_le_bytes(wav[4..8].try_into().unwrap()));
+        assert_eq!(b"WAVE", &wav[8..12]);
+        assert_eq!(8000, u32::from_le_bytes(wav[24..28].try_into().unwrap()));
+        assert_eq!(4, u32::from_le_bytes(wav[40..44].try_into().unwrap()));
+        assert_eq!(&[1, 0, 0xFF, 0xFF], &wav[44..48]);
+    }
+}
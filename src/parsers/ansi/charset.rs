@@ -0,0 +1,164 @@
+use crate::ascii::UNICODE_TO_CP437;
+
+/// 94 character sets that can be designated to G0-G3.
+/// See <https://vt100.net/docs/vt510-rm/SCS.html>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CharacterSet {
+    #[default]
+    Ascii,
+    /// `ESC ( 0` line drawing set.
+    DecSpecialGraphics,
+    /// `ESC ( A` ASCII with `#` replaced by `£`.
+    Uk,
+    /// `ESC ( <` or `ESC ( % 5` Latin-1 like upper half of the DEC multinational set.
+    DecSupplemental,
+    /// Soft character set loaded with DECDLD, chars are printed with the font in the given slot.
+    Drcs(usize),
+}
+
+impl CharacterSet {
+    /// Gets the set for the final char(s) of a designation sequence.
+    pub fn from_designator(designator: &str) -> Option<Self> {
+        match designator {
+            "B" => Some(CharacterSet::Ascii),
+            "0" => Some(CharacterSet::DecSpecialGraphics),
+            "A" => Some(CharacterSet::Uk),
+            "<" | "%5" => Some(CharacterSet::DecSupplemental),
+            _ => None,
+        }
+    }
+
+    /// Maps a char of the GL area (`0x21..=0x7E`) to a CP437 code point.
+    /// Chars without CP437 glyph are left unchanged.
+    pub fn map_char(self, ch: char) -> char {
+        if !('\x21'..='\x7E').contains(&ch) {
+            return ch;
+        }
+        match self {
+            CharacterSet::Ascii | CharacterSet::Drcs(_) => ch,
+            CharacterSet::Uk => {
+                if ch == '#' {
+                    '\u{9C}'
+                } else {
+                    ch
+                }
+            }
+            CharacterSet::DecSpecialGraphics => map_special_graphics(ch),
+            CharacterSet::DecSupplemental => {
+                let unicode = match ch as u32 + 0x80 {
+                    0xA8 => '\u{A4}',
+                    0xD7 => 'Œ',
+                    0xDD => 'Ÿ',
+                    0xF7 => 'œ',
+                    0xFD => 'ÿ',
+                    code => char::from_u32(code).unwrap_or(ch),
+                };
+                UNICODE_TO_CP437.get(&unicode).copied().unwrap_or(ch)
+            }
+        }
+    }
+}
+
+fn map_special_graphics(ch: char) -> char {
+    match ch {
+        '_' => ' ',
+        '`' => '\u{04}', // ◆
+        'a' => '\u{B1}', // ▒
+        'f' => '\u{F8}', // °
+        'g' => '\u{F1}', // ±
+        'j' => '\u{D9}', // ┘
+        'k' => '\u{BF}', // ┐
+        'l' => '\u{DA}', // ┌
+        'm' => '\u{C0}', // └
+        'n' => '\u{C5}', // ┼
+        // scan lines 1, 3, 5, 7
+        'o' | 'p' | 'q' | 'r' => '\u{C4}',
+        's' => '_',
+        't' => '\u{C3}', // ├
+        'u' => '\u{B4}', // ┤
+        'v' => '\u{C1}', // ┴
+        'w' => '\u{C2}', // ┬
+        'x' => '\u{B3}', // │
+        'y' => '\u{F3}', // ≤
+        'z' => '\u{F2}', // ≥
+        '{' => '\u{E3}', // π
+        '}' => '\u{9C}', // £
+        '~' => '\u{FA}', // ·
+        _ => ch,
+    }
+}
+
+/// G0-G3 designations and the shift state.
+#[derive(Debug, Clone, Default)]
+pub(super) struct CharsetState {
+    pub charsets: [CharacterSet; 4],
+    /// Set invoked into GL, 0 (SI) or 1 (SO).
+    pub gl: usize,
+    /// Set used for the next char only (SS2/SS3).
+    pub single_shift: Option<usize>,
+    /// SO & SI are only treated as shifts once G1 got designated, before that
+    /// they're printed as the CP437 glyphs ANSI art uses them for.
+    pub g1_designated: bool,
+}
+
+impl CharsetState {
+    pub fn designate(&mut self, g: usize, charset: CharacterSet) {
+        self.charsets[g] = charset;
+        if g == 1 {
+            self.g1_designated = true;
+        }
+    }
+
+    /// Maps a char with the invoked set, gives back the font slot for soft character sets.
+    pub fn map_char(&mut self, ch: char) -> (char, Option<usize>) {
+        let g = self.single_shift.take().unwrap_or(self.gl);
+        let font_page = match self.charsets[g] {
+            CharacterSet::Drcs(slot) if ('\x20'..='\x7F').contains(&ch) => Some(slot),
+            _ => None,
+        };
+        (self.charsets[g].map_char(ch), font_page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CharacterSet, CharsetState};
+
+    #[test]
+    fn test_special_graphics() {
+        let set = CharacterSet::DecSpecialGraphics;
+        let line: String = "lqkxmjntuvwa".chars().map(|ch| set.map_char(ch)).collect();
+        assert_eq!(
+            "\u{DA}\u{C4}\u{BF}\u{B3}\u{C0}\u{D9}\u{C5}\u{C3}\u{B4}\u{C1}\u{C2}\u{B1}",
+            line
+        );
+        assert_eq!('A', set.map_char('A'));
+    }
+
+    #[test]
+    fn test_uk_and_supplemental() {
+        assert_eq!('\u{9C}', CharacterSet::Uk.map_char('#'));
+        assert_eq!('a', CharacterSet::Uk.map_char('a'));
+        // 0xE4 ä
+        assert_eq!('\u{84}', CharacterSet::DecSupplemental.map_char('d'));
+        // 0xFD ÿ
+        assert_eq!('\u{98}', CharacterSet::DecSupplemental.map_char('}'));
+    }
+
+    #[test]
+    fn test_single_shift() {
+        let mut state = CharsetState::default();
+        state.designate(2, CharacterSet::DecSpecialGraphics);
+        state.single_shift = Some(2);
+        assert_eq!(('\u{C4}', None), state.map_char('q'));
+        assert_eq!(('q', None), state.map_char('q'));
+    }
+
+    #[test]
+    fn test_drcs() {
+        let mut state = CharsetState::default();
+        state.designate(0, CharacterSet::Drcs(256));
+        assert_eq!(('A', Some(256)), state.map_char('A'));
+        assert_eq!(('\u{84}', None), state.map_char('\u{84}'));
+    }
+}
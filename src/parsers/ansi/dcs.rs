@@ -1,8 +1,9 @@
-use std::thread;
-
 use base64::{engine::general_purpose, Engine};
 
-use crate::{BitFont, Buffer, CallbackAction, Caret, EngineResult, ParserError, Sixel, HEX_TABLE};
+use crate::{
+    BitFont, Buffer, CallbackAction, Caret, EngineResult, Palette, ParserError, Position, Sixel,
+    SixelJob, HEX_TABLE,
+};
 
 use super::Parser;
 
@@ -17,7 +18,7 @@ impl Parser {
     pub(super) fn execute_dcs(
         &mut self,
         buf: &mut Buffer,
-        caret: &Caret,
+        caret: &mut Caret,
     ) -> EngineResult<CallbackAction> {
         if self.dcs_string.starts_with("CTerm:Font:") {
             return self.load_custom_font(buf);
@@ -42,34 +43,81 @@ impl Parser {
             i += 1;
         }
 
+        if self.dcs_string[i..].starts_with('{') {
+            let dcs_string = std::mem::take(&mut self.dcs_string);
+            return self.load_soft_font(buf, &dcs_string[..i], &dcs_string[i + 1..]);
+        }
+
         if self.dcs_string[i..].starts_with("!z") {
             return self.parse_macro(i + 2);
         }
 
         if self.dcs_string[i..].starts_with('q') {
-            let horizontal_scale = match self.parsed_numbers.first() {
+            // P1 selects the pixel aspect ratio, the raster attributes may override it
+            let vertical_scale = match self.parsed_numbers.first() {
                 Some(0 | 1 | 5 | 6) => 2,
                 Some(2) => 5,
                 Some(3 | 4) => 3,
                 _ => 1,
             };
 
+            // P2 = 1 keeps the pixels that aren't drawn transparent
             let bg_color = if let Some(1) = self.parsed_numbers.get(1) {
                 [0, 0, 0, 0]
             } else {
                 let (r, g, b) = buf.palette.colors[caret.attr.get_background() as usize].get_rgb();
-                [0xff, r, g, b]
+                [r, g, b, 0xff]
             };
 
-            let p = caret.get_position();
+            let p = if buf.terminal_state.sixel_display_mode {
+                Position::new(0, buf.get_first_visible_line())
+            } else {
+                caret.get_position()
+            };
             let dcs_string = std::mem::take(&mut self.dcs_string);
-            let handle = thread::spawn(move || {
-                Sixel::parse_from(p, 1, horizontal_scale, bg_color, &dcs_string[i + 1..])
-                    .unwrap()
-            });
 
-            buf.sixel_threads.push_back(handle);
+            if buf.terminal_state.sixel_display_mode
+                && buf.terminal_state.sixel_private_color_registers
+            {
+                // the picture doesn't move the caret, it may be decoded in the background
+                buf.decode_sixel(SixelJob {
+                    position: p,
+                    vertical_scale,
+                    horizontal_scale: 1,
+                    background: bg_color,
+                    data: dcs_string[i + 1..].to_string(),
+                })?;
+                return Ok(CallbackAction::None);
+            }
+
+            // Scrolling pictures are decoded here even with a worker pool: the caret moves below the picture
+            // and its height is only known once the data is decoded - the raster attributes don't limit it.
+            // Shared color registers need the colors of the previous picture, so they're decoded in order as well.
+            let mut private_palette = Palette::default();
+            let palette = if buf.terminal_state.sixel_private_color_registers {
+                &mut private_palette
+            } else {
+                &mut self.sixel_palette
+            };
+            let sixel = Sixel::parse_with_palette(
+                p,
+                vertical_scale,
+                1,
+                bg_color,
+                palette,
+                &dcs_string[i + 1..],
+            )?;
+            buf.finish_sixels()?;
+            let line_count = buf.get_sixel_line_count(&sixel);
+            buf.add_sixel(sixel);
 
+            if !buf.terminal_state.sixel_display_mode {
+                // sixel scrolling: text continues on the line below the picture
+                for _ in 0..line_count {
+                    caret.index(buf);
+                }
+                caret.pos.x = p.x;
+            }
             return Ok(CallbackAction::None);
         }
 
@@ -0,0 +1,119 @@
+use crate::{BitFont, Buffer, CallbackAction, EngineResult, ParserError, SauceString};
+
+use super::Parser;
+
+/// Soft fonts are stored after the 256 slots the font selection sequences can address.
+pub(super) const DRCS_FONT_SLOT: usize = 256;
+
+impl Parser {
+    /// Loads a soft character set, `params` are the DCS parameters, `data` starts after the `{`.
+    /// `DCS Pfn ; Pcn ; Pe ; Pcmw ; Pss ; Pt ; Pcmh ; Pcss { Dscs Sxbp1 ; Sxbp2 ; ... ST`
+    ///
+    /// The glyphs are placed in a font of the current cell size, larger character
+    /// matrices are clipped at the cell (max. 8 pixels wide).
+    /// See <https://vt100.net/docs/vt510-rm/DECDLD.html>
+    pub(super) fn load_soft_font(
+        &mut self,
+        buf: &mut Buffer,
+        params: &str,
+        data: &str,
+    ) -> EngineResult<CallbackAction> {
+        let params: Vec<i32> = params
+            .split(';')
+            .map(|p| p.parse::<i32>().unwrap_or(0))
+            .collect();
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+
+        let cell_size = buf.get_font_dimensions();
+        let matrix_width = match param(3) {
+            0 => cell_size.width as usize,
+            // VT220 5x10, 6x10 & 7x10 matrices
+            2..=4 => param(3) as usize + 3,
+            w @ 5..=15 => w as usize,
+            _ => return unsupported_soft_font(data),
+        };
+        let matrix_height = match param(6) {
+            0 => cell_size.height as usize,
+            h @ 1..=16 => h as usize,
+            _ => return unsupported_soft_font(data),
+        };
+        let first_char = 0x20 + param(1).clamp(0, 95) as usize;
+
+        let Some(dscs_len) = data
+            .find(|ch: char| ('\x30'..='\x7E').contains(&ch))
+            .map(|i| i + 1)
+        else {
+            return unsupported_soft_font(data);
+        };
+        let dscs = data[..dscs_len].to_string();
+        if dscs_len > 2
+            || data[..dscs_len - 1]
+                .chars()
+                .any(|ch| !(' '..='/').contains(&ch))
+        {
+            return unsupported_soft_font(data);
+        }
+
+        // Pe: 0 erases the set, 1 only the loaded chars, 2 all soft sets
+        if param(2) == 2 {
+            for (_, slot) in self.soft_fonts.drain() {
+                buf.remove_font(slot);
+            }
+        }
+        let slot = match self.soft_fonts.get(&dscs) {
+            Some(slot) => *slot,
+            None => (DRCS_FONT_SLOT..=DRCS_FONT_SLOT + self.soft_fonts.len())
+                .find(|slot| !self.soft_fonts.values().any(|s| s == slot))
+                .unwrap_or(DRCS_FONT_SLOT),
+        };
+        let mut font = match buf.get_font(slot) {
+            Some(font) if param(2) == 1 && font.size == cell_size => font.clone(),
+            _ => {
+                let height = cell_size.height as usize;
+                let mut font =
+                    BitFont::from_basic(cell_size.width, cell_size.height, &vec![0; 256 * height]);
+                font.name = SauceString::from(format!("DRCS {dscs}"));
+                font
+            }
+        };
+
+        let width = matrix_width.min(cell_size.width as usize).min(8);
+        let height = matrix_height.min(cell_size.height as usize);
+        for (i, sixels) in data[dscs_len..].split(';').enumerate() {
+            let code = first_char + i;
+            if code > 0x7F {
+                break;
+            }
+            let mut rows = vec![0u8; cell_size.height as usize];
+            for (band, columns) in sixels.split('/').enumerate() {
+                let mut x = 0;
+                for ch in columns.chars() {
+                    if !('?'..='~').contains(&ch) {
+                        continue;
+                    }
+                    let bits = ch as u8 - b'?';
+                    for bit in 0..6 {
+                        let y = band * 6 + bit;
+                        if bits & (1 << bit) != 0 && x < width && y < height {
+                            rows[y] |= 0x80 >> x;
+                        }
+                    }
+                    x += 1;
+                }
+            }
+            if let Some(glyph) = font.get_glyph_mut(char::from(code as u8)) {
+                glyph.data = rows;
+            }
+        }
+
+        buf.set_font(slot, font);
+        self.soft_fonts.insert(dscs, slot);
+        Ok(CallbackAction::None)
+    }
+}
+
+fn unsupported_soft_font(data: &str) -> EngineResult<CallbackAction> {
+    Err(Box::new(ParserError::UnsupportedDCSSequence(format!(
+        "invalid soft font in dcs: {data}"
+    ))))
+}
@@ -0,0 +1,436 @@
+use base64::{engine::general_purpose, Engine};
+
+use crate::{
+    Buffer, CallbackAction, Caret, EngineResult, KittyImage, KittyPlacement, ParserError, Position,
+    MAX_KITTY_PLACEMENT_CELLS,
+};
+
+use super::Parser;
+
+/// The keys of a kitty graphics command, keys that weren't sent are 0.
+#[derive(Clone, Debug, Default)]
+pub(super) struct KittyCommand {
+    /// `a`: t(ransmit), T(ransmit & display), q(uery), p(ut), d(elete)
+    action: char,
+    /// `q`: 1 suppresses OK responses, 2 errors as well
+    quiet: u32,
+    /// `f`: 24 RGB, 32 RGBA, 100 PNG
+    format: u32,
+    /// `t`: only d(irect) is supported
+    medium: char,
+    /// `o`: z(lib), not supported
+    compression: char,
+    /// `s`, `v`: size of RGB & RGBA data in pixels
+    width: u32,
+    height: u32,
+    /// `i`, `I`, `p`
+    image_id: u32,
+    image_number: u32,
+    placement_id: u32,
+    /// `m`: 1 if more chunks follow
+    more: u32,
+    /// `x`, `y`, `w`, `h`, `X`, `Y`, `c`, `r`, `z`, see [`KittyPlacement`]
+    placement: KittyPlacement,
+    /// `C`: 1 doesn't move the cursor after placing the image
+    cursor_movement: u32,
+    /// `d`: what to delete, upper case frees the images as well
+    delete: char,
+}
+
+impl KittyCommand {
+    fn parse(control: &str) -> Result<Self, String> {
+        let mut cmd = KittyCommand {
+            action: 't',
+            format: 32,
+            medium: 'd',
+            delete: 'a',
+            ..Default::default()
+        };
+        for pair in control.split(',').filter(|pair| !pair.is_empty()) {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("EINVAL:invalid key {pair}"));
+            };
+            let invalid = || format!("EINVAL:invalid value for {key}: {value}");
+            let char_value = || {
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => Ok(ch),
+                    _ => Err(invalid()),
+                }
+            };
+            let number = || value.parse::<u32>().map_err(|_| invalid());
+            let signed = || value.parse::<i32>().map_err(|_| invalid());
+            match key {
+                "a" => cmd.action = char_value()?,
+                "q" => cmd.quiet = number()?,
+                "f" => cmd.format = number()?,
+                "t" => cmd.medium = char_value()?,
+                "o" => cmd.compression = char_value()?,
+                "s" => cmd.width = number()?,
+                "v" => cmd.height = number()?,
+                "i" => cmd.image_id = number()?,
+                "I" => cmd.image_number = number()?,
+                "p" => cmd.placement_id = number()?,
+                "m" => cmd.more = number()?,
+                "x" => cmd.placement.source_x = number()?,
+                "y" => cmd.placement.source_y = number()?,
+                "w" => cmd.placement.source_width = number()?,
+                "h" => cmd.placement.source_height = number()?,
+                "X" => cmd.placement.cell_offset.x = signed()?,
+                "Y" => cmd.placement.cell_offset.y = signed()?,
+                "c" => cmd.placement.columns = number()?.min(MAX_KITTY_PLACEMENT_CELLS),
+                "r" => cmd.placement.rows = number()?.min(MAX_KITTY_PLACEMENT_CELLS),
+                "z" => cmd.placement.z_index = signed()?,
+                "C" => cmd.cursor_movement = number()?,
+                "d" => cmd.delete = char_value()?,
+                // animation, unicode placeholders & relative placements
+                _ => {}
+            }
+        }
+        Ok(cmd)
+    }
+
+    /// Formats a response, `None` if the client didn't ask for one.
+    fn response(&self, message: &str) -> Option<String> {
+        let is_ok = message == "OK";
+        if self.image_id == 0 && self.image_number == 0
+            || self.quiet >= 2
+            || self.quiet == 1 && is_ok
+        {
+            return None;
+        }
+        let mut keys = Vec::new();
+        if self.image_id != 0 {
+            keys.push(format!("i={}", self.image_id));
+        }
+        if self.image_number != 0 {
+            keys.push(format!("I={}", self.image_number));
+        }
+        if self.placement_id != 0 {
+            keys.push(format!("p={}", self.placement_id));
+        }
+        Some(format!("\x1B_G{};{message}\x1B\\", keys.join(",")))
+    }
+}
+
+impl Parser {
+    /// Executes an APC string, only kitty graphics commands (`APC G ... ST`) are supported,
+    /// other strings are ignored.
+    pub(super) fn execute_aps_command(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+    ) -> EngineResult<CallbackAction> {
+        let Some(command) = self.aps_string.strip_prefix('G') else {
+            return Ok(CallbackAction::None);
+        };
+        let command = command.to_string();
+        self.execute_kitty_graphics(buf, caret, &command)
+    }
+
+    /// Executes a kitty graphics command `control keys;payload`.
+    /// Errors are reported to the client, only malformed chunks are returned as parser errors.
+    /// See <https://sw.kovidgoyal.net/kitty/graphics-protocol/>
+    fn execute_kitty_graphics(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        command: &str,
+    ) -> EngineResult<CallbackAction> {
+        let (control, payload) = command.split_once(';').unwrap_or((command, ""));
+        let cmd = match KittyCommand::parse(control) {
+            Ok(cmd) => cmd,
+            Err(err) => {
+                self.kitty_transmission = None;
+                return Err(Box::new(ParserError::Error(format!(
+                    "kitty graphics: {err}"
+                ))));
+            }
+        };
+
+        // chunks after the first one only carry m & q, the first chunk has the other keys
+        let (mut cmd, payload) = match self.kitty_transmission.take() {
+            Some((first, None)) => {
+                // the transmission was too large, the remaining chunks are dropped
+                if cmd.more == 1 {
+                    self.kitty_transmission = Some((first, None));
+                }
+                return Ok(CallbackAction::None);
+            }
+            Some((mut first, Some(mut data))) => {
+                data.push_str(payload);
+                if exceeds_quota(buf, &data) {
+                    if cmd.more == 1 {
+                        self.kitty_transmission = Some((first.clone(), None));
+                    }
+                    return Ok(respond(&first, "EFBIG:image exceeds the quota"));
+                }
+                if cmd.more == 1 {
+                    self.kitty_transmission = Some((first, Some(data)));
+                    return Ok(CallbackAction::None);
+                }
+                first.more = 0;
+                (first, data)
+            }
+            None if exceeds_quota(buf, payload) => {
+                if cmd.more == 1 {
+                    self.kitty_transmission = Some((cmd.clone(), None));
+                }
+                return Ok(respond(&cmd, "EFBIG:image exceeds the quota"));
+            }
+            None if cmd.more == 1 && matches!(cmd.action, 't' | 'T' | 'q') => {
+                self.kitty_transmission = Some((cmd, Some(payload.to_string())));
+                return Ok(CallbackAction::None);
+            }
+            None => (cmd, payload.to_string()),
+        };
+
+        let result = match cmd.action {
+            't' | 'T' | 'q' => transmit(buf, caret, &cmd, &payload),
+            'p' => put(buf, caret, &cmd),
+            'd' => {
+                delete(buf, caret, &cmd);
+                // deletions don't answer
+                return Ok(CallbackAction::None);
+            }
+            action => Err(format!("EINVAL:unsupported action {action}")),
+        };
+        let message = match result {
+            Ok(image_id) => {
+                // the client learns the id the terminal assigned to an image number
+                if cmd.image_number != 0 {
+                    cmd.image_id = image_id;
+                }
+                "OK".to_string()
+            }
+            Err(err) => err,
+        };
+        Ok(respond(&cmd, &message))
+    }
+}
+
+fn respond(cmd: &KittyCommand, message: &str) -> CallbackAction {
+    match cmd.response(message) {
+        Some(response) => CallbackAction::SendString(response),
+        None => CallbackAction::None,
+    }
+}
+
+/// Returns `true` if the base64 payload decodes to more bytes than the images may use.
+fn exceeds_quota(buf: &Buffer, payload: &str) -> bool {
+    payload.len() / 4 * 3 > buf.get_kitty_image_quota()
+}
+
+/// Loads the image of a transmit (`t`, `T`) or query (`q`) command, `T` places it as well.
+/// Gives back the id of the image.
+fn transmit(
+    buf: &mut Buffer,
+    caret: &mut Caret,
+    cmd: &KittyCommand,
+    payload: &str,
+) -> Result<u32, String> {
+    if cmd.image_id != 0 && cmd.image_number != 0 {
+        return Err("EINVAL:image id and number are mutually exclusive".to_string());
+    }
+    if cmd.medium != 'd' {
+        return Err(format!(
+            "EINVAL:unsupported transmission medium {}",
+            cmd.medium
+        ));
+    }
+    if cmd.compression != '\0' {
+        return Err(format!(
+            "EINVAL:unsupported compression {}",
+            cmd.compression
+        ));
+    }
+    let data = general_purpose::STANDARD_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|err| format!("EINVAL:invalid base64 data: {err}"))?;
+    let (width, height, pixels) = match cmd.format {
+        24 | 32 => {
+            let bytes_per_pixel = cmd.format as usize / 8;
+            let size = (cmd.width as usize)
+                .checked_mul(cmd.height as usize)
+                .and_then(|size| size.checked_mul(bytes_per_pixel));
+            if cmd.width == 0 || cmd.height == 0 || size != Some(data.len()) {
+                return Err(format!(
+                    "ENODATA:expected {}x{} pixels but got {} bytes",
+                    cmd.width,
+                    cmd.height,
+                    data.len()
+                ));
+            }
+            let pixels = if bytes_per_pixel == 3 {
+                data.chunks_exact(3)
+                    .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF])
+                    .collect()
+            } else {
+                data
+            };
+            (cmd.width, cmd.height, pixels)
+        }
+        100 => decode_png(&data).map_err(|err| format!("EBADPNG:{err}"))?,
+        format => return Err(format!("EINVAL:unsupported format {format}")),
+    };
+    if cmd.action == 'q' {
+        return Ok(cmd.image_id);
+    }
+
+    let id = if cmd.image_id == 0 {
+        buf.get_free_kitty_image_id()
+    } else {
+        cmd.image_id
+    };
+    let mut image = KittyImage::new(id, width, height, pixels);
+    image.number = cmd.image_number;
+    buf.add_kitty_image(image);
+    if cmd.action == 'T' {
+        place(buf, caret, cmd, id);
+    }
+    Ok(id)
+}
+
+/// Decodes a PNG to RGBA.
+fn decode_png(data: &[u8]) -> EngineResult<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let buffer = &buffer[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buffer.to_vec(),
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|g| [*g, *g, *g, 0xFF]).collect(),
+        png::ColorType::Indexed => return Err("indexed colors weren't expanded".into()),
+    };
+    Ok((info.width, info.height, pixels))
+}
+
+/// Places an image that was transmitted before (`p`), gives back its id.
+fn put(buf: &mut Buffer, caret: &mut Caret, cmd: &KittyCommand) -> Result<u32, String> {
+    if cmd.image_id == 0 && cmd.image_number == 0 {
+        return Err("EINVAL:image id or number required".to_string());
+    }
+    let image = if cmd.image_id != 0 {
+        buf.get_kitty_image(cmd.image_id)
+    } else {
+        buf.get_kitty_image_by_number(cmd.image_number)
+    };
+    let Some(image) = image else {
+        return Err("ENOENT:image not found".to_string());
+    };
+    let id = image.id;
+    place(buf, caret, cmd, id);
+    Ok(id)
+}
+
+/// Places an image at the caret, the caret moves to the cell after the image on its last line.
+fn place(buf: &mut Buffer, caret: &mut Caret, cmd: &KittyCommand, image_id: u32) {
+    let font_size = buf.get_font_dimensions();
+    let mut placement = cmd.placement;
+    placement.image_id = image_id;
+    placement.placement_id = cmd.placement_id;
+    placement.position = caret.get_position();
+    placement.cell_offset.x = placement
+        .cell_offset
+        .x
+        .clamp(0, i32::from(font_size.width) - 1);
+    placement.cell_offset.y = placement
+        .cell_offset
+        .y
+        .clamp(0, i32::from(font_size.height) - 1);
+    let cells = buf.get_kitty_placement_cells(&placement);
+    buf.add_kitty_placement(placement);
+
+    if cmd.cursor_movement != 1 {
+        // the caret can't move further than a screen
+        for _ in 1..cells.size.height.min(buf.get_buffer_height()) {
+            caret.index(buf);
+        }
+        caret.pos.x = (placement.position.x + cells.size.width).min(buf.get_buffer_width() - 1);
+    }
+}
+
+/// Deletes placements (`d`), upper case variants free the images as well.
+fn delete(buf: &mut Buffer, caret: &Caret, cmd: &KittyCommand) {
+    let free_images = cmd.delete.is_ascii_uppercase();
+    let first_line = buf.get_first_visible_line();
+    let caret_pos = caret.get_position();
+    // deletions use x & y for 1 based cell positions on the screen
+    let pos = Position::new(
+        cmd.placement.source_x as i32 - 1,
+        first_line + cmd.placement.source_y as i32 - 1,
+    );
+    let covers = |buf: &Buffer, placement: &KittyPlacement, pos: Position| {
+        let cells = buf.get_kitty_placement_cells(placement);
+        (cells.start.x..cells.start.x + cells.size.width).contains(&pos.x)
+            && (cells.start.y..cells.start.y + cells.size.height).contains(&pos.y)
+    };
+    match cmd.delete.to_ascii_lowercase() {
+        'a' => buf.delete_kitty_placements(|_, _| true, free_images),
+        'i' => {
+            buf.delete_kitty_placements(
+                |_, p| {
+                    p.image_id == cmd.image_id
+                        && (cmd.placement_id == 0 || p.placement_id == cmd.placement_id)
+                },
+                free_images,
+            );
+            if free_images {
+                buf.free_kitty_image(cmd.image_id);
+            }
+        }
+        'n' => {
+            if cmd.image_number == 0 {
+                return;
+            }
+            let Some(id) = buf
+                .get_kitty_image_by_number(cmd.image_number)
+                .map(|image| image.id)
+            else {
+                return;
+            };
+            buf.delete_kitty_placements(
+                |_, p| {
+                    p.image_id == id
+                        && (cmd.placement_id == 0 || p.placement_id == cmd.placement_id)
+                },
+                free_images,
+            );
+            if free_images {
+                buf.free_kitty_image(id);
+            }
+        }
+        'c' => buf.delete_kitty_placements(|buf, p| covers(buf, p, caret_pos), free_images),
+        'p' => buf.delete_kitty_placements(|buf, p| covers(buf, p, pos), free_images),
+        'q' => buf.delete_kitty_placements(
+            |buf, p| covers(buf, p, pos) && p.z_index == cmd.placement.z_index,
+            free_images,
+        ),
+        'x' => buf.delete_kitty_placements(
+            |buf, p| {
+                let cells = buf.get_kitty_placement_cells(p);
+                (cells.start.x..cells.start.x + cells.size.width).contains(&pos.x)
+            },
+            free_images,
+        ),
+        'y' => buf.delete_kitty_placements(
+            |buf, p| {
+                let cells = buf.get_kitty_placement_cells(p);
+                (cells.start.y..cells.start.y + cells.size.height).contains(&pos.y)
+            },
+            free_images,
+        ),
+        'z' => buf.delete_kitty_placements(|_, p| p.z_index == cmd.placement.z_index, free_images),
+        _ => {}
+    }
+}
@@ -11,12 +11,20 @@ use self::constants::{ANSI_FONT_NAMES, COLOR_OFFSETS};
 use super::{ascii, BufferParser};
 use crate::{
     update_crc16, AnsiMusic, AttributedChar, AutoWrapMode, BitFont, Buffer, CallbackAction, Caret,
-    EngineResult, FontSelectionState, MouseMode, MusicAction, MusicStyle, OriginMode, ParserError,
-    Position, TerminalScrolling, TextAttribute, BEL, BS, CR, FF, LF, XTERM_256_PALETTE,
+    EngineResult, FontSelectionState, LineAttribute, MouseEncoding, MouseMode, MusicAction,
+    MusicStyle, OriginMode, Palette, ParserError, Position, TerminalScrolling, TextAttribute, BEL,
+    BS, CR, FF, LF, XTERM_256_PALETTE,
 };
 
+mod charset;
+pub use charset::*;
 mod constants;
 mod dcs;
+mod drcs;
+mod kitty;
+mod osc;
+mod utf8;
+pub use utf8::*;
 
 #[cfg(test)]
 mod sixel_tests;
@@ -51,6 +59,11 @@ pub enum EngineState {
     ParseAnsiMusic(MusicState),
 
     ReadAPS(ReadSTState),
+    ReadOSC(ReadSTState),
+    /// `ESC ( ) * +` designate G0-G3, with the intermediate char of the designator if read.
+    ReadCharsetDesignation(usize, Option<char>),
+    /// `ESC #` line attributes & screen alignment.
+    ReadHashCommand,
 }
 
 #[repr(u8)]
@@ -127,24 +140,37 @@ pub struct Parser {
     ascii_parser: ascii::Parser,
     pub(crate) state: EngineState,
     pub(crate) current_font_page: usize,
+    /// Link id of the open OSC 8 hyperlink, 0 if no link is open.
+    pub(crate) current_hyperlink: usize,
     saved_pos: Position,
     saved_cursor_opt: Option<Caret>,
     pub(crate) parsed_numbers: Vec<i32>,
 
     current_escape_sequence: String,
 
-    /*     current_sixel_color: i32,
-        sixel_cursor: Position,
-        current_sixel_palette: Palette,
-    */
+    /// Color registers shared by the sixels if private color registers are off (1070).
+    sixel_palette: Palette,
     pub ansi_music: MusicOption,
+    pub input_encoding: InputEncoding,
+    /// Code point printed for UTF-8 input that is invalid or has no glyph in the font.
+    pub replacement_char: char,
+    utf8_decoder: Utf8Decoder,
+    charset_state: CharsetState,
     cur_music: Option<AnsiMusic>,
     cur_octave: usize,
     cur_length: u32,
     cur_tempo: u32,
 
     last_char: char,
+    /// Font page of `last_char`, soft character sets use their own font.
+    last_font_page: usize,
+    /// Font slots of the DECDLD soft character sets by designator.
+    soft_fonts: HashMap<String, usize>,
+    /// First chunk of a kitty graphics transmission that continues and the payload received so far,
+    /// `None` if the payload exceeded the image quota and the remaining chunks are dropped.
+    kitty_transmission: Option<(kitty::KittyCommand, Option<String>)>,
     pub aps_string: String,
+    pub osc_string: String,
     pub(crate) macros: HashMap<usize, String>,
     pub dcs_string: String,
 }
@@ -154,20 +180,30 @@ impl Default for Parser {
         Parser {
             ascii_parser: ascii::Parser::default(),
             current_font_page: 0,
+            current_hyperlink: 0,
             state: EngineState::Default,
             saved_pos: Position::default(),
             parsed_numbers: Vec::new(),
             current_escape_sequence: String::new(),
             saved_cursor_opt: None,
             ansi_music: MusicOption::Off,
+            input_encoding: InputEncoding::default(),
+            replacement_char: '?',
+            utf8_decoder: Utf8Decoder::default(),
+            charset_state: CharsetState::default(),
             cur_music: None,
             cur_octave: 3,
             cur_length: 4,
             cur_tempo: 120,
             aps_string: String::new(),
+            osc_string: String::new(),
             macros: HashMap::new(),
             dcs_string: String::new(),
             last_char: '\0',
+            last_font_page: 0,
+            soft_fonts: HashMap::new(),
+            kitty_transmission: None,
+            sixel_palette: Palette::default(),
         }
     }
 }
@@ -181,12 +217,55 @@ impl BufferParser for Parser {
         self.ascii_parser.convert_to_unicode(ch)
     }
 
-    #[allow(clippy::single_match)]
+    /// In UTF-8 mode `ch` is one byte of the input, chars above `0xFF` are taken as decoded.
     fn print_char(
         &mut self,
         buf: &mut Buffer,
         caret: &mut Caret,
         ch: char,
+    ) -> EngineResult<CallbackAction> {
+        if self.input_encoding == InputEncoding::CP437 {
+            return self.print_ansi_char(buf, caret, ch);
+        }
+        let Ok(byte) = u8::try_from(ch) else {
+            return self.print_decoded_char(buf, caret, ch);
+        };
+        let mut action = CallbackAction::None;
+        for decoded in self.utf8_decoder.decode(byte).into_iter().flatten() {
+            action = self.print_decoded_char(buf, caret, decoded)?;
+        }
+        Ok(action)
+    }
+
+    fn print_bytes(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        data: &[u8],
+    ) -> EngineResult<Vec<CallbackAction>> {
+        let mut actions = Vec::new();
+        for b in data {
+            if matches!(self.state, EngineState::Default)
+                && is_text_byte(*b)
+                && (self.input_encoding == InputEncoding::CP437
+                    || *b < 0x80 && !self.utf8_decoder.is_pending())
+            {
+                self.print_text_char(buf, caret, char::from(*b));
+                continue;
+            }
+            super::push_action(&mut actions, self.print_char(buf, caret, char::from(*b))?);
+        }
+        Ok(actions)
+    }
+}
+
+impl Parser {
+    #[allow(clippy::single_match)]
+    fn print_ansi_char(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        ch: char,
     ) -> EngineResult<CallbackAction> {
         match &self.state {
             EngineState::ParseAnsiMusic(_) => {
@@ -219,6 +298,9 @@ impl BufferParser for Parser {
                             caret.ff(buf);
                             buf.terminal_state.reset();
                             self.macros.clear();
+                            self.charset_state = CharsetState::default();
+                            self.current_hyperlink = 0;
+                            buf.prune_hyperlinks(0);
                             Ok(CallbackAction::None)
                         }
 
@@ -260,6 +342,48 @@ impl BufferParser for Parser {
                             Ok(CallbackAction::None)
                         }
 
+                        ']' => {
+                            // Operating System Command
+                            self.state = EngineState::ReadOSC(ReadSTState::Default(0));
+                            self.osc_string.clear();
+                            Ok(CallbackAction::None)
+                        }
+
+                        '(' | ')' | '*' | '+' => {
+                            let g = match ch {
+                                '(' => 0,
+                                ')' => 1,
+                                '*' => 2,
+                                _ => 3,
+                            };
+                            self.state = EngineState::ReadCharsetDesignation(g, None);
+                            Ok(CallbackAction::None)
+                        }
+                        '#' => {
+                            self.state = EngineState::ReadHashCommand;
+                            Ok(CallbackAction::None)
+                        }
+                        '=' => {
+                            // DECKPAM - Keypad Application Mode
+                            buf.terminal_state.application_keypad = true;
+                            Ok(CallbackAction::None)
+                        }
+                        '>' => {
+                            // DECKPNM - Keypad Numeric Mode
+                            buf.terminal_state.application_keypad = false;
+                            Ok(CallbackAction::None)
+                        }
+                        'N' => {
+                            // SS2 - Single Shift 2
+                            self.charset_state.single_shift = Some(2);
+                            Ok(CallbackAction::None)
+                        }
+                        'O' => {
+                            // SS3 - Single Shift 3
+                            self.charset_state.single_shift = Some(3);
+                            Ok(CallbackAction::None)
+                        }
+
                         '0'..='~' => {
                             // Silently drop unsupported sequences
                             self.state = EngineState::Default;
@@ -283,14 +407,88 @@ impl BufferParser for Parser {
                 ReadSTState::GotEscape(nesting_level) => {
                     if ch == '\\' {
                         self.state = EngineState::Default;
-                        self.execute_aps_command(buf, caret);
-                        return Ok(CallbackAction::None);
+                        return self.execute_aps_command(buf, caret);
                     }
                     self.state = EngineState::ReadAPS(ReadSTState::Default(*nesting_level));
                     self.aps_string.push('\x1B');
                     self.aps_string.push(ch);
                 }
             },
+            EngineState::ReadOSC(st_state) => match st_state {
+                ReadSTState::Default(nesting_level) => match ch {
+                    BEL => {
+                        self.state = EngineState::Default;
+                        return self.execute_osc(buf, "\x07");
+                    }
+                    '\x1B' => {
+                        self.state = EngineState::ReadOSC(ReadSTState::GotEscape(*nesting_level));
+                        return Ok(CallbackAction::None);
+                    }
+                    _ => self.osc_string.push(ch),
+                },
+                ReadSTState::GotEscape(nesting_level) => {
+                    if ch == '\\' {
+                        self.state = EngineState::Default;
+                        return self.execute_osc(buf, "\x1B\\");
+                    }
+                    self.state = EngineState::ReadOSC(ReadSTState::Default(*nesting_level));
+                    self.osc_string.push('\x1B');
+                    self.osc_string.push(ch);
+                }
+            },
+            EngineState::ReadCharsetDesignation(g, intermediate) => {
+                let g = *g;
+                if ('\x20'..='\x2F').contains(&ch) && intermediate.is_none() {
+                    self.state = EngineState::ReadCharsetDesignation(g, Some(ch));
+                    return Ok(CallbackAction::None);
+                }
+                let designator = match intermediate {
+                    Some(intermediate) => format!("{intermediate}{ch}"),
+                    None => ch.to_string(),
+                };
+                self.state = EngineState::Default;
+                // soft fonts take precedence, unknown sets are ignored like other terminals do
+                if let Some(slot) = self.soft_fonts.get(&designator) {
+                    self.charset_state.designate(g, CharacterSet::Drcs(*slot));
+                } else if let Some(charset) = CharacterSet::from_designator(&designator) {
+                    self.charset_state.designate(g, charset);
+                }
+            }
+            EngineState::ReadHashCommand => {
+                self.state = EngineState::Default;
+                let line_attribute = match ch {
+                    '3' => LineAttribute::DoubleHeightTop,
+                    '4' => LineAttribute::DoubleHeightBottom,
+                    '5' => LineAttribute::Single,
+                    '6' => LineAttribute::DoubleWidth,
+                    '8' => {
+                        // DECALN - Screen Alignment Pattern
+                        buf.terminal_state.margins_up_down = None;
+                        buf.terminal_state.margins_left_right = None;
+                        let first_line = buf.get_first_visible_line();
+                        for y in first_line..buf.get_last_visible_line() {
+                            buf.set_line_attribute(y, LineAttribute::Single);
+                            for x in 0..buf.get_buffer_width() {
+                                buf.set_char(
+                                    0,
+                                    Position::new(x, y),
+                                    Some(AttributedChar::new('E', TextAttribute::default())),
+                                );
+                            }
+                        }
+                        caret.home(buf);
+                        return Ok(CallbackAction::None);
+                    }
+                    _ => {
+                        self.current_escape_sequence.push(ch);
+                        return Err(Box::new(ParserError::UnsupportedEscapeSequence(
+                            self.current_escape_sequence.clone(),
+                        )));
+                    }
+                };
+                buf.set_line_attribute(caret.pos.y, line_attribute);
+                buf.terminal_state.limit_caret_pos(buf, caret);
+            }
             EngineState::ReadPossibleMacroInDCS(i) => {
                 // \x1B[<num>*z
                 // read macro inside dcs sequence, 3 states:´
@@ -402,6 +600,7 @@ impl BufferParser for Parser {
                             Some(6) => {
                                 //  buf.terminal_state.origin_mode = OriginMode::WithinMargins;
                             }
+                            Some(1) => buf.terminal_state.application_cursor_keys = false,
                             Some(7) => buf.terminal_state.auto_wrap_mode = AutoWrapMode::NoWrap,
                             Some(25) => caret.is_visible = false,
                             Some(33) => buf.terminal_state.set_use_ice_colors(false),
@@ -412,9 +611,33 @@ impl BufferParser for Parser {
                                 buf.terminal_state.margins_left_right = None;
                             }
 
-                            Some(9 | 1000..=1007 | 1015 | 1016) => {
+                            Some(9 | 1000..=1003) => {
                                 buf.terminal_state.mouse_mode = MouseMode::Default;
                             }
+                            Some(1004) => buf.terminal_state.focus_events = false,
+                            Some(1007) => buf.terminal_state.alternate_scroll = false,
+                            Some(80) => buf.terminal_state.sixel_display_mode = false,
+                            Some(1070) => {
+                                buf.terminal_state.sixel_private_color_registers = false;
+                            }
+                            Some(1005 | 1006 | 1015 | 1016) => {
+                                buf.terminal_state.mouse_encoding = MouseEncoding::Default;
+                            }
+
+                            Some(47) => {
+                                buf.switch_to_primary_screen();
+                            }
+                            Some(1047) => {
+                                if buf.is_alternate_screen() {
+                                    buf.clear();
+                                }
+                                buf.switch_to_primary_screen();
+                            }
+                            Some(1049) => {
+                                if let Some(saved_caret) = buf.switch_to_primary_screen() {
+                                    *caret = saved_caret;
+                                }
+                            }
                             _ => {
                                 return Err(Box::new(ParserError::UnsupportedEscapeSequence(
                                     self.current_escape_sequence.clone(),
@@ -432,6 +655,7 @@ impl BufferParser for Parser {
                         match self.parsed_numbers.first() {
                             Some(4) => buf.terminal_state.scroll_state = TerminalScrolling::Smooth,
                             Some(6) => buf.terminal_state.origin_mode = OriginMode::UpperLeftCorner,
+                            Some(1) => buf.terminal_state.application_cursor_keys = true,
                             Some(7) => buf.terminal_state.auto_wrap_mode = AutoWrapMode::AutoWrap,
                             Some(25) => caret.is_visible = true,
                             Some(33) => buf.terminal_state.set_use_ice_colors(true),
@@ -448,18 +672,27 @@ impl BufferParser for Parser {
                             Some(1002) => buf.terminal_state.mouse_mode = MouseMode::ButtonEvents,
                             Some(1003) => buf.terminal_state.mouse_mode = MouseMode::AnyEvents,
 
-                            Some(1004) => buf.terminal_state.mouse_mode = MouseMode::FocusEvent,
-                            Some(1007) => {
-                                buf.terminal_state.mouse_mode = MouseMode::AlternateScroll;
-                            }
-                            Some(1005) => buf.terminal_state.mouse_mode = MouseMode::ExtendedMode,
-                            Some(1006) => {
-                                buf.terminal_state.mouse_mode = MouseMode::SGRExtendedMode;
+                            Some(1004) => buf.terminal_state.focus_events = true,
+                            Some(1007) => buf.terminal_state.alternate_scroll = true,
+                            Some(80) => buf.terminal_state.sixel_display_mode = true,
+                            Some(1070) => {
+                                buf.terminal_state.sixel_private_color_registers = true;
                             }
+                            Some(1005) => buf.terminal_state.mouse_encoding = MouseEncoding::Utf8,
+                            Some(1006) => buf.terminal_state.mouse_encoding = MouseEncoding::SGR,
                             Some(1015) => {
-                                buf.terminal_state.mouse_mode = MouseMode::URXVTExtendedMode;
+                                buf.terminal_state.mouse_encoding = MouseEncoding::URXVT;
+                            }
+                            Some(1016) => {
+                                buf.terminal_state.mouse_encoding = MouseEncoding::SGRPixels;
+                            }
+
+                            // Alternate screen see https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-The-Alternate-Screen-Buffer
+                            Some(47 | 1047) => buf.switch_to_alternate_screen(caret),
+                            Some(1049) => {
+                                buf.switch_to_alternate_screen(caret);
+                                buf.clear();
                             }
-                            Some(1016) => buf.terminal_state.mouse_mode = MouseMode::PixelPosition,
 
                             Some(cmd) => {
                                 return Err(Box::new(ParserError::UnsupportedCustomCommand(*cmd)));
@@ -573,18 +806,26 @@ impl BufferParser for Parser {
                                     mode_report.push_str(";35");
                                 }
                                 match buf.terminal_state.mouse_mode {
-                                    MouseMode::Default => {}
                                     MouseMode::X10 => mode_report.push_str(";9"),
                                     MouseMode::VT200 => mode_report.push_str(";1000"),
                                     MouseMode::VT200_Highlight => mode_report.push_str(";1001"),
                                     MouseMode::ButtonEvents => mode_report.push_str(";1002"),
                                     MouseMode::AnyEvents => mode_report.push_str(";1003"),
-                                    MouseMode::FocusEvent => mode_report.push_str(";1004"),
-                                    MouseMode::AlternateScroll => mode_report.push_str(";1007"),
-                                    MouseMode::ExtendedMode => mode_report.push_str(";1005"),
-                                    MouseMode::SGRExtendedMode => mode_report.push_str(";1006"),
-                                    MouseMode::URXVTExtendedMode => mode_report.push_str(";1015"),
-                                    MouseMode::PixelPosition => mode_report.push_str(";1016"),
+                                    // Default reports nothing, the deprecated modes are never set
+                                    _ => {}
+                                }
+                                if buf.terminal_state.focus_events {
+                                    mode_report.push_str(";1004");
+                                }
+                                match buf.terminal_state.mouse_encoding {
+                                    MouseEncoding::Default => {}
+                                    MouseEncoding::Utf8 => mode_report.push_str(";1005"),
+                                    MouseEncoding::SGR => mode_report.push_str(";1006"),
+                                    MouseEncoding::URXVT => mode_report.push_str(";1015"),
+                                    MouseEncoding::SGRPixels => mode_report.push_str(";1016"),
+                                }
+                                if buf.terminal_state.alternate_scroll {
+                                    mode_report.push_str(";1007");
                                 }
 
                                 if mode_report.len() == "\x1B[=2".len() {
@@ -638,13 +879,14 @@ impl BufferParser for Parser {
                         self.state = EngineState::Default;
                         buf.terminal_state.reset();
                         caret.reset();
+                        self.charset_state = CharsetState::default();
                         return Ok(CallbackAction::None);
                     }
                     _ => {
                         // potential rip support request
                         // ignore that for now and continue parsing
                         self.state = EngineState::Default;
-                        return self.print_char(buf, caret, ch);
+                        return self.print_ansi_char(buf, caret, ch);
                     }
                 }
             }
@@ -1323,9 +1565,15 @@ impl BufferParser for Parser {
                                 1 => {
                                     buf.clear_buffer_up(caret);
                                 }
-                                2 |  // clear entire screen
-                                3 => {
+                                2 => {
+                                    // clear entire screen
                                     buf.clear_screen(caret);
+                                    buf.prune_hyperlinks(self.current_hyperlink);
+                                }
+                                3 => {
+                                    // erase saved lines
+                                    buf.scrollback.clear();
+                                    buf.prune_hyperlinks(self.current_hyperlink);
                                 }
                                 _ => {
                                     buf.clear_buffer_down(caret);
@@ -1420,7 +1668,7 @@ impl BufferParser for Parser {
                         let (start, end) = match self.parsed_numbers.len() {
                             2 => (self.parsed_numbers[0] - 1, self.parsed_numbers[1] - 1),
                             1 => (0, self.parsed_numbers[0] - 1),
-                            0 => (0, buf.terminal_state.height),
+                            0 => (0, buf.terminal_state.height - 1),
                             _ => {
                                 return Err(Box::new(ParserError::UnsupportedEscapeSequence(
                                     self.current_escape_sequence.clone(),
@@ -1557,7 +1805,8 @@ impl BufferParser for Parser {
                             1
                         };
                         let mut ch = AttributedChar::new(self.last_char, caret.attr);
-                        ch.set_font_page(self.current_font_page);
+                        ch.set_font_page(self.last_font_page);
+                        ch.set_hyperlink_id(self.current_hyperlink);
                         (0..num).for_each(|_| buf.print_char(caret, ch));
                     }
                     'g' => {
@@ -1664,20 +1913,50 @@ impl BufferParser for Parser {
                 BS => caret.bs(buf),
                 BEL => return Ok(CallbackAction::Beep),
                 '\x7F' => caret.del(buf),
-                _ => {
-                    self.last_char = unsafe { char::from_u32_unchecked(ch as u32) };
-                    let mut ch = AttributedChar::new(self.last_char, caret.attr);
-                    ch.set_font_page(self.current_font_page);
-                    buf.print_char(caret, ch);
-                }
+                // SO - Shift Out
+                '\x0E' if self.charset_state.g1_designated => self.charset_state.gl = 1,
+                // SI - Shift In
+                '\x0F' if self.charset_state.g1_designated => self.charset_state.gl = 0,
+                _ => self.print_text_char(buf, caret, ch),
             },
         }
 
         Ok(CallbackAction::None)
     }
+
+    /// Prints a char decoded from the UTF-8 input. Non ASCII text is mapped to the font,
+    /// inside of control sequences it's kept as it is.
+    fn print_decoded_char(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        ch: char,
+    ) -> EngineResult<CallbackAction> {
+        if ch.is_ascii() || !matches!(self.state, EngineState::Default) {
+            return self.print_ansi_char(buf, caret, ch);
+        }
+        let ch = self.map_unicode_char(buf, ch);
+        self.print_text_char(buf, caret, ch);
+        Ok(CallbackAction::None)
+    }
+}
+
+/// Bytes that are printed as they are in the default state.
+fn is_text_byte(b: u8) -> bool {
+    b >= b' ' && b != 0x7F && b != 0xFF
 }
 
 impl Parser {
+    fn print_text_char(&mut self, buf: &mut Buffer, caret: &mut Caret, ch: char) {
+        let (ch, font_page) = self.charset_state.map_char(ch);
+        self.last_char = ch;
+        self.last_font_page = font_page.unwrap_or(self.current_font_page);
+        let mut ch = AttributedChar::new(ch, caret.attr);
+        ch.set_font_page(self.last_font_page);
+        ch.set_hyperlink_id(self.current_hyperlink);
+        buf.print_char(caret, ch);
+    }
+
     fn parse_extended_colors(&mut self, buf: &mut Buffer, i: &mut usize) -> EngineResult<u32> {
         if *i + 1 >= self.parsed_numbers.len() {
             return Err(Box::new(ParserError::UnsupportedEscapeSequence(
@@ -1924,14 +2203,14 @@ impl Parser {
             return Ok(CallbackAction::None);
         };
         for ch in m.chars() {
-            self.print_char(buf, caret, ch)?;
+            if self.input_encoding == InputEncoding::CP437 {
+                self.print_ansi_char(buf, caret, ch)?;
+            } else {
+                self.print_decoded_char(buf, caret, ch)?;
+            }
         }
         Ok(CallbackAction::None)
     }
-
-    fn execute_aps_command(&self, _buf: &mut Buffer, _caret: &mut Caret) {
-        println!("TODO execute APS command: {}", self.aps_string);
-    }
 }
 
 fn set_font_selection_success(buf: &mut Buffer, caret: &Caret, slot: usize) {
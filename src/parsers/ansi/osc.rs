@@ -0,0 +1,187 @@
+use std::fmt::Write;
+
+use base64::{engine::general_purpose, Engine};
+
+use crate::{
+    Buffer, CallbackAction, Color, EngineResult, HyperLink, ParserError, DOS_DEFAULT_PALETTE,
+    XTERM_256_PALETTE,
+};
+
+use super::{constants::COLOR_OFFSETS, Parser};
+
+impl Parser {
+    /// Executes an OSC string, `terminator` is the string terminator used by the host
+    /// so replies can use the same one.
+    /// See <https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands>
+    pub(super) fn execute_osc(
+        &mut self,
+        buf: &mut Buffer,
+        terminator: &str,
+    ) -> EngineResult<CallbackAction> {
+        let osc_string = std::mem::take(&mut self.osc_string);
+        let (command, data) = osc_string.split_once(';').unwrap_or((&osc_string, ""));
+        match command {
+            "0" | "2" => Ok(CallbackAction::ChangeWindowTitle(data.to_string())),
+            "1" => Ok(CallbackAction::ChangeIconName(data.to_string())),
+            "4" => {
+                let mut reply = String::new();
+                let mut params = data.split(';');
+                while let (Some(color), Some(spec)) = (params.next(), params.next()) {
+                    let Ok(color) = color.parse::<usize>() else {
+                        return unsupported_osc(&osc_string);
+                    };
+                    if spec == "?" {
+                        if let Some(rgb) = get_xterm_color(buf, color) {
+                            let _ = write!(reply, "\x1B]4;{color};{}{terminator}", format_rgb(rgb));
+                        }
+                        continue;
+                    }
+                    let Some(rgb) = parse_rgb(spec) else {
+                        return unsupported_osc(&osc_string);
+                    };
+                    // only the 16 ANSI colors have a fixed palette entry
+                    if let Some(index) = get_palette_index(color) {
+                        let (r, g, b) = rgb.get_rgb();
+                        buf.palette.set_color_rgb(index, r, g, b);
+                    }
+                }
+                if reply.is_empty() {
+                    Ok(CallbackAction::None)
+                } else {
+                    Ok(CallbackAction::SendString(reply))
+                }
+            }
+            "104" => {
+                if data.is_empty() {
+                    for (i, color) in DOS_DEFAULT_PALETTE.iter().enumerate() {
+                        let (r, g, b) = color.get_rgb();
+                        buf.palette.set_color_rgb(i, r, g, b);
+                    }
+                    return Ok(CallbackAction::None);
+                }
+                for color in data.split(';') {
+                    let Ok(color) = color.parse::<usize>() else {
+                        return unsupported_osc(&osc_string);
+                    };
+                    if let Some(index) = get_palette_index(color) {
+                        let (r, g, b) = DOS_DEFAULT_PALETTE[index].get_rgb();
+                        buf.palette.set_color_rgb(index, r, g, b);
+                    }
+                }
+                Ok(CallbackAction::None)
+            }
+            "10" | "11" => {
+                if data != "?" {
+                    return unsupported_osc(&osc_string);
+                }
+                // default foreground is light gray, default background black
+                let index = if command == "10" { 7 } else { 0 };
+                let color = buf.palette.colors.get(index).copied().unwrap_or_default();
+                Ok(CallbackAction::SendString(format!(
+                    "\x1B]{command};{}{terminator}",
+                    format_rgb(color)
+                )))
+            }
+            "8" => {
+                let Some((params, uri)) = data.split_once(';') else {
+                    return unsupported_osc(&osc_string);
+                };
+                self.current_hyperlink = if uri.is_empty() {
+                    0
+                } else {
+                    let id = params
+                        .split(':')
+                        .find_map(|param| param.strip_prefix("id="))
+                        .map(str::to_string);
+                    // links of chars that are gone are dropped from time to time
+                    if buf.hyperlinks.needs_prune() {
+                        buf.prune_hyperlinks(0);
+                    }
+                    buf.add_hyperlink(HyperLink {
+                        id,
+                        uri: uri.to_string(),
+                    })
+                };
+                Ok(CallbackAction::None)
+            }
+            "52" => {
+                let (_selection, data) = data.split_once(';').unwrap_or(("", data));
+                // reading the host clipboard isn't supported
+                if data == "?" {
+                    return Ok(CallbackAction::None);
+                }
+                match general_purpose::STANDARD.decode(data) {
+                    Ok(bytes) => Ok(CallbackAction::SetClipboard(
+                        String::from_utf8_lossy(&bytes).to_string(),
+                    )),
+                    Err(_) => unsupported_osc(&osc_string),
+                }
+            }
+            _ => unsupported_osc(&osc_string),
+        }
+    }
+}
+
+fn unsupported_osc(osc_string: &str) -> EngineResult<CallbackAction> {
+    Err(Box::new(ParserError::UnsupportedOSCSequence(
+        osc_string.to_string(),
+    )))
+}
+
+/// Translates an ANSI color number to the DOS palette order used by the buffer.
+fn get_palette_index(color: usize) -> Option<usize> {
+    match color {
+        0..=7 => Some(COLOR_OFFSETS[color] as usize),
+        8..=15 => Some(COLOR_OFFSETS[color - 8] as usize + 8),
+        _ => None,
+    }
+}
+
+fn get_xterm_color(buf: &Buffer, color: usize) -> Option<Color> {
+    match get_palette_index(color) {
+        Some(index) => buf.palette.colors.get(index).copied(),
+        None => XTERM_256_PALETTE.get(color).copied(),
+    }
+}
+
+fn format_rgb(color: Color) -> String {
+    let (r, g, b) = color.get_rgb();
+    format!(
+        "rgb:{:04x}/{:04x}/{:04x}",
+        r as u16 * 0x101,
+        g as u16 * 0x101,
+        b as u16 * 0x101
+    )
+}
+
+/// Parses `rgb:r/g/b` with 1-4 hex digits per component and `#rrggbb`.
+fn parse_rgb(spec: &str) -> Option<Color> {
+    if let Some(rgb) = spec.strip_prefix("rgb:") {
+        let mut components = [0u8; 3];
+        let mut parts = rgb.split('/');
+        for component in &mut components {
+            let part = parts.next()?;
+            if part.is_empty() || part.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(part, 16).ok()?;
+            let max = (1u32 << (4 * part.len())) - 1;
+            *component = (value * 255 / max) as u8;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        return Some(Color::new(components[0], components[1], components[2]));
+    }
+    if let Some(rgb) = spec.strip_prefix('#') {
+        if rgb.len() == 6 {
+            let value = u32::from_str_radix(rgb, 16).ok()?;
+            return Some(Color::new(
+                (value >> 16) as u8,
+                (value >> 8) as u8,
+                value as u8,
+            ));
+        }
+    }
+    None
+}
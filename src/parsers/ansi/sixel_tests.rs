@@ -3,12 +3,13 @@ use std::{thread, time::Duration};
 use crate::{
     ansi::Parser,
     parsers::{create_buffer, update_buffer},
-    Buffer, Position,
+    Buffer, BufferParser, Palette, ParserError, Position, Sixel, SixelDecoding,
+    MAX_SIXEL_COLOR_REGISTERS,
 };
 
 fn update_sixels(buf: &mut Buffer) {
-    while !buf.sixel_threads.is_empty() {
-        buf.update_sixel_threads();
+    while buf.has_pending_sixels() {
+        buf.poll_sixels().unwrap();
         thread::sleep(Duration::from_millis(10));
     }
 }
@@ -164,3 +165,342 @@ fn test_sixel_raster_attributes() {
     assert_eq!(6, buf.layers[0].sixels[0].width());
     assert_eq!(8, buf.layers[0].sixels[0].height());
 }
+
+#[test]
+fn test_sixel_max_picture_size() {
+    let too_large = |data: &str| {
+        let err = Sixel::parse_from(Position::default(), 1, 1, [0; 4], data).unwrap_err();
+        matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::InvalidPictureSize)
+        )
+    };
+    assert!(too_large("\"1;1;99999;99999"));
+    assert!(too_large("!99999~"));
+    assert!(too_large("!4097~"));
+    assert!(Sixel::parse_from(Position::default(), 1, 1, [0; 4], "!4096~").is_ok());
+}
+
+#[test]
+fn test_sixel_color_register_overflow() {
+    let sixel = Sixel::parse_from(
+        Position::default(),
+        1,
+        1,
+        [0; 4],
+        "#99999999999999999999;2;100;0;0~#1;2;0;100;0#99999999999999999999~",
+    )
+    .unwrap();
+    assert_eq!(2, sixel.width());
+}
+
+#[test]
+fn test_sixel_huge_color_register() {
+    let mut parser = Parser::default();
+    let (mut buf, _) = create_buffer(
+        &mut parser,
+        b"\x1B[?1070l\x1BPq#20000000;2;100;0;0#2147483647;1;0;50;100#20000000~\x1B\\",
+    );
+    update_sixels(&mut buf);
+    assert_eq!(1, buf.layers[0].sixels.len());
+    assert!(parser.sixel_palette.colors.len() <= MAX_SIXEL_COLOR_REGISTERS);
+}
+
+fn get_pixel(sixel: &Sixel, x: usize, y: usize) -> &[u8] {
+    let o = (y * sixel.width() as usize + x) * 4;
+    &sixel.picture_data[o..o + 4]
+}
+
+#[test]
+fn test_sixel_hls_colors() {
+    let (mut buf, _) = create_buffer(
+        &mut Parser::default(),
+        b"\x1BPq#1;1;120;50;100#2;1;240;50;100#3;1;0;50;100#4;1;0;50;0#1~#2~#3~#4~\x1B\\",
+    );
+    update_sixels(&mut buf);
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!([0xFF, 0, 0, 0xFF], get_pixel(sixel, 0, 0));
+    assert_eq!([0, 0xFF, 0, 0xFF], get_pixel(sixel, 1, 0));
+    assert_eq!([0, 0, 0xFF, 0xFF], get_pixel(sixel, 2, 0));
+    assert_eq!([127, 127, 127, 0xFF], get_pixel(sixel, 3, 0));
+}
+
+#[test]
+fn test_sixel_background_selection() {
+    // P2 = 0 fills the raster area with the background color
+    let (mut buf, caret) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[44m\x1BP0;0;0q\"1;1;4;6#1;2;100;0;0#1@\x1B\\",
+    );
+    update_sixels(&mut buf);
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!(4, sixel.width());
+    assert_eq!(6, sixel.height());
+    assert_eq!([0xFF, 0, 0, 0xFF], get_pixel(sixel, 0, 0));
+    let (r, g, b) = buf.palette.colors[caret.attr.get_background() as usize].get_rgb();
+    assert_eq!([r, g, b, 0xFF], get_pixel(sixel, 0, 1));
+    assert_eq!([r, g, b, 0xFF], get_pixel(sixel, 3, 5));
+
+    // P2 = 1 keeps them transparent
+    let (mut buf, _) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[44m\x1BP0;1;0q\"1;1;4;6#1;2;100;0;0#1@\x1B\\",
+    );
+    update_sixels(&mut buf);
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!([0xFF, 0, 0, 0xFF], get_pixel(sixel, 0, 0));
+    assert_eq!(0, get_pixel(sixel, 0, 1)[3]);
+    assert_eq!(0, get_pixel(sixel, 3, 5)[3]);
+}
+
+#[test]
+fn test_sixel_ragged_lines() {
+    let (mut buf, _) = create_buffer(&mut Parser::default(), b"\x1BP0;1q#1~-#1~~~\x1B\\");
+    update_sixels(&mut buf);
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!(3, sixel.width());
+    assert_eq!(12, sixel.height());
+    assert_eq!(3 * 12 * 4, sixel.picture_data.len());
+    assert_eq!(0, get_pixel(sixel, 2, 0)[3]);
+    assert_eq!(0xFF, get_pixel(sixel, 2, 6)[3]);
+}
+
+#[test]
+fn test_sixel_aspect_ratio() {
+    let (mut buf, _) = create_buffer(&mut Parser::default(), b"\x1BP2q#1~\x1B\\");
+    update_sixels(&mut buf);
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!(5, sixel.vertical_scale);
+    assert_eq!(6, sixel.height());
+    assert_eq!(30, sixel.get_screen_height());
+
+    // raster attributes override P1
+    let (mut buf, _) = create_buffer(&mut Parser::default(), b"\x1BP2q\"2;1#1~\x1B\\");
+    update_sixels(&mut buf);
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!(2, sixel.vertical_scale);
+    assert_eq!(1, sixel.horizontal_scale);
+    assert_eq!(12, sixel.get_screen_height());
+}
+
+#[test]
+fn test_sixel_color_registers() {
+    // private color registers are the default
+    let (mut buf, _) = create_buffer(
+        &mut Parser::default(),
+        b"\x1BPq#5;2;0;100;0\x1B\\\x1B[2;1H\x1BPq#5~\x1B\\",
+    );
+    update_sixels(&mut buf);
+    let (r, g, b) = Palette::default().colors[5].get_rgb();
+    assert_eq!([r, g, b, 0xFF], get_pixel(&buf.layers[0].sixels[1], 0, 0));
+
+    let (buf, _) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[?1070l\x1BPq#5;2;0;100;0\x1B\\\x1B[2;1H\x1BPq#5~\x1B\\",
+    );
+    assert!(!buf.terminal_state.sixel_private_color_registers);
+    assert_eq!(
+        [0, 0xFF, 0, 0xFF],
+        get_pixel(&buf.layers[0].sixels[1], 0, 0)
+    );
+}
+
+#[test]
+fn test_sixel_display_mode() {
+    let (mut buf, _) = create_buffer(&mut Parser::default(), b"\x1B[5;5H\x1B[?80h\x1BPq#1~\x1B\\");
+    update_sixels(&mut buf);
+    assert!(buf.terminal_state.sixel_display_mode);
+    assert_eq!(Position::new(0, 0), buf.layers[0].sixels[0].position);
+
+    let (mut buf, _) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[?80h\x1B[?80l\x1B[5;5H\x1BPq#1~\x1B\\",
+    );
+    update_sixels(&mut buf);
+    assert_eq!(Position::new(4, 4), buf.layers[0].sixels[0].position);
+}
+
+#[test]
+fn test_sixel_moves_caret() {
+    let (buf, caret) = create_buffer(&mut Parser::default(), b"\x1B[3;5H\x1BPq#1~-~-~-~\x1B\\");
+    assert_eq!(24, buf.layers[0].sixels[0].height());
+    assert_eq!(Position::new(4, 4), caret.get_position());
+
+    // display mode leaves the caret where it is
+    let (_, caret) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[?80h\x1B[3;5H\x1BPq#1~-~-~-~\x1B\\",
+    );
+    assert_eq!(Position::new(4, 2), caret.get_position());
+}
+
+#[test]
+fn test_sixel_scrolls_into_scrollback() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[6;1H\x1BPq\"1;1;8;16#1!8~-!8~-!8~\x1B\\\x1B[25;1H",
+    );
+    update_buffer(&mut buf, &mut caret, &mut Parser::default(), b"\n\n\n\n\n");
+    assert_eq!(Position::new(0, 0), buf.layers[0].sixels[0].position);
+    assert!(buf.scrollback.get_sixels().is_empty());
+
+    update_buffer(&mut buf, &mut caret, &mut Parser::default(), b"\n");
+    assert!(buf.layers[0].sixels.is_empty());
+    assert_eq!(6, buf.scrollback.len());
+    let sixels = buf.scrollback.get_sixels();
+    assert_eq!(1, sixels.len());
+    assert_eq!(Position::new(0, 5), sixels[0].position);
+}
+
+#[test]
+fn test_sixel_scroll_region() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[5;10r\x1B[5;1H\x1BPq\"1;1;8;32#1!8~-!8~-!8~-!8~-!8~-!8~\x1B\\",
+    );
+    assert_eq!(Position::new(0, 6), caret.get_position());
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut Parser::default(),
+        b"\x1B[10;1H\n",
+    );
+
+    let sixels = &buf.layers[0].sixels;
+    assert_eq!(1, sixels.len());
+    assert_eq!(Position::new(0, 4), sixels[0].position);
+    assert_eq!(16, sixels[0].height());
+
+    update_buffer(&mut buf, &mut caret, &mut Parser::default(), b"\n");
+    assert!(buf.layers[0].sixels.is_empty());
+}
+
+#[test]
+fn test_sixel_delete_insert_line() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut Parser::default(),
+        b"\x1B[10;1Hx\x1B[6;1H\x1BPq\"1;1;8;16#1!8~-!8~-!8~\x1B\\\x1B[3;1H\x1B[M",
+    );
+    assert_eq!(Position::new(0, 4), buf.layers[0].sixels[0].position);
+    update_buffer(&mut buf, &mut caret, &mut Parser::default(), b"\x1B[2L");
+    assert_eq!(Position::new(0, 6), buf.layers[0].sixels[0].position);
+}
+
+#[test]
+fn test_sixel_erase() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut Parser::default(),
+        b"\x1BPq\"1;1;64;32#1!64~-!64~-!64~-!64~-!64~-!64~\x1B\\\x1B[1;1H\x1B[2K",
+    );
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!(0, get_pixel(sixel, 0, 15)[3]);
+    assert_eq!(0xFF, get_pixel(sixel, 0, 16)[3]);
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut Parser::default(),
+        b"\x1B[2;5H\x1B[1K",
+    );
+    let sixel = &buf.layers[0].sixels[0];
+    assert_eq!(0, get_pixel(sixel, 31, 16)[3]);
+    assert_eq!(0xFF, get_pixel(sixel, 32, 16)[3]);
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut Parser::default(),
+        b"\x1B[2;1H\x1B[J",
+    );
+    assert!(buf.layers[0].sixels.is_empty());
+}
+
+#[test]
+fn test_synchronous_sixel_decoding() {
+    let (mut buf, mut caret) = create_buffer(&mut Parser::default(), b"");
+    buf.set_sixel_decoding(SixelDecoding::Synchronous);
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut Parser::default(),
+        b"\x1B[?80h\x1BPq#1~~\x1B\\",
+    );
+    assert!(!buf.has_pending_sixels());
+    assert_eq!(1, buf.layers[0].sixels.len());
+    assert_eq!(2, buf.layers[0].sixels[0].width());
+
+    let mut parser = Parser::default();
+    let result = b"\x1BPq#1;2;3~\x1B\\"
+        .iter()
+        .map(|b| parser.print_char(&mut buf, &mut caret, *b as char))
+        .last()
+        .unwrap();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_sixel_decoding_error() {
+    let (mut buf, mut caret) = create_buffer(&mut Parser::default(), b"");
+    buf.set_sixel_decoding(SixelDecoding::WorkerPool {
+        workers: 1,
+        queue_size: 4,
+    });
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut Parser::default(),
+        b"\x1B[?80h\x1BPq#1;2;3~\x1B\\\x1BPq#1~\x1B\\",
+    );
+    let mut errors = 0;
+    while buf.has_pending_sixels() {
+        if buf.poll_sixels().is_err() {
+            errors += 1;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(1, errors);
+    assert_eq!(1, buf.layers[0].sixels.len());
+}
+
+#[test]
+fn test_sixel_queue_size() {
+    let (mut buf, mut caret) = create_buffer(&mut Parser::default(), b"\x1B[?80h");
+    buf.set_sixel_decoding(SixelDecoding::WorkerPool {
+        workers: 1,
+        queue_size: 2,
+    });
+    for _ in 0..5 {
+        update_buffer(
+            &mut buf,
+            &mut caret,
+            &mut Parser::default(),
+            b"\x1BPq#1!200~-!200~\x1B\\",
+        );
+        assert!(buf.has_pending_sixels());
+        assert!(buf.get_pending_sixel_count() <= 2);
+    }
+    buf.finish_sixels().unwrap();
+    assert!(!buf.has_pending_sixels());
+    assert_eq!(1, buf.layers[0].sixels.len());
+}
+
+#[test]
+fn test_pending_sixel_stays_on_primary_screen() {
+    let (mut buf, mut caret) = create_buffer(&mut Parser::default(), b"\x1B[?80h");
+    buf.set_sixel_decoding(SixelDecoding::WorkerPool {
+        workers: 1,
+        queue_size: 4,
+    });
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut Parser::default(),
+        b"\x1BPq#1!200~-!200~\x1B\\\x1B[?1049h",
+    );
+    assert!(!buf.has_pending_sixels());
+    assert!(buf.layers[0].sixels.is_empty());
+
+    update_buffer(&mut buf, &mut caret, &mut Parser::default(), b"\x1B[?1049l");
+    assert_eq!(1, buf.layers[0].sixels.len());
+    assert_eq!(200, buf.layers[0].sixels[0].width());
+}
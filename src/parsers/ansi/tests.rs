@@ -1,11 +1,14 @@
 #![allow(clippy::float_cmp)]
 use crate::{
     ansi::MusicOption,
-    convert_to_ans,
+    convert_to_ans, convert_to_midi,
     parsers::{ansi, create_buffer, get_action, get_simple_action, update_buffer},
-    AttributedChar, BufferType, CallbackAction, Caret, Color, MusicAction, Position, SaveOptions,
-    TerminalScrolling, TextAttribute, XTERM_256_PALETTE,
+    read_midi, render_music, AnsiMusic, AttributedChar, AudioOptions, Buffer, BufferParser,
+    BufferType, CallbackAction, Caret, Color, LineAttribute, MouseEncoding, MouseMode, MusicAction,
+    Position, RgbaImage, SaveOptions, TerminalScrolling, TextAttribute, MAX_KITTY_PLACEMENT_CELLS,
+    XTERM_256_PALETTE,
 };
+use base64::{engine::general_purpose, Engine};
 
 #[test]
 fn test_ansi_sequence() {
@@ -499,12 +502,77 @@ fn test_reset_empty_colors() {
 #[test]
 fn test_print_char_extension() {
     let (mut buf, mut caret) = create_buffer(&mut ansi::Parser::default(), b"");
+    // only terminal screens have a fixed height
+    buf.is_terminal_buffer = false;
     for _ in 0..30 {
         update_buffer(&mut buf, &mut caret, &mut ansi::Parser::default(), b"a\n");
     }
     assert_eq!(31, buf.layers[0].lines.len());
 }
 
+#[test]
+fn test_scrollback() {
+    let (mut buf, mut caret) = create_buffer(&mut ansi::Parser::default(), b"");
+    for _ in 0..30 {
+        update_buffer(&mut buf, &mut caret, &mut ansi::Parser::default(), b"a\n");
+    }
+    // the screen doesn't grow, the lines scrolled off the screen end up in the scrollback
+    assert_eq!(25, buf.layers[0].lines.len());
+    assert_eq!(6, buf.scrollback.len());
+    assert_eq!(
+        'a',
+        buf.scrollback.get_line(5).unwrap().chars[0].unwrap().ch
+    );
+    assert_eq!(Position::new(0, 24), caret.get_position());
+}
+
+#[test]
+fn test_reset_margins() {
+    let (mut buf, mut caret) = create_buffer(&mut ansi::Parser::default(), b"\x1B[5;10r\x1B[r");
+    assert_eq!(Some((0, 24)), buf.terminal_state.margins_up_down);
+
+    // the last line scrolls the screen instead of moving the caret below it
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[25;1Hx\n",
+    );
+    assert_eq!(Position::new(0, 24), caret.get_position());
+    assert_eq!(1, buf.scrollback.len());
+}
+
+#[test]
+fn test_scrollback_margins() {
+    let (mut buf, mut caret) = create_buffer(&mut ansi::Parser::default(), b"\x1B[5;10r");
+    for i in 0..30 {
+        update_buffer(
+            &mut buf,
+            &mut caret,
+            &mut ansi::Parser::default(),
+            format!("\x1B[10;1H{i}\n").as_bytes(),
+        );
+    }
+    // scrolling inside margins doesn't feed the scrollback
+    assert!(buf.scrollback.is_empty());
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[1;25r\x1B[25;1Hx\n",
+    );
+    assert_eq!(1, buf.scrollback.len());
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[3J",
+    );
+    assert!(buf.scrollback.is_empty());
+}
+
 #[test]
 fn test_insert_mode() {
     let (buf, _) = create_buffer(
@@ -728,6 +796,42 @@ fn test_melody() {
     assert_eq!(14, music.music_actions.len());
 }
 
+#[test]
+fn test_music_midi_round_trip() {
+    let sequences: [&[u8]; 5] = [
+        b"\x1B[NC\x0E",
+        b"\x1B[NNL8C\x0E",
+        b"\x1B[NT123C\x0E",
+        b"\x1B[NCP32.D\x0E",
+        b"\x1B[MFT225O3L8GL8GL8GL2E-P8L8FL8FL8FMLL2DL2DMNP8\x0E",
+    ];
+    for sequence in sequences {
+        let mut p = ansi::Parser {
+            ansi_music: MusicOption::Both,
+            ..ansi::Parser::default()
+        };
+        let CallbackAction::PlayMusic(music) = get_simple_action(&mut p, sequence) else {
+            panic!();
+        };
+        let round_trip = read_midi(&convert_to_midi(&music)).unwrap();
+
+        let notes = |music: &AnsiMusic| -> Vec<MusicAction> {
+            music
+                .music_actions
+                .iter()
+                .filter(|action| matches!(action, MusicAction::PlayNote(..)))
+                .copied()
+                .collect()
+        };
+        let options = AudioOptions::default();
+        assert_eq!(
+            render_music(&music, &options),
+            render_music(&round_trip, &options)
+        );
+        assert_eq!(notes(&music), notes(&round_trip));
+    }
+}
+
 #[test]
 fn test_macro() {
     let mut parser = ansi::Parser::default();
@@ -1237,3 +1341,898 @@ fn test_rip_support_request_ignore() {
     update_buffer(&mut buf, &mut caret, &mut parser, b"\x1B[!#");
     assert_eq!('#', buf.get_char_xy(0, 0).unwrap().ch);
 }
+
+#[test]
+fn test_print_bytes() {
+    let data = b"\x1B[1;33mHello\x07 \x1B[44mWorld\r\n\xDB\xB0\x1B[6n";
+    let (char_buf, char_caret) = create_buffer(&mut ansi::Parser::default(), data);
+
+    let mut buf = Buffer::create(80, 25);
+    buf.is_terminal_buffer = true;
+    buf.layers.remove(0);
+    buf.layers[0].is_locked = false;
+    buf.layers[0].is_transparent = false;
+    buf.layers.first_mut().unwrap().lines.clear();
+    let mut caret = Caret::default();
+    let actions = ansi::Parser::default()
+        .print_bytes(&mut buf, &mut caret, data)
+        .unwrap();
+
+    assert_eq!(
+        vec![
+            CallbackAction::Beep,
+            CallbackAction::SendString("\x1B[2;3R".to_string())
+        ],
+        actions
+    );
+    assert_eq!(char_caret.get_position(), caret.get_position());
+    for y in 0..3 {
+        for x in 0..80 {
+            let pos = Position::new(x, y);
+            assert_eq!(char_buf.get_char(pos), buf.get_char(pos));
+        }
+    }
+}
+
+#[test]
+fn test_alternate_screen() {
+    let (mut buf, mut caret) =
+        create_buffer(&mut ansi::Parser::default(), b"\x1B[1;31mPrimary\x1B[2;5H");
+    let attr = caret.attr;
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[?1049h\x1B[0mAlt",
+    );
+    assert!(buf.is_alternate_screen());
+    assert_eq!(
+        ' ',
+        buf.get_char(Position::new(0, 0)).unwrap_or_default().ch
+    );
+    assert_eq!('A', buf.get_char(Position::new(4, 1)).unwrap().ch);
+
+    // the alternate screen doesn't feed the scrollback
+    for _ in 0..30 {
+        update_buffer(&mut buf, &mut caret, &mut ansi::Parser::default(), b"\n");
+    }
+    assert!(buf.scrollback.is_empty());
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[?1049l",
+    );
+    assert!(!buf.is_alternate_screen());
+    assert_eq!('P', buf.get_char(Position::new(0, 0)).unwrap().ch);
+    assert_eq!(Position::new(4, 1), caret.get_position());
+    assert_eq!(attr, caret.attr);
+}
+
+#[test]
+fn test_alternate_screen_47() {
+    let (mut buf, mut caret) = create_buffer(&mut ansi::Parser::default(), b"Primary");
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[?47h\x1B[HAlt\x1B[?47l",
+    );
+    assert_eq!('P', buf.get_char(Position::new(0, 0)).unwrap().ch);
+
+    // the alternate screen content is kept with mode 47
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[?47h",
+    );
+    assert_eq!('A', buf.get_char(Position::new(0, 0)).unwrap().ch);
+
+    // 1047 clears the alternate screen when leaving it
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[?1047l\x1B[?1047h",
+    );
+    assert_eq!(
+        ' ',
+        buf.get_char(Position::new(0, 0)).unwrap_or_default().ch
+    );
+}
+
+#[test]
+fn test_osc_window_title() {
+    let mut parser = ansi::Parser::default();
+    let action = get_simple_action(&mut parser, b"\x1B]0;Hello World\x07");
+    assert_eq!(
+        CallbackAction::ChangeWindowTitle("Hello World".to_string()),
+        action
+    );
+    let action = get_simple_action(&mut parser, b"\x1B]2;Title\x1B\\");
+    assert_eq!(
+        CallbackAction::ChangeWindowTitle("Title".to_string()),
+        action
+    );
+    let action = get_simple_action(&mut parser, b"\x1B]1;Icon\x07");
+    assert_eq!(CallbackAction::ChangeIconName("Icon".to_string()), action);
+}
+
+#[test]
+fn test_osc_is_not_printed() {
+    let (buf, caret) = create_buffer(&mut ansi::Parser::default(), b"\x1B]0;Title\x07A");
+    assert_eq!('A', buf.get_char(Position::new(0, 0)).unwrap().ch);
+    assert_eq!(Position::new(1, 0), caret.get_position());
+}
+
+#[test]
+fn test_osc_set_palette() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"\x1B]4;1;rgb:ff/80/00;12;#102030\x07",
+    );
+    // ANSI red is DOS color 4, ANSI bright blue DOS color 9
+    assert_eq!(Color::new(0xFF, 0x80, 0x00), buf.palette.colors[4]);
+    assert_eq!(Color::new(0x10, 0x20, 0x30), buf.palette.colors[9]);
+
+    let action = get_action(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B]4;1;?\x1B\\",
+    );
+    assert_eq!(
+        CallbackAction::SendString("\x1B]4;1;rgb:ffff/8080/0000\x1B\\".to_string()),
+        action
+    );
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B]104;12\x07",
+    );
+    assert_eq!(Color::new(0xFF, 0x80, 0x00), buf.palette.colors[4]);
+    assert_eq!(Color::new(0x55, 0x55, 0xFF), buf.palette.colors[9]);
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B]104\x07",
+    );
+    assert_eq!(Color::new(0xAA, 0x00, 0x00), buf.palette.colors[4]);
+}
+
+#[test]
+fn test_osc_query_colors() {
+    let mut parser = ansi::Parser::default();
+    let action = get_simple_action(&mut parser, b"\x1B]10;?\x07");
+    assert_eq!(
+        CallbackAction::SendString("\x1B]10;rgb:aaaa/aaaa/aaaa\x07".to_string()),
+        action
+    );
+    let action = get_simple_action(&mut parser, b"\x1B]11;?\x07");
+    assert_eq!(
+        CallbackAction::SendString("\x1B]11;rgb:0000/0000/0000\x07".to_string()),
+        action
+    );
+    let action = get_simple_action(&mut parser, b"\x1B]4;196;?\x07");
+    assert_eq!(
+        CallbackAction::SendString("\x1B]4;196;rgb:ffff/0000/0000\x07".to_string()),
+        action
+    );
+}
+
+#[test]
+fn test_osc_clipboard() {
+    let mut parser = ansi::Parser::default();
+    let action = get_simple_action(&mut parser, b"\x1B]52;c;SGVsbG8=\x07");
+    assert_eq!(CallbackAction::SetClipboard("Hello".to_string()), action);
+    let action = get_simple_action(&mut parser, b"\x1B]52;c;?\x07");
+    assert_eq!(CallbackAction::None, action);
+}
+
+#[test]
+fn test_osc_unsupported() {
+    let mut parser = ansi::Parser::default();
+    let mut buf = Buffer::new();
+    let mut caret = Caret::default();
+    for ch in "\x1B]777;notify\x1B".chars() {
+        parser.print_char(&mut buf, &mut caret, ch).unwrap();
+    }
+    assert!(parser.print_char(&mut buf, &mut caret, '\\').is_err());
+    parser.print_char(&mut buf, &mut caret, 'A').unwrap();
+    assert_eq!('A', buf.get_char(Position::new(0, 0)).unwrap().ch);
+}
+
+#[test]
+fn test_osc_hyperlink() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"A\x1B]8;id=x;https://example.com\x1B\\Link\x1B]8;;\x1B\\B",
+    );
+    assert!(buf.get_hyperlink_at(Position::new(0, 0)).is_none());
+    let link = buf.get_hyperlink_at(Position::new(1, 0)).unwrap();
+    assert_eq!("https://example.com", link.uri);
+    assert_eq!(Some("x".to_string()), link.id);
+    assert!(buf.get_hyperlink_at(Position::new(4, 0)).is_some());
+    assert!(buf.get_hyperlink_at(Position::new(5, 0)).is_none());
+
+    // links move with the text
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[1;1H\x1B[L",
+    );
+    assert!(buf.get_hyperlink_at(Position::new(1, 0)).is_none());
+    assert!(buf.get_hyperlink_at(Position::new(1, 1)).is_some());
+}
+
+#[test]
+fn test_hyperlink_table_is_pruned() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    for i in 0..1000 {
+        let link = format!("\x1B[1;1H\x1B]8;;https://example.com/{i}\x1B\\L");
+        update_buffer(&mut buf, &mut caret, &mut parser, link.as_bytes());
+    }
+    assert!(buf.hyperlinks.len() <= 64);
+    assert_eq!(
+        "https://example.com/999",
+        buf.get_hyperlink_at(Position::new(0, 0)).unwrap().uri
+    );
+
+    // RIS drops the links with the screen content
+    update_buffer(&mut buf, &mut caret, &mut parser, b"\x1Bc");
+    assert!(buf.hyperlinks.is_empty());
+}
+
+#[test]
+fn test_save_hyperlink() {
+    let (buf, _) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"\x1B]8;;http://a\x07AB\x1B]8;;\x07C",
+    );
+    let mut opt = SaveOptions::new();
+    opt.modern_terminal_output = true;
+    let ansi = String::from_utf8(convert_to_ans(&buf, &opt).unwrap()).unwrap();
+    let line = ansi.lines().next().unwrap();
+    assert!(line.contains("\x1B]8;;http://a\x1B\\AB\x1B]8;;\x1B\\C"));
+
+    let (buf2, _) = create_buffer(&mut ansi::Parser::default(), ansi.as_bytes());
+    assert_eq!(
+        "http://a",
+        buf2.get_hyperlink_at(Position::new(1, 0)).unwrap().uri
+    );
+    assert!(buf2.get_hyperlink_at(Position::new(2, 0)).is_none());
+}
+
+fn utf8_parser() -> ansi::Parser {
+    ansi::Parser {
+        input_encoding: ansi::InputEncoding::Utf8,
+        ..Default::default()
+    }
+}
+
+fn get_line(buf: &Buffer, len: i32) -> String {
+    (0..len)
+        .map(|x| buf.get_char(Position::new(x, 0)).unwrap().ch)
+        .collect()
+}
+
+#[test]
+fn test_utf8_input() {
+    let (buf, caret) = create_buffer(&mut utf8_parser(), "ä░\x1B[1m█€".as_bytes());
+    assert_eq!("\u{84}\u{B0}\u{DB}?", get_line(&buf, 4));
+    assert!(buf
+        .get_char(Position::new(2, 0))
+        .unwrap()
+        .attribute
+        .is_bold());
+    assert_eq!(Position::new(4, 0), caret.get_position());
+
+    // the fast path needs to decode as well
+    let mut buf = Buffer::create(80, 25);
+    let mut caret = Caret::default();
+    utf8_parser()
+        .print_bytes(&mut buf, &mut caret, "aä░b".as_bytes())
+        .unwrap();
+    assert_eq!("a\u{84}\u{B0}b", get_line(&buf, 4));
+}
+
+#[test]
+fn test_utf8_invalid_input() {
+    let mut parser = ansi::Parser {
+        replacement_char: '\u{FE}',
+        ..utf8_parser()
+    };
+    let (buf, _) = create_buffer(&mut parser, b"\xFFa\xE2\x96b\x80");
+    assert_eq!("\u{FE}a\u{FE}b\u{FE}", get_line(&buf, 5));
+}
+
+#[test]
+fn test_utf8_osc() {
+    let action = get_simple_action(&mut utf8_parser(), "\x1B]0;Grüße\x07".as_bytes());
+    assert_eq!(
+        CallbackAction::ChangeWindowTitle("Grüße".to_string()),
+        action
+    );
+}
+
+#[test]
+fn test_cp437_input() {
+    let (buf, _) = create_buffer(&mut ansi::Parser::default(), b"\xC3\xA4");
+    assert_eq!("\u{C3}\u{A4}", get_line(&buf, 2));
+}
+
+#[test]
+fn test_dec_special_graphics() {
+    let (buf, _) = create_buffer(&mut ansi::Parser::default(), b"\x1B(0lqk\x1B(Bq");
+    assert_eq!("\u{DA}\u{C4}\u{BF}q", get_line(&buf, 4));
+
+    // fast path
+    let mut buf = Buffer::create(80, 25);
+    let mut caret = Caret::default();
+    ansi::Parser::default()
+        .print_bytes(&mut buf, &mut caret, b"\x1B(0x")
+        .unwrap();
+    assert_eq!('\u{B3}', buf.get_char(Position::new(0, 0)).unwrap().ch);
+}
+
+#[test]
+fn test_charset_shifts() {
+    let (buf, _) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"\x1B)0\x1B*A\x1B+<q\x0Eq\x0Fq\x1BN#\x1BOd#",
+    );
+    assert_eq!("q\u{C4}q\u{9C}\u{84}#", get_line(&buf, 6));
+
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"\x1B(%5d");
+    assert_eq!('\u{84}', buf.get_char(Position::new(0, 0)).unwrap().ch);
+
+    // RIS resets the designations
+    update_buffer(&mut buf, &mut caret, &mut parser, b"\x1Bcd");
+    assert_eq!('d', buf.get_char(Position::new(0, 0)).unwrap().ch);
+}
+
+#[test]
+fn test_so_si_without_designation() {
+    // ANSI art uses 0x0E & 0x0F as glyphs
+    let (buf, _) = create_buffer(&mut ansi::Parser::default(), b"\x0Eq\x0F");
+    assert_eq!("\x0Eq\x0F", get_line(&buf, 3));
+}
+
+#[test]
+fn test_double_width_line() {
+    let mut data = b"\x1B#6".to_vec();
+    data.extend(vec![b'#'; 41]);
+    let (buf, caret) = create_buffer(&mut ansi::Parser::default(), &data);
+    assert_eq!(LineAttribute::DoubleWidth, buf.get_line_attribute(0));
+    assert_eq!(40, buf.get_line_width(0));
+    // the 41st char wraps to the next line
+    assert_eq!(Position::new(1, 1), caret.get_position());
+
+    let (buf, caret) = create_buffer(&mut ansi::Parser::default(), b"\x1B#6\x1B[70C");
+    assert_eq!(80, buf.get_line_width(1));
+    assert_eq!(Position::new(39, 0), caret.get_position());
+}
+
+#[test]
+fn test_double_height_line() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"\x1B[1;50HX\x1B[1;1H\x1B#3Big\r\n\x1B#4Big",
+    );
+    assert_eq!(LineAttribute::DoubleHeightTop, buf.get_line_attribute(0));
+    assert_eq!(LineAttribute::DoubleHeightBottom, buf.get_line_attribute(1));
+    // chars of the right half are lost
+    assert_eq!(
+        ' ',
+        buf.get_char(Position::new(49, 0)).unwrap_or_default().ch
+    );
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[1;1H\x1B#5",
+    );
+    assert_eq!(LineAttribute::Single, buf.get_line_attribute(0));
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[2J",
+    );
+    assert_eq!(LineAttribute::Single, buf.get_line_attribute(1));
+}
+
+#[test]
+fn test_decaln() {
+    let (buf, caret) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"\x1B[5;10r\x1B[3;3H\x1B#6\x1B#8",
+    );
+    assert_eq!(Position::new(0, 0), caret.get_position());
+    assert_eq!(None, buf.terminal_state.margins_up_down);
+    assert_eq!(LineAttribute::Single, buf.get_line_attribute(2));
+    for y in 0..25 {
+        for x in 0..80 {
+            assert_eq!('E', buf.get_char(Position::new(x, y)).unwrap().ch);
+        }
+    }
+}
+
+#[test]
+fn test_mouse_mode_and_encoding() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"\x1B[?1000h\x1B[?1006h\x1B[?1004h",
+    );
+    assert_eq!(MouseMode::VT200, buf.terminal_state.mouse_mode);
+    assert_eq!(MouseEncoding::SGR, buf.terminal_state.mouse_encoding);
+    assert!(buf.terminal_state.focus_events);
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[?1006l",
+    );
+    assert_eq!(MouseMode::VT200, buf.terminal_state.mouse_mode);
+    assert_eq!(MouseEncoding::Default, buf.terminal_state.mouse_encoding);
+    assert!(buf.terminal_state.focus_events);
+}
+
+#[test]
+fn test_reset_mouse_reporting() {
+    let (mut buf, mut caret) = create_buffer(
+        &mut ansi::Parser::default(),
+        b"\x1B[?1003h\x1B[?1006h\x1B[?1004h\x1B[?1007h",
+    );
+    assert_eq!(MouseMode::AnyEvents, buf.terminal_state.mouse_mode);
+    assert!(buf.terminal_state.alternate_scroll);
+
+    update_buffer(&mut buf, &mut caret, &mut ansi::Parser::default(), b"\x1Bc");
+    assert_eq!(MouseMode::Default, buf.terminal_state.mouse_mode);
+    assert_eq!(MouseEncoding::Default, buf.terminal_state.mouse_encoding);
+    assert!(!buf.terminal_state.focus_events);
+    assert!(!buf.terminal_state.alternate_scroll);
+}
+
+#[test]
+fn test_cursor_key_and_keypad_mode() {
+    let (mut buf, mut caret) = create_buffer(&mut ansi::Parser::default(), b"\x1B[?1h\x1B=");
+    assert!(buf.terminal_state.application_cursor_keys);
+    assert!(buf.terminal_state.application_keypad);
+
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut ansi::Parser::default(),
+        b"\x1B[?1l\x1B>",
+    );
+    assert!(!buf.terminal_state.application_cursor_keys);
+    assert!(!buf.terminal_state.application_keypad);
+}
+
+#[test]
+fn test_soft_font() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(
+        &mut parser,
+        b"\x1BP0;1;1;8;0;0;12;0{ @~~~~~~~~/~~~~~~~~;?????@@?\x1B\\",
+    );
+    let font = buf.get_font(256).unwrap();
+    assert_eq!(buf.get_font_dimensions(), font.size);
+    let glyph = &font.get_glyph('!').unwrap().data;
+    assert_eq!(vec![0xFF; 12], glyph[..12]);
+    assert_eq!(0, glyph[12]);
+    assert_eq!(0x06, font.get_glyph('"').unwrap().data[0]);
+    assert_eq!(0, font.get_glyph('A').unwrap().data[0]);
+
+    // designate as G0, the font page switches back with ASCII
+    update_buffer(&mut buf, &mut caret, &mut parser, b"\x1B( @!\x1B[b\x1B(B!");
+    assert_eq!('!', buf.get_char(Position::new(0, 0)).unwrap().ch);
+    assert_eq!(
+        256,
+        buf.get_char(Position::new(0, 0)).unwrap().get_font_page()
+    );
+    assert_eq!(
+        256,
+        buf.get_char(Position::new(1, 0)).unwrap().get_font_page()
+    );
+    assert_eq!(
+        0,
+        buf.get_char(Position::new(2, 0)).unwrap().get_font_page()
+    );
+}
+
+#[test]
+fn test_soft_font_erase_control() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"\x1BP0;1;1;0;0;0;0;0{A~;~\x1B\\");
+    // Pe = 1 keeps the other chars of the set
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        b"\x1BP0;2;1;0;0;0;0;0{A?\x1B\\",
+    );
+    let font = buf.get_font(256).unwrap();
+    assert_eq!(0x80, font.get_glyph('!').unwrap().data[0]);
+    assert_eq!(0, font.get_glyph('"').unwrap().data[0]);
+
+    // Pe = 0 erases the set
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        b"\x1BP0;2;0;0;0;0;0;0{A~\x1B\\",
+    );
+    assert_eq!(
+        0,
+        buf.get_font(256).unwrap().get_glyph('!').unwrap().data[0]
+    );
+
+    // a second set gets its own slot, Pe = 2 erases all sets
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        b"\x1BP0;1;0;0;0;0;0;0{B~\x1B\\",
+    );
+    assert!(buf.get_font(257).is_some());
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        b"\x1BP0;1;2;0;0;0;0;0{C~\x1B\\",
+    );
+    assert_eq!(
+        0x80,
+        buf.get_font(256).unwrap().get_glyph('!').unwrap().data[0]
+    );
+    assert!(buf.get_font(257).is_none());
+}
+
+#[test]
+fn test_invalid_soft_font() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    for invalid in [
+        b"\x1BP0;1;0;1;0;0;0;0{A~\x1B\\".as_slice(),
+        b"\x1BP0;1;0;0;0;0;17;0{A~\x1B\\".as_slice(),
+        b"\x1BP0;1;0;0;0;0;0;0{\x1B\\".as_slice(),
+    ] {
+        let mut result = Ok(CallbackAction::None);
+        for b in invalid {
+            result = parser.print_char(&mut buf, &mut caret, *b as char);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(result.is_err());
+    }
+}
+
+fn send_kitty(
+    buf: &mut Buffer,
+    caret: &mut Caret,
+    parser: &mut ansi::Parser,
+    cmd: &str,
+) -> CallbackAction {
+    get_action(buf, caret, parser, format!("\x1B_G{cmd}\x1B\\").as_bytes())
+}
+
+fn kitty_response(response: &str) -> CallbackAction {
+    CallbackAction::SendString(format!("\x1B_G{response}\x1B\\"))
+}
+
+#[test]
+fn test_kitty_transmit() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    // 2x1 RGB: red, green
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=7;/wAAAP8A",
+    );
+    assert_eq!(kitty_response("i=7;OK"), action);
+    let image = buf.get_kitty_image(7).unwrap();
+    assert_eq!((2, 1), (image.width, image.height));
+    assert_eq!(vec![0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0xFF], image.pixels);
+    assert!(buf.layers[0].kitty_placements.is_empty());
+    assert_eq!(Position::default(), caret.get_position());
+
+    // without id or number the client doesn't get a response
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "f=32,s=1,v=1;AAAAAA==");
+    assert_eq!(CallbackAction::None, action);
+    assert_eq!(2, buf.kitty_images.len());
+}
+
+#[test]
+fn test_kitty_chunked_transmission() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=1,m=1;/wAA",
+    );
+    assert_eq!(CallbackAction::None, action);
+    assert!(buf.get_kitty_image(1).is_none());
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "m=0;AP8A");
+    assert_eq!(kitty_response("i=1;OK"), action);
+    assert_eq!(
+        vec![0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0xFF],
+        buf.get_kitty_image(1).unwrap().pixels
+    );
+}
+
+#[test]
+fn test_kitty_transmission_exceeds_quota() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    buf.set_kitty_image_quota(8);
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=1,m=1;/wAA",
+    );
+    assert_eq!(CallbackAction::None, action);
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "m=1;AP8AAAAA");
+    assert_eq!(kitty_response("i=1;EFBIG:image exceeds the quota"), action);
+    // the remaining chunks are dropped
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "m=0;AAAA");
+    assert_eq!(CallbackAction::None, action);
+    assert!(buf.get_kitty_image(1).is_none());
+
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=2;/wAAAP8A",
+    );
+    assert_eq!(kitty_response("i=2;OK"), action);
+}
+
+#[test]
+fn test_kitty_huge_placement() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "a=T,f=24,s=2,v=1,i=1,c=300000000,r=300000000;/wAAAP8A",
+    );
+    assert_eq!(kitty_response("i=1;OK"), action);
+    let placement = buf.layers[0].kitty_placements[0];
+    assert_eq!(
+        (MAX_KITTY_PLACEMENT_CELLS, MAX_KITTY_PLACEMENT_CELLS),
+        (placement.columns, placement.rows)
+    );
+    // the caret moves one screen at most
+    assert_eq!(
+        buf.get_buffer_height() - 1,
+        caret.get_position().y - buf.get_first_visible_line()
+    );
+}
+
+#[test]
+fn test_kitty_png() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    let mut image = RgbaImage::new(3, 2);
+    image.pixels[0..4].copy_from_slice(&[1, 2, 3, 4]);
+    let png = general_purpose::STANDARD.encode(image.to_png().unwrap());
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        &format!("f=100,i=2;{png}"),
+    );
+    assert_eq!(kitty_response("i=2;OK"), action);
+    let kitty_image = buf.get_kitty_image(2).unwrap();
+    assert_eq!((3, 2), (kitty_image.width, kitty_image.height));
+    assert_eq!(image.pixels, kitty_image.pixels);
+
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "f=100,i=3;AAAA");
+    let CallbackAction::SendString(response) = action else {
+        panic!("expected an error response");
+    };
+    assert!(response.starts_with("\x1B_Gi=3;EBADPNG:"));
+}
+
+#[test]
+fn test_kitty_transmit_and_display() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"Hello");
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "a=T,f=24,s=2,v=1,i=1,c=2,r=3,z=-1;/wAAAP8A",
+    );
+    assert_eq!(kitty_response("i=1;OK"), action);
+    let placement = buf.layers[0].kitty_placements[0];
+    assert_eq!(Position::new(5, 0), placement.position);
+    assert_eq!(
+        (2, 3, -1),
+        (placement.columns, placement.rows, placement.z_index)
+    );
+    // the caret moves behind the image on its last line
+    assert_eq!(Position::new(7, 2), caret.get_position());
+
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "a=p,i=1,C=1,q=1");
+    assert_eq!(CallbackAction::None, action);
+    assert_eq!(2, buf.layers[0].kitty_placements.len());
+    assert_eq!(Position::new(7, 2), caret.get_position());
+}
+
+#[test]
+fn test_kitty_image_number() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=4;/wAAAP8A",
+    );
+    // the terminal picks the id for an image number
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,I=13;/wAAAP8A",
+    );
+    assert_eq!(kitty_response("i=5,I=13;OK"), action);
+    assert_eq!(13, buf.get_kitty_image(5).unwrap().number);
+
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "a=p,I=13,p=3");
+    assert_eq!(kitty_response("i=5,I=13,p=3;OK"), action);
+    // placing with the same placement id moves the placement
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=p,I=13,p=3");
+    assert_eq!(1, buf.layers[0].kitty_placements.len());
+    assert_eq!(
+        Position::new(1, 0),
+        buf.layers[0].kitty_placements[0].position
+    );
+
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "a=p,i=9");
+    assert_eq!(kitty_response("i=9;ENOENT:image not found"), action);
+
+    // neither id nor number doesn't pick an image
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=p,p=4");
+    assert_eq!(1, buf.layers[0].kitty_placements.len());
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=d,d=N");
+    assert!(buf.get_kitty_image(4).is_some());
+}
+
+#[test]
+fn test_kitty_errors_and_quiet() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=3,v=1,i=1;/wAAAP8A",
+    );
+    assert_eq!(
+        kitty_response("i=1;ENODATA:expected 3x1 pixels but got 6 bytes"),
+        action
+    );
+    assert!(buf.get_kitty_image(1).is_none());
+
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=1,q=1;/wAAAP8A",
+    );
+    assert_eq!(CallbackAction::None, action);
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "t=f,i=1,q=1;L3RtcC9h");
+    assert_eq!(
+        kitty_response("i=1;EINVAL:unsupported transmission medium f"),
+        action
+    );
+    let action = send_kitty(&mut buf, &mut caret, &mut parser, "t=f,i=1,q=2;L3RtcC9h");
+    assert_eq!(CallbackAction::None, action);
+    assert!(buf.get_kitty_image(1).is_some());
+
+    // a query checks the data without storing the image
+    let action = send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "a=q,f=24,s=2,v=1,i=31;/wAAAP8A",
+    );
+    assert_eq!(kitty_response("i=31;OK"), action);
+    assert!(buf.get_kitty_image(31).is_none());
+}
+
+#[test]
+fn test_kitty_delete() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=1;/wAAAP8A",
+    );
+    send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=2;/wAAAP8A",
+    );
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=p,i=1,C=1");
+    caret.set_position(Position::new(10, 5));
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=p,i=2,z=3,C=1");
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=p,i=1,p=1,C=1");
+    assert_eq!(3, buf.layers[0].kitty_placements.len());
+
+    // screen positions are 1 based
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=d,d=p,x=1,y=1");
+    assert_eq!(2, buf.layers[0].kitty_placements.len());
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=d,d=z,z=3");
+    assert_eq!(1, buf.layers[0].kitty_placements.len());
+    assert!(buf.get_kitty_image(2).is_some());
+
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=d,d=I,i=2");
+    assert!(buf.get_kitty_image(2).is_none());
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=d,d=C");
+    assert!(buf.layers[0].kitty_placements.is_empty());
+    assert!(buf.get_kitty_image(1).is_none());
+}
+
+#[test]
+fn test_kitty_placement_scrolls() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    send_kitty(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        "f=24,s=2,v=1,i=1;/wAAAP8A",
+    );
+    caret.set_position(Position::new(0, 1));
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=p,i=1,C=1");
+    caret.set_position(Position::new(0, 24));
+    update_buffer(&mut buf, &mut caret, &mut parser, b"\n");
+    assert_eq!(
+        Position::new(0, 0),
+        buf.layers[0].kitty_placements[0].position
+    );
+    update_buffer(&mut buf, &mut caret, &mut parser, b"\n");
+    assert!(buf.layers[0].kitty_placements.is_empty());
+
+    // clearing the screen removes the placements
+    send_kitty(&mut buf, &mut caret, &mut parser, "a=p,i=1");
+    update_buffer(&mut buf, &mut caret, &mut parser, b"\x1B[2J");
+    assert!(buf.layers[0].kitty_placements.is_empty());
+}
+
+#[test]
+fn test_unsupported_apc() {
+    let mut parser = ansi::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"");
+    let action = get_action(&mut buf, &mut caret, &mut parser, b"\x1B_Xfoo\x1B\\A");
+    assert_eq!(CallbackAction::None, action);
+    assert_eq!('A', buf.get_char(Position::new(0, 0)).unwrap().ch);
+    assert!(buf.kitty_images.is_empty());
+}
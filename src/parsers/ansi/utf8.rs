@@ -0,0 +1,118 @@
+use crate::{ascii::UNICODE_TO_CP437, Buffer};
+
+use super::Parser;
+
+/// Encoding of the bytes fed to [`Parser`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputEncoding {
+    /// Every byte is one code point of the font (classic BBS / DOS).
+    #[default]
+    CP437,
+    /// Bytes are decoded as UTF-8 and mapped to the font code points.
+    Utf8,
+}
+
+/// Incremental UTF-8 decoder, invalid sequences produce [`char::REPLACEMENT_CHARACTER`]
+/// and the decoder resyncs at the next byte that can start a sequence.
+#[derive(Debug, Default)]
+pub(super) struct Utf8Decoder {
+    bytes: [u8; 4],
+    len: usize,
+    needed: usize,
+}
+
+impl Utf8Decoder {
+    pub fn is_pending(&self) -> bool {
+        self.needed > 0
+    }
+
+    /// Feeds one byte. Gives back up to two chars, the completed or invalid pending
+    /// sequence and the byte itself if it interrupted the sequence.
+    pub fn decode(&mut self, byte: u8) -> [Option<char>; 2] {
+        if self.needed > 0 {
+            if (0x80..=0xBF).contains(&byte) {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+                if self.len < self.needed {
+                    return [None, None];
+                }
+                let result = std::str::from_utf8(&self.bytes[..self.len])
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                self.needed = 0;
+                return [Some(result), None];
+            }
+            // interrupted sequence
+            self.needed = 0;
+            return [Some(char::REPLACEMENT_CHARACTER), self.start(byte)];
+        }
+        [self.start(byte), None]
+    }
+
+    fn start(&mut self, byte: u8) -> Option<char> {
+        let needed = match byte {
+            0x00..=0x7F => return Some(char::from(byte)),
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+        self.bytes[0] = byte;
+        self.len = 1;
+        self.needed = needed;
+        None
+    }
+}
+
+impl Parser {
+    /// Maps a decoded char to a code point of the current font. Unicode fonts are used
+    /// as they are, otherwise the char is mapped to CP437 or the replacement char.
+    pub(super) fn map_unicode_char(&self, buf: &Buffer, ch: char) -> char {
+        if ch.is_ascii() {
+            return ch;
+        }
+        if ch as u32 > 0xFF
+            && buf
+                .get_font(self.current_font_page)
+                .is_some_and(|font| font.get_glyph(ch).is_some())
+        {
+            return ch;
+        }
+        match UNICODE_TO_CP437.get(&ch) {
+            Some(cp437) => *cp437,
+            None => self.replacement_char,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Utf8Decoder;
+
+    const INVALID: char = char::REPLACEMENT_CHARACTER;
+
+    fn decode(bytes: &[u8]) -> Vec<char> {
+        let mut decoder = Utf8Decoder::default();
+        bytes
+            .iter()
+            .flat_map(|b| decoder.decode(*b))
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(vec!['a', 'ä', '€', '😀'], decode("aä€😀".as_bytes()));
+    }
+
+    #[test]
+    fn test_invalid_sequences() {
+        // stray continuation byte & invalid lead bytes
+        assert_eq!(vec![INVALID, 'a', INVALID], decode(b"\x80a\xFF"));
+        // interrupted sequence
+        assert_eq!(vec![INVALID, 'a'], decode(b"\xE2\x82a"));
+        // overlong encoding & surrogate
+        assert_eq!(vec![INVALID, INVALID], decode(b"\xE0\x80\x80\xED\xA0\x80"));
+    }
+}
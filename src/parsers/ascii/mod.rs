@@ -47,7 +47,7 @@ impl BufferParser for Parser {
 }
 
 lazy_static::lazy_static! {
-    static ref UNICODE_TO_CP437: std::collections::HashMap<char,char> = {
+    pub(crate) static ref UNICODE_TO_CP437: std::collections::HashMap<char,char> = {
         let mut res = std::collections::HashMap::new();
         (0..256).for_each(|a| {
             res.insert(CP437_TO_UNICODE[a], char::from_u32(a as u32).unwrap());
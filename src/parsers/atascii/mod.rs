@@ -56,6 +56,27 @@ impl BufferParser for Parser {
         }
         Ok(CallbackAction::None)
     }
+
+    fn print_bytes(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        data: &[u8],
+    ) -> EngineResult<Vec<CallbackAction>> {
+        let mut actions = Vec::new();
+        for b in data {
+            if !self.got_escape && !is_control_byte(*b) {
+                buf.print_value(caret, *b as u16);
+                continue;
+            }
+            super::push_action(&mut actions, self.print_char(buf, caret, char::from(*b))?);
+        }
+        Ok(actions)
+    }
+}
+
+fn is_control_byte(b: u8) -> bool {
+    matches!(b, 0x1B..=0x1F | 0x7D..=0x7F | 0x9B..=0x9F | 0xFD..=0xFF)
 }
 
 lazy_static::lazy_static! {
@@ -166,4 +166,37 @@ impl BufferParser for Parser {
             },
         }
     }
+
+    fn print_bytes(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        data: &[u8],
+    ) -> EngineResult<Vec<CallbackAction>> {
+        let mut actions = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            if let AvtReadState::Chars = self.avt_state {
+                // everything up to the next avatar command is handled by the ansi parser
+                let run = data[i..]
+                    .iter()
+                    .position(|b| matches!(char::from(*b), AVT_CLR | AVT_REP | AVT_CMD))
+                    .unwrap_or(data.len() - i);
+                if run > 0 {
+                    actions.extend(
+                        self.ansi_parser
+                            .print_bytes(buf, caret, &data[i..i + run])?,
+                    );
+                    i += run;
+                    continue;
+                }
+            }
+            super::push_action(
+                &mut actions,
+                self.print_char(buf, caret, char::from(data[i]))?,
+            );
+            i += 1;
+        }
+        Ok(actions)
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{EngineResult, Line};
+use crate::{EngineResult, Line, LineAttribute, Rectangle, Sixel};
 use std::cmp::{max, min};
 
 use super::{AttributedChar, Buffer, Caret, Position};
@@ -49,6 +49,10 @@ pub enum CallbackAction {
     SendString(String),
     PlayMusic(AnsiMusic),
     ChangeBaudRate(u32),
+    ChangeWindowTitle(String),
+    ChangeIconName(String),
+    /// Text the host wants to put into the clipboard (OSC 52).
+    SetClipboard(String),
 }
 
 pub trait BufferParser {
@@ -66,6 +70,33 @@ pub trait BufferParser {
         caret: &mut Caret,
         c: char,
     ) -> EngineResult<CallbackAction>;
+
+    /// Prints a block of raw bytes to the buffer, every byte is treated as one character.
+    /// Parsers override this with fast paths for runs of printable text.
+    /// Gives back all actions that are not [`CallbackAction::None`] in the order they occurred.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error at the first byte that fails to parse, the bytes before it are already printed.
+    fn print_bytes(
+        &mut self,
+        buffer: &mut Buffer,
+        caret: &mut Caret,
+        data: &[u8],
+    ) -> EngineResult<Vec<CallbackAction>> {
+        let mut actions = Vec::new();
+        for b in data {
+            let action = self.print_char(buffer, caret, char::from(*b))?;
+            push_action(&mut actions, action);
+        }
+        Ok(actions)
+    }
+}
+
+fn push_action(actions: &mut Vec<CallbackAction>, action: CallbackAction) {
+    if action != CallbackAction::None {
+        actions.push(action);
+    }
 }
 
 impl Caret {
@@ -75,7 +106,10 @@ impl Caret {
 
         self.pos.x = 0;
         self.pos.y += 1;
-        while self.pos.y >= buf.layers[0].lines.len() as i32 {
+        // terminal screens don't grow, lines scrolled off the screen go to the scrollback
+        while self.pos.y >= buf.layers[0].lines.len() as i32
+            && (!buf.is_terminal_buffer || self.pos.y < buf.get_buffer_height())
+        {
             let len = buf.layers[0].lines.len();
             buf.layers[0].lines.insert(len, Line::new());
         }
@@ -104,7 +138,7 @@ impl Caret {
     }
 
     pub fn eol(&mut self, buf: &Buffer) {
-        self.pos.x = buf.get_buffer_width() - 1;
+        self.pos.x = buf.get_line_width(self.pos.y) - 1;
     }
 
     pub fn home(&mut self, buf: &Buffer) {
@@ -148,6 +182,7 @@ impl Caret {
                 i += 1;
             }
         }
+        buf.erase_sixels(Rectangle::from(self.pos.x, self.pos.y, number, 1));
     }
 
     pub fn left(&mut self, buf: &Buffer, num: i32) {
@@ -233,7 +268,7 @@ impl Buffer {
             layer.lines[caret.pos.y as usize]
                 .insert_char(caret.pos.x, Some(AttributedChar::default()));
         }
-        if caret.pos.x >= self.get_buffer_width() {
+        if caret.pos.x >= self.get_line_width(caret.pos.y) {
             if let crate::AutoWrapMode::AutoWrap = self.terminal_state.auto_wrap_mode {
                 caret.lf(self);
             } else {
@@ -255,12 +290,54 @@ impl Buffer {
         }
     }*/
 
+    /// Scrolls that affect the whole screen move the top line into the scrollback.
+    /// On the alternate screen the line is dropped.
+    fn is_full_screen_scroll(&self) -> bool {
+        let full_height = match self.terminal_state.margins_up_down {
+            Some((start, end)) => start <= 0 && end >= self.get_buffer_height() - 1,
+            None => true,
+        };
+        let full_width = match self.terminal_state.margins_left_right {
+            Some((start, end)) => start <= 0 && end >= self.get_buffer_width() - 1,
+            None => true,
+        };
+        self.is_terminal_buffer && full_height && full_width
+    }
+
     fn scroll_up(&mut self) {
         let start_line: i32 = self.get_first_editable_line();
         let end_line = self.get_last_editable_line();
 
+        if self.is_full_screen_scroll() {
+            let feed_scrollback = !self.is_alternate_screen();
+            for (i, layer) in self.layers.iter_mut().enumerate() {
+                let line = if start_line < layer.lines.len() as i32 {
+                    let line = layer.lines.remove(start_line as usize);
+                    if end_line <= layer.lines.len() as i32 {
+                        layer.lines.insert(end_line as usize, Line::new());
+                    }
+                    line
+                } else {
+                    Line::new()
+                };
+                if i == 0 && feed_scrollback {
+                    self.scrollback.push(line);
+                }
+            }
+            let region = Rectangle::from(
+                0,
+                start_line,
+                self.get_buffer_width(),
+                end_line - start_line + 1,
+            );
+            self.scroll_sixels(region, 0, -1, true);
+            return;
+        }
+
         let start_column = self.get_first_editable_column();
         let end_column = self.get_last_editable_column();
+        let region = Rectangle::from_coords(start_column, start_line, end_column, end_line);
+        self.scroll_sixels(region, 0, -1, false);
 
         for layer in &mut self.layers {
             for x in start_column..=end_column {
@@ -279,6 +356,8 @@ impl Buffer {
 
         let start_column = self.get_first_editable_column();
         let end_column = self.get_last_editable_column();
+        let region = Rectangle::from_coords(start_column, start_line, end_column, end_line);
+        self.scroll_sixels(region, 0, 1, self.is_full_screen_scroll());
 
         for layer in &mut self.layers {
             for x in start_column..=end_column {
@@ -300,6 +379,13 @@ impl Buffer {
 
         let start_column = self.get_first_editable_column() as usize;
         let end_column = self.get_last_editable_column() as usize + 1;
+        let region = Rectangle::from_coords(
+            start_column as i32,
+            start_line,
+            end_column as i32 - 1,
+            end_line,
+        );
+        self.scroll_sixels(region, -1, 0, false);
 
         for layer in &mut self.layers {
             for i in start_line..=end_line {
@@ -319,6 +405,9 @@ impl Buffer {
 
         let start_column = self.get_first_editable_column() as usize;
         let end_column = self.get_last_editable_column() as usize;
+        let region =
+            Rectangle::from_coords(start_column as i32, start_line, end_column as i32, end_line);
+        self.scroll_sixels(region, 1, 0, false);
 
         for layer in &mut self.layers {
             for i in start_line..=end_line {
@@ -344,10 +433,13 @@ impl Buffer {
         ch.attribute = caret.attr;
 
         for y in pos.y..self.get_last_visible_line() {
+            self.set_line_attribute(y, LineAttribute::Single);
             for x in 0..self.get_buffer_width() {
                 self.set_char(0, Position::new(x, y), Some(ch));
             }
         }
+        let height = self.get_last_visible_line() - pos.y;
+        self.erase_sixels(Rectangle::from(0, pos.y, self.get_buffer_width(), height));
     }
 
     fn clear_buffer_up(&mut self, caret: &Caret) {
@@ -355,11 +447,20 @@ impl Buffer {
         let mut ch = AttributedChar::default();
         ch.attribute = caret.attr;
 
-        for y in self.get_first_visible_line()..pos.y {
+        let first_line = self.get_first_visible_line();
+        for y in first_line..pos.y {
+            self.set_line_attribute(y, LineAttribute::Single);
             for x in 0..self.get_buffer_width() {
                 self.set_char(0, Position::new(x, y), Some(ch));
             }
         }
+        let height = pos.y - first_line;
+        self.erase_sixels(Rectangle::from(
+            0,
+            first_line,
+            self.get_buffer_width(),
+            height,
+        ));
     }
 
     fn clear_line(&mut self, caret: &Caret) {
@@ -370,16 +471,20 @@ impl Buffer {
             pos.x = x;
             self.set_char(0, pos, Some(ch));
         }
+        self.erase_sixels(Rectangle::from(0, pos.y, self.get_buffer_width(), 1));
     }
 
     fn clear_line_end(&mut self, caret: &Caret) {
         let mut pos = caret.get_position();
         let mut ch = AttributedChar::default();
         ch.attribute = caret.attr;
-        for x in pos.x..self.get_buffer_width() {
+        let start = pos.x;
+        for x in start..self.get_buffer_width() {
             pos.x = x;
             self.set_char(0, pos, Some(ch));
         }
+        let width = self.get_buffer_width() - start;
+        self.erase_sixels(Rectangle::from(start, pos.y, width, 1));
     }
 
     fn clear_line_start(&mut self, caret: &Caret) {
@@ -390,12 +495,20 @@ impl Buffer {
             pos.x = x;
             self.set_char(0, pos, Some(ch));
         }
+        self.erase_sixels(Rectangle::from(0, pos.y, caret.get_position().x, 1));
     }
 
     fn remove_terminal_line(&mut self, line: i32) {
         if line >= self.layers[0].lines.len() as i32 {
             return;
         }
+        let region = Rectangle::from_coords(
+            0,
+            line,
+            self.get_buffer_width() - 1,
+            self.get_last_editable_line().max(line),
+        );
+        self.scroll_sixels(region, 0, -1, false);
         self.layers[0].remove_line(line);
         if let Some((_, end)) = self.terminal_state.margins_up_down {
             self.layers[0].insert_line(end, Line::new());
@@ -403,6 +516,13 @@ impl Buffer {
     }
 
     fn insert_terminal_line(&mut self, line: i32) {
+        let region = Rectangle::from_coords(
+            0,
+            line,
+            self.get_buffer_width() - 1,
+            self.get_last_editable_line().max(line),
+        );
+        self.scroll_sixels(region, 0, 1, false);
         if let Some((_, end)) = self.terminal_state.margins_up_down {
             if end < self.layers[0].lines.len() as i32 {
                 self.layers[0].lines.remove(end as usize);
@@ -410,6 +530,138 @@ impl Buffer {
         }
         self.layers[0].insert_line(line, Line::new());
     }
+
+    /// Number of text lines a sixel covers.
+    pub fn get_sixel_line_count(&self, sixel: &Sixel) -> i32 {
+        self.get_sixel_cells(sixel).size.height
+    }
+
+    /// Cells covered by a sixel, partially covered cells count.
+    fn get_sixel_cells(&self, sixel: &Sixel) -> Rectangle {
+        let font_size = self.get_font_dimensions();
+        let font_width = u32::from(font_size.width.max(1));
+        let font_height = u32::from(font_size.height.max(1));
+        Rectangle::from(
+            sixel.position.x,
+            sixel.position.y,
+            sixel.width().div_ceil(font_width) as i32,
+            (sixel.get_screen_height().max(0) as u32).div_ceil(font_height) as i32,
+        )
+    }
+
+    /// Gets the part of `area` within a sixel in picture pixels.
+    fn get_sixel_pixels(&self, sixel: &Sixel, area: Rectangle) -> Rectangle {
+        let font_size = self.get_font_dimensions();
+        let font_width = i32::from(font_size.width);
+        let font_height = i32::from(font_size.height);
+        let scale = (sixel.get_screen_height() / (sixel.height() as i32).max(1)).max(1);
+        Rectangle::from(
+            (area.start.x - sixel.position.x) * font_width,
+            (area.start.y - sixel.position.y) * font_height / scale,
+            area.size.width * font_width,
+            (area.size.height * font_height + scale - 1) / scale,
+        )
+    }
+
+    /// Moves the sixels in a scrolled region of cells together with the text, parts moved out of the region are cut off.
+    /// On full screen scrolls pictures are only dropped when they leave the screen, the ones scrolled off the top go to the scrollback.
+    /// Kitty image placements are moved as well.
+    fn scroll_sixels(&mut self, region: Rectangle, dx: i32, dy: i32, full_screen: bool) {
+        self.scroll_kitty_placements(region, dx, dy, full_screen);
+        self.settle_sixels();
+        if self.layers[0].sixels.is_empty() {
+            return;
+        }
+        let first_line = self.get_first_visible_line();
+        let last_line = self.get_last_visible_line();
+        let feed_scrollback = full_screen && !self.is_alternate_screen();
+
+        for mut sixel in std::mem::take(&mut self.layers[0].sixels) {
+            let cells = self.get_sixel_cells(&sixel);
+            if intersect(&cells, &region).is_none() {
+                self.layers[0].sixels.push(sixel);
+                continue;
+            }
+            sixel.position = sixel.position + Position::new(dx, dy);
+            if full_screen {
+                if sixel.position.y + cells.size.height <= first_line {
+                    if feed_scrollback {
+                        sixel.position.y += self.scrollback.len() as i32 - first_line;
+                        self.scrollback.push_sixel(sixel);
+                    }
+                } else if sixel.position.y < last_line {
+                    self.layers[0].sixels.push(sixel);
+                }
+                continue;
+            }
+
+            let moved = Rectangle::new(sixel.position, cells.size);
+            let Some(visible) = intersect(&moved, &region) else {
+                continue;
+            };
+            if sixel.crop(self.get_sixel_pixels(&sixel, visible)) {
+                sixel.position = visible.start;
+                self.layers[0].sixels.push(sixel);
+            }
+        }
+    }
+
+    /// Moves the kitty image placements starting in a scrolled region of cells together with the text.
+    /// Placements are removed once they leave the screen or the region.
+    fn scroll_kitty_placements(&mut self, region: Rectangle, dx: i32, dy: i32, full_screen: bool) {
+        if self.layers[0].kitty_placements.is_empty() {
+            return;
+        }
+        let first_line = self.get_first_visible_line();
+        let last_line = self.get_last_visible_line();
+        for mut placement in std::mem::take(&mut self.layers[0].kitty_placements) {
+            let anchor = Rectangle::from(placement.position.x, placement.position.y, 1, 1);
+            if intersect(&anchor, &region).is_none() {
+                self.layers[0].kitty_placements.push(placement);
+                continue;
+            }
+            placement.position = placement.position + Position::new(dx, dy);
+            let cells = self.get_kitty_placement_cells(&placement);
+            let is_visible = if full_screen {
+                placement.position.y + cells.size.height > first_line
+                    && placement.position.y < last_line
+            } else {
+                intersect(&cells, &region).is_some()
+            };
+            if is_visible {
+                self.layers[0].kitty_placements.push(placement);
+            }
+        }
+    }
+
+    /// Erases the sixel pixels in an area of cells, pictures that are erased completely are removed.
+    fn erase_sixels(&mut self, area: Rectangle) {
+        self.settle_sixels();
+        if self.layers[0].sixels.is_empty() {
+            return;
+        }
+        for mut sixel in std::mem::take(&mut self.layers[0].sixels) {
+            let cells = self.get_sixel_cells(&sixel);
+            if intersect(&cells, &area).is_none()
+                || sixel.erase(self.get_sixel_pixels(&sixel, area))
+            {
+                self.layers[0].sixels.push(sixel);
+            }
+        }
+    }
+}
+
+/// Intersection of two rectangles, `None` if they don't overlap.
+fn intersect(a: &Rectangle, b: &Rectangle) -> Option<Rectangle> {
+    let left = a.start.x.max(b.start.x);
+    let top = a.start.y.max(b.start.y);
+    let right = (a.start.x + a.size.width).min(b.start.x + b.size.width);
+    let bottom = (a.start.y + a.size.height).min(b.start.y + b.size.height);
+    if left < right && top < bottom {
+        Some(Rectangle::from(left, top, right - left, bottom - top))
+    } else {
+        None
+    }
 }
 
 fn _get_string_from_buffer(buf: &Buffer) -> String {
@@ -6,6 +6,7 @@ pub enum ParserError {
     InvalidBuffer,
     UnsupportedEscapeSequence(String),
     UnsupportedDCSSequence(String),
+    UnsupportedOSCSequence(String),
     UnsupportedCustomCommand(i32),
     Description(&'static str),
     UnsupportedControlCode(u32),
@@ -19,6 +20,7 @@ pub enum ParserError {
     InvalidPictureSize,
 
     InvalidRipAnsiQuery(i32),
+    InvalidRipParameters(String),
 
     Error(String),
 }
@@ -33,6 +35,9 @@ impl std::fmt::Display for ParserError {
             ParserError::UnsupportedDCSSequence(seq) => {
                 write!(f, "unsupported DCS sequence {seq}")
             }
+            ParserError::UnsupportedOSCSequence(seq) => {
+                write!(f, "unsupported OSC sequence {seq}")
+            }
             ParserError::Description(str) => write!(f, "{str}"),
             ParserError::UnsupportedControlCode(code) => {
                 write!(f, "unsupported control code {}", *code)
@@ -58,6 +63,9 @@ impl std::fmt::Display for ParserError {
             ParserError::ErrorInSixelEngine(err) => write!(f, "sixel engine error: {err}"),
             ParserError::InvalidPictureSize => write!(f, "invalid sixel picture size description"),
             ParserError::InvalidRipAnsiQuery(i) => write!(f, "invalid rip ansi query <esc>[{i}!"),
+            ParserError::InvalidRipParameters(params) => {
+                write!(f, "invalid rip command parameters: {params}")
+            }
             ParserError::Error(err) => write!(f, "Parse error: {err}"),
         }
     }
@@ -68,6 +68,39 @@ impl BufferParser for Parser {
             _ => self.ansi_parser.print_char(buf, caret, ch),
         }
     }
+
+    fn print_bytes(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        data: &[u8],
+    ) -> EngineResult<Vec<CallbackAction>> {
+        let mut actions = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            if !self.pcb_code && !self.pcb_color {
+                // everything up to the next @ code is handled by the ansi parser
+                let run = data[i..]
+                    .iter()
+                    .position(|b| *b == b'@')
+                    .unwrap_or(data.len() - i);
+                if run > 0 {
+                    actions.extend(
+                        self.ansi_parser
+                            .print_bytes(buf, caret, &data[i..i + run])?,
+                    );
+                    i += run;
+                    continue;
+                }
+            }
+            super::push_action(
+                &mut actions,
+                self.print_char(buf, caret, char::from(data[i]))?,
+            );
+            i += 1;
+        }
+        Ok(actions)
+    }
 }
 
 fn conv_ch(ch: char) -> u8 {
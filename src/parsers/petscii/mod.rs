@@ -19,6 +19,15 @@ impl Parser {
         }
     }
 
+    fn print_screen_code(&self, buf: &mut Buffer, caret: &mut Caret, tch: u8) {
+        let mut ch = AttributedChar::new(
+            char::from_u32(self.handle_reverse_mode(tch) as u32).unwrap(),
+            caret.attr,
+        );
+        ch.set_font_page(usize::from(self.shift_mode));
+        buf.print_char(caret, ch);
+    }
+
     /// .
     ///
     /// # Errors
@@ -240,25 +249,44 @@ impl BufferParser for Parser {
             0x9F => caret.set_foreground(CYAN),
             0xFF => buf.print_value(caret, 94), // PI character
             _ => {
-                let tch = match ch {
-                    0x20..=0x3F => ch,
-                    0x40..=0x5F | 0xA0..=0xBF => ch - 0x40,
-                    0x60..=0x7F => ch - 0x20,
-                    0xC0..=0xFE => ch - 0x80,
-                    _ => {
-                        return Err(Box::new(ParserError::UnsupportedControlCode(ch as u32)));
-                    }
+                let Some(tch) = screen_code(ch) else {
+                    return Err(Box::new(ParserError::UnsupportedControlCode(ch as u32)));
                 };
-                let mut ch = AttributedChar::new(
-                    char::from_u32(self.handle_reverse_mode(tch) as u32).unwrap(),
-                    caret.attr,
-                );
-                ch.set_font_page(usize::from(self.shift_mode));
-                buf.print_char(caret, ch);
+                self.print_screen_code(buf, caret, tch);
             }
         }
         Ok(CallbackAction::None)
     }
+
+    fn print_bytes(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        data: &[u8],
+    ) -> EngineResult<Vec<CallbackAction>> {
+        let mut actions = Vec::new();
+        for b in data {
+            if !self.got_esc {
+                if let Some(tch) = screen_code(*b) {
+                    self.print_screen_code(buf, caret, tch);
+                    continue;
+                }
+            }
+            super::push_action(&mut actions, self.print_char(buf, caret, char::from(*b))?);
+        }
+        Ok(actions)
+    }
+}
+
+/// Maps a printable PETSCII code to its screen code.
+fn screen_code(ch: u8) -> Option<u8> {
+    match ch {
+        0x20..=0x3F => Some(ch),
+        0x40..=0x5F | 0xA0..=0xBF => Some(ch - 0x40),
+        0x60..=0x7F => Some(ch - 0x20),
+        0xC0..=0xFE => Some(ch - 0x80),
+        _ => None,
+    }
 }
 
 lazy_static::lazy_static! {
@@ -0,0 +1,762 @@
+use crate::{BitFont, Palette, Position, Rectangle, Size, EGA_PALETTE};
+
+use super::{RipImage, WriteMode};
+
+/// Default `RIPscrip` screen width (EGA 640x350).
+pub const RIP_SCREEN_WIDTH: i32 = 640;
+/// Default `RIPscrip` screen height (EGA 640x350).
+pub const RIP_SCREEN_HEIGHT: i32 = 350;
+
+/// EGA palette registers used by `RIPscrip` after a reset.
+const DEFAULT_EGA_REGISTERS: [usize; 16] =
+    [0, 1, 2, 3, 4, 5, 20, 7, 56, 57, 58, 59, 60, 61, 62, 63];
+
+const DEFAULT_BITFONT: &str = "IBM VGA50";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineStyle {
+    #[default]
+    Solid,
+    Dotted,
+    Center,
+    Dashed,
+    User,
+}
+
+impl LineStyle {
+    pub fn from(style: i32) -> Self {
+        match style {
+            1 => LineStyle::Dotted,
+            2 => LineStyle::Center,
+            3 => LineStyle::Dashed,
+            4 => LineStyle::User,
+            _ => LineStyle::Solid,
+        }
+    }
+
+    fn get_pattern(self, user_pattern: u16) -> u16 {
+        match self {
+            LineStyle::Solid => 0xFFFF,
+            LineStyle::Dotted => 0xCCCC,
+            LineStyle::Center => 0xF878,
+            LineStyle::Dashed => 0xF8F8,
+            LineStyle::User => user_pattern,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillStyle {
+    Empty,
+    #[default]
+    Solid,
+    Line,
+    LtSlash,
+    Slash,
+    BkSlash,
+    LtBkSlash,
+    Hatch,
+    XHatch,
+    Interleave,
+    WideDot,
+    CloseDot,
+    User,
+}
+
+impl FillStyle {
+    pub fn from(style: i32) -> Self {
+        match style {
+            0 => FillStyle::Empty,
+            2 => FillStyle::Line,
+            3 => FillStyle::LtSlash,
+            4 => FillStyle::Slash,
+            5 => FillStyle::BkSlash,
+            6 => FillStyle::LtBkSlash,
+            7 => FillStyle::Hatch,
+            8 => FillStyle::XHatch,
+            9 => FillStyle::Interleave,
+            10 => FillStyle::WideDot,
+            11 => FillStyle::CloseDot,
+            12 => FillStyle::User,
+            _ => FillStyle::Solid,
+        }
+    }
+
+    fn get_pattern(self, user_pattern: [u8; 8]) -> [u8; 8] {
+        match self {
+            FillStyle::Empty => [0x00; 8],
+            FillStyle::Solid => [0xFF; 8],
+            FillStyle::Line => [0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00],
+            FillStyle::LtSlash => [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80],
+            FillStyle::Slash => [0xE0, 0xC1, 0x83, 0x07, 0x0E, 0x1C, 0x38, 0x70],
+            FillStyle::BkSlash => [0xF0, 0x78, 0x3C, 0x1E, 0x0F, 0x87, 0xC3, 0xE1],
+            FillStyle::LtBkSlash => [0xA5, 0xD2, 0x69, 0xB4, 0x5A, 0x2D, 0x96, 0x4B],
+            FillStyle::Hatch => [0xFF, 0x88, 0x88, 0x88, 0xFF, 0x88, 0x88, 0x88],
+            FillStyle::XHatch => [0x81, 0x42, 0x24, 0x18, 0x18, 0x24, 0x42, 0x81],
+            FillStyle::Interleave => [0xCC, 0x33, 0xCC, 0x33, 0xCC, 0x33, 0xCC, 0x33],
+            FillStyle::WideDot => [0x80, 0x00, 0x08, 0x00, 0x80, 0x00, 0x08, 0x00],
+            FillStyle::CloseDot => [0x88, 0x00, 0x22, 0x00, 0x88, 0x00, 0x22, 0x00],
+            FillStyle::User => user_pattern,
+        }
+    }
+}
+
+/// How `RIP_PUT_IMAGE` combines image pixels with the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageOp {
+    #[default]
+    Copy,
+    Xor,
+    Or,
+    And,
+    Not,
+}
+
+impl ImageOp {
+    pub fn from(mode: i32) -> Self {
+        match mode {
+            1 => ImageOp::Xor,
+            2 => ImageOp::Or,
+            3 => ImageOp::And,
+            4 => ImageOp::Not,
+            _ => ImageOp::Copy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Horizontal,
+    Vertical,
+}
+
+/// A small Borland Graphics Interface emulation - `RIPscrip` is a serialized stream of BGI calls.
+///
+/// The surface stores EGA color indices (0-15), the palette maps them to RGB.
+/// All drawing coordinates are relative to the current viewport and clipped to it.
+#[derive(Debug, Clone)]
+pub struct Bgi {
+    pub color: u8,
+    pub bk_color: u8,
+    pub write_mode: WriteMode,
+
+    pub line_style: LineStyle,
+    pub user_line_pattern: u16,
+    pub line_thickness: i32,
+
+    pub fill_style: FillStyle,
+    pub fill_color: u8,
+    pub user_fill_pattern: [u8; 8],
+
+    pub font: i32,
+    pub direction: Direction,
+    pub char_size: i32,
+
+    pub palette: Palette,
+
+    size: Size<i32>,
+    viewport: Rectangle,
+    current_pos: Position,
+    pixels: Vec<u8>,
+    bitfont: Option<BitFont>,
+}
+
+impl Default for Bgi {
+    fn default() -> Self {
+        Self::new(RIP_SCREEN_WIDTH, RIP_SCREEN_HEIGHT)
+    }
+}
+
+impl Bgi {
+    pub fn new(width: i32, height: i32) -> Self {
+        let mut res = Self {
+            color: 15,
+            bk_color: 0,
+            write_mode: WriteMode::Normal,
+            line_style: LineStyle::Solid,
+            user_line_pattern: 0xFFFF,
+            line_thickness: 1,
+            fill_style: FillStyle::Solid,
+            fill_color: 15,
+            user_fill_pattern: [0xFF; 8],
+            font: 0,
+            direction: Direction::Horizontal,
+            char_size: 1,
+            palette: Palette::new(),
+            size: Size::new(width, height),
+            viewport: Rectangle::from(0, 0, width, height),
+            current_pos: Position::default(),
+            pixels: vec![0; (width * height) as usize],
+            bitfont: BitFont::from_name(DEFAULT_BITFONT).ok(),
+        };
+        res.reset_palette();
+        res
+    }
+
+    pub fn width(&self) -> i32 {
+        self.size.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.size.height
+    }
+
+    /// Color indices of the whole surface, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Gets the color index at an absolute screen position.
+    pub fn get_pixel(&self, x: i32, y: i32) -> u8 {
+        if x < 0 || y < 0 || x >= self.size.width || y >= self.size.height {
+            return 0;
+        }
+        self.pixels[(y * self.size.width + x) as usize]
+    }
+
+    /// Inverts the colors of a rectangle in absolute screen coordinates, inverting it twice restores it.
+    pub fn invert(&mut self, rect: Rectangle) {
+        let x0 = rect.start.x.clamp(0, self.size.width);
+        let x1 = (rect.start.x + rect.size.width).clamp(x0, self.size.width);
+        for y in rect.start.y.max(0)..(rect.start.y + rect.size.height).min(self.size.height) {
+            let o = (y * self.size.width) as usize;
+            for pixel in &mut self.pixels[o + x0 as usize..o + x1 as usize] {
+                *pixel ^= 0x0F;
+            }
+        }
+    }
+
+    pub fn get_viewport(&self) -> Rectangle {
+        self.viewport
+    }
+
+    /// Sets the viewport, `rect` is given in absolute screen coordinates and clipped to the screen.
+    pub fn set_viewport(&mut self, rect: Rectangle) {
+        let x0 = rect.start.x.clamp(0, self.size.width - 1);
+        let y0 = rect.start.y.clamp(0, self.size.height - 1);
+        let x1 = (rect.start.x + rect.size.width).clamp(x0 + 1, self.size.width);
+        let y1 = (rect.start.y + rect.size.height).clamp(y0 + 1, self.size.height);
+        self.viewport = Rectangle::from(x0, y0, x1 - x0, y1 - y0);
+        self.current_pos = Position::default();
+    }
+
+    pub fn reset_viewport(&mut self) {
+        self.viewport = Rectangle::from(0, 0, self.size.width, self.size.height);
+        self.current_pos = Position::default();
+    }
+
+    pub fn get_current_pos(&self) -> Position {
+        self.current_pos
+    }
+
+    pub fn move_to(&mut self, x: i32, y: i32) {
+        self.current_pos = Position::new(x, y);
+    }
+
+    /// Restores the `RIPscrip` default EGA palette.
+    pub fn reset_palette(&mut self) {
+        self.palette.colors = DEFAULT_EGA_REGISTERS
+            .iter()
+            .map(|i| EGA_PALETTE[*i])
+            .collect();
+    }
+
+    /// Maps palette entry `color` to one of the 64 EGA colors.
+    pub fn set_palette(&mut self, color: usize, ega_color: usize) {
+        if color < self.palette.colors.len() {
+            self.palette.colors[color] = EGA_PALETTE[ega_color % EGA_PALETTE.len()];
+        }
+    }
+
+    /// Clears the whole screen with the background color.
+    pub fn clear_device(&mut self) {
+        self.pixels.fill(self.bk_color);
+        self.current_pos = Position::default();
+    }
+
+    /// Clears the viewport with the background color.
+    pub fn clear_viewport(&mut self) {
+        let vp = self.viewport;
+        for y in vp.start.y..vp.start.y + vp.size.height {
+            let o = (y * self.size.width) as usize;
+            self.pixels[o + vp.start.x as usize..o + (vp.start.x + vp.size.width) as usize]
+                .fill(self.bk_color);
+        }
+        self.current_pos = Position::default();
+    }
+
+    fn set_raw_pixel(&mut self, x: i32, y: i32, color: u8, write_mode: WriteMode) {
+        let vp = self.viewport;
+        if x < 0 || y < 0 || x >= vp.size.width || y >= vp.size.height {
+            return;
+        }
+        let o = ((vp.start.y + y) * self.size.width + vp.start.x + x) as usize;
+        match write_mode {
+            WriteMode::Normal => self.pixels[o] = color,
+            WriteMode::Xor => self.pixels[o] ^= color,
+        }
+    }
+
+    /// Gets the color index at a viewport relative position.
+    pub fn get_viewport_pixel(&self, x: i32, y: i32) -> Option<u8> {
+        let vp = self.viewport;
+        if x < 0 || y < 0 || x >= vp.size.width || y >= vp.size.height {
+            return None;
+        }
+        Some(self.pixels[((vp.start.y + y) * self.size.width + vp.start.x + x) as usize])
+    }
+
+    pub fn put_pixel(&mut self, x: i32, y: i32, color: u8) {
+        self.set_raw_pixel(x, y, color, WriteMode::Normal);
+    }
+
+    /// Copies the viewport relative rectangle (inclusive) into an image.
+    pub fn get_image(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> RipImage {
+        let (x0, x1) = (x0.min(x1), x0.max(x1));
+        let (y0, y1) = (y0.min(y1), y0.max(y1));
+        let mut res = RipImage::new(x1 - x0 + 1, y1 - y0 + 1);
+        for y in 0..res.height {
+            for x in 0..res.width {
+                res.pixels[(y * res.width + x) as usize] =
+                    self.get_viewport_pixel(x0 + x, y0 + y).unwrap_or_default();
+            }
+        }
+        res
+    }
+
+    /// Draws an image with its upper left corner at a viewport relative position.
+    pub fn put_image(&mut self, x: i32, y: i32, image: &RipImage, op: ImageOp) {
+        for iy in 0..image.height {
+            for ix in 0..image.width {
+                let Some(old) = self.get_viewport_pixel(x + ix, y + iy) else {
+                    continue;
+                };
+                let src = image.get_pixel(ix, iy);
+                let color = match op {
+                    ImageOp::Copy => src,
+                    ImageOp::Xor => old ^ src,
+                    ImageOp::Or => old | src,
+                    ImageOp::And => old & src,
+                    ImageOp::Not => !src & 0x0F,
+                };
+                self.set_raw_pixel(x + ix, y + iy, color, WriteMode::Normal);
+            }
+        }
+    }
+
+    fn fill_pixel(&mut self, x: i32, y: i32) {
+        let vp = self.viewport;
+        let pattern = self.fill_style.get_pattern(self.user_fill_pattern);
+        let (ax, ay) = (vp.start.x + x, vp.start.y + y);
+        let color = if pattern[(ay & 7) as usize] & (0x80 >> (ax & 7)) == 0 {
+            self.bk_color
+        } else {
+            self.fill_color
+        };
+        self.set_raw_pixel(x, y, color, WriteMode::Normal);
+    }
+
+    fn plot_thick(&mut self, x: i32, y: i32, x_major: bool) {
+        self.set_raw_pixel(x, y, self.color, self.write_mode);
+        if self.line_thickness > 1 {
+            if x_major {
+                self.set_raw_pixel(x, y - 1, self.color, self.write_mode);
+                self.set_raw_pixel(x, y + 1, self.color, self.write_mode);
+            } else {
+                self.set_raw_pixel(x - 1, y, self.color, self.write_mode);
+                self.set_raw_pixel(x + 1, y, self.color, self.write_mode);
+            }
+        }
+    }
+
+    /// Draws a line using the current color, line style, thickness and write mode.
+    pub fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        let pattern = self.line_style.get_pattern(self.user_line_pattern);
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let x_major = dx >= -dy;
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+        let mut i = 0;
+        loop {
+            if pattern & (0x8000 >> (i % 16)) != 0 {
+                self.plot_thick(x, y, x_major);
+            }
+            i += 1;
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draws a line from the current position and updates it.
+    pub fn line_to(&mut self, x: i32, y: i32) {
+        let pos = self.current_pos;
+        self.line(pos.x, pos.y, x, y);
+        self.current_pos = Position::new(x, y);
+    }
+
+    pub fn rectangle(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        self.line(x0, y0, x1, y0);
+        self.line(x1, y0, x1, y1);
+        self.line(x1, y1, x0, y1);
+        self.line(x0, y1, x0, y0);
+    }
+
+    /// Fills a rectangle with the current fill pattern & color, no outline is drawn.
+    pub fn bar(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        for y in y0.min(y1)..=y0.max(y1) {
+            for x in x0.min(x1)..=x0.max(x1) {
+                self.fill_pixel(x, y);
+            }
+        }
+    }
+
+    /// Draws a polyline, set `close` to connect the last point with the first one.
+    pub fn draw_poly(&mut self, points: &[Position], close: bool) {
+        for p in points.windows(2) {
+            self.line(p[0].x, p[0].y, p[1].x, p[1].y);
+        }
+        if close && points.len() > 2 {
+            let (first, last) = (points[0], points[points.len() - 1]);
+            self.line(last.x, last.y, first.x, first.y);
+        }
+    }
+
+    /// Fills a polygon with the current fill pattern and draws the outline with the current color.
+    pub fn fill_poly(&mut self, points: &[Position]) {
+        self.fill_poly_interior(points);
+        self.draw_poly(points, true);
+    }
+
+    fn fill_poly_interior(&mut self, points: &[Position]) {
+        if points.len() < 3 {
+            return;
+        }
+        let min_y = points.iter().map(|p| p.y).min().unwrap_or(0);
+        let max_y = points.iter().map(|p| p.y).max().unwrap_or(0);
+        let mut nodes = Vec::new();
+        for y in min_y..=max_y {
+            nodes.clear();
+            let scan_y = y as f64 + 0.5;
+            let mut j = points.len() - 1;
+            for i in 0..points.len() {
+                let (a, b) = (points[i], points[j]);
+                let (ay, by) = (a.y as f64, b.y as f64);
+                if (ay < scan_y && by >= scan_y) || (by < scan_y && ay >= scan_y) {
+                    nodes.push(a.x as f64 + (scan_y - ay) / (by - ay) * (b.x - a.x) as f64);
+                }
+                j = i;
+            }
+            nodes.sort_by(f64::total_cmp);
+            for pair in nodes.chunks_exact(2) {
+                let start = pair[0].round() as i32;
+                let end = pair[1].round() as i32;
+                for x in start..=end {
+                    self.fill_pixel(x, y);
+                }
+            }
+        }
+    }
+
+    /// Draws an elliptical arc, angles are in degrees counter clockwise starting at 3 o'clock.
+    pub fn ellipse(&mut self, x: i32, y: i32, start_angle: i32, end_angle: i32, rx: i32, ry: i32) {
+        let (start, end) = normalize_angles(start_angle, end_angle);
+        self.draw_ellipse_outline(x, y, start, end, rx, ry);
+        if self.line_thickness > 1 {
+            self.draw_ellipse_outline(x, y, start, end, rx + 1, ry + 1);
+            if rx > 0 && ry > 0 {
+                self.draw_ellipse_outline(x, y, start, end, rx - 1, ry - 1);
+            }
+        }
+    }
+
+    fn draw_ellipse_outline(&mut self, cx: i32, cy: i32, start: f64, end: f64, rx: i32, ry: i32) {
+        let full = end - start >= 360.0;
+        for p in ellipse_points(rx.abs(), ry.abs()) {
+            if full || angle_in_range(point_angle(p.x, p.y, rx, ry), start, end) {
+                self.set_raw_pixel(cx + p.x, cy - p.y, self.color, self.write_mode);
+            }
+        }
+    }
+
+    pub fn arc(&mut self, x: i32, y: i32, start_angle: i32, end_angle: i32, radius: i32) {
+        self.ellipse(x, y, start_angle, end_angle, radius, radius);
+    }
+
+    pub fn circle(&mut self, x: i32, y: i32, radius: i32) {
+        self.ellipse(x, y, 0, 360, radius, radius);
+    }
+
+    /// Draws a filled ellipse with outline.
+    pub fn fill_ellipse(&mut self, x: i32, y: i32, rx: i32, ry: i32) {
+        let points = arc_polygon(x, y, 0.0, 360.0, rx, ry);
+        self.fill_poly_interior(&points);
+        self.ellipse(x, y, 0, 360, rx, ry);
+    }
+
+    /// Draws a filled elliptical pie slice with outline.
+    pub fn sector(&mut self, x: i32, y: i32, start_angle: i32, end_angle: i32, rx: i32, ry: i32) {
+        let (start, end) = normalize_angles(start_angle, end_angle);
+        let mut points = vec![Position::new(x, y)];
+        points.extend(arc_polygon(x, y, start, end, rx, ry));
+        self.fill_poly_interior(&points);
+
+        self.ellipse(x, y, start_angle, end_angle, rx, ry);
+        let p1 = arc_point(x, y, start, rx, ry);
+        let p2 = arc_point(x, y, end, rx, ry);
+        self.line(x, y, p1.x, p1.y);
+        self.line(x, y, p2.x, p2.y);
+    }
+
+    pub fn pie_slice(&mut self, x: i32, y: i32, start_angle: i32, end_angle: i32, radius: i32) {
+        self.sector(x, y, start_angle, end_angle, radius, radius);
+    }
+
+    /// Draws a cubic bezier curve through `count` line segments.
+    pub fn bezier(&mut self, points: &[Position; 4], count: i32) {
+        let count = count.max(1);
+        let mut last = points[0];
+        for i in 1..=count {
+            let t = i as f64 / count as f64;
+            let u = 1.0 - t;
+            let (b0, b1, b2, b3) = (u * u * u, 3.0 * t * u * u, 3.0 * t * t * u, t * t * t);
+            let x = b0 * points[0].x as f64
+                + b1 * points[1].x as f64
+                + b2 * points[2].x as f64
+                + b3 * points[3].x as f64;
+            let y = b0 * points[0].y as f64
+                + b1 * points[1].y as f64
+                + b2 * points[2].y as f64
+                + b3 * points[3].y as f64;
+            let next = Position::new(x.round() as i32, y.round() as i32);
+            self.line(last.x, last.y, next.x, next.y);
+            last = next;
+        }
+    }
+
+    /// Fills the area around (x, y) bounded by `border` with the current fill pattern.
+    pub fn flood_fill(&mut self, x: i32, y: i32, border: u8) {
+        let vp = self.viewport;
+        match self.get_viewport_pixel(x, y) {
+            Some(c) if c != border => {}
+            _ => return,
+        }
+        let w = vp.size.width as usize;
+        let mut visited = vec![false; w * vp.size.height as usize];
+        let mut region = Vec::new();
+        let mut stack = vec![Position::new(x, y)];
+        while let Some(p) = stack.pop() {
+            match self.get_viewport_pixel(p.x, p.y) {
+                Some(c) if c != border => {}
+                _ => continue,
+            }
+            let idx = p.y as usize * w + p.x as usize;
+            if visited[idx] {
+                continue;
+            }
+            visited[idx] = true;
+            region.push(p);
+            stack.push(Position::new(p.x + 1, p.y));
+            stack.push(Position::new(p.x - 1, p.y));
+            stack.push(Position::new(p.x, p.y + 1));
+            stack.push(Position::new(p.x, p.y - 1));
+        }
+        for p in region {
+            self.fill_pixel(p.x, p.y);
+        }
+    }
+
+    fn char_dimensions(&self) -> Size<i32> {
+        let size = self.char_size.max(1);
+        if let Some(font) = &self.bitfont {
+            Size::new(
+                font.size.width as i32 * size,
+                font.size.height as i32 * size,
+            )
+        } else {
+            Size::new(8 * size, 8 * size)
+        }
+    }
+
+    /// Height in pixels of a text line in the current font.
+    pub fn get_text_height(&self) -> i32 {
+        self.char_dimensions().height
+    }
+
+    /// Width in pixels of `text` in the current font.
+    pub fn get_text_width(&self, text: &str) -> i32 {
+        self.char_dimensions().width * text.chars().count() as i32
+    }
+
+    /// Draws text at the current position and advances it.
+    /// Only the bitmap font is available, stroked fonts are rendered with it scaled to the requested size.
+    pub fn out_text(&mut self, text: &str) {
+        let pos = self.current_pos;
+        self.out_text_xy(pos.x, pos.y, text);
+        let dim = self.char_dimensions();
+        let len = text.chars().count() as i32;
+        match self.direction {
+            Direction::Horizontal => self.current_pos.x += len * dim.width,
+            Direction::Vertical => self.current_pos.y -= len * dim.width,
+        }
+    }
+
+    pub fn out_text_xy(&mut self, x: i32, y: i32, text: &str) {
+        let Some(font) = self.bitfont.take() else {
+            return;
+        };
+        let scale = self.char_size.max(1);
+        let char_width = font.size.width as i32 * scale;
+        for (i, ch) in text.chars().enumerate() {
+            let Some(glyph) = font.get_glyph(ch) else {
+                continue;
+            };
+            let offset = i as i32 * char_width;
+            for (gy, row) in glyph.data.iter().enumerate() {
+                for gx in 0..font.size.width as i32 {
+                    if row & (0x80 >> gx) == 0 {
+                        continue;
+                    }
+                    for sy in 0..scale {
+                        for sx in 0..scale {
+                            let px = gx * scale + sx;
+                            let py = gy as i32 * scale + sy;
+                            match self.direction {
+                                Direction::Horizontal => {
+                                    self.put_pixel(x + offset + px, y + py, self.color);
+                                }
+                                Direction::Vertical => {
+                                    self.put_pixel(x + py, y - offset - px, self.color);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.bitfont = Some(font);
+    }
+}
+
+fn normalize_angles(start_angle: i32, end_angle: i32) -> (f64, f64) {
+    let start = start_angle.rem_euclid(360) as f64;
+    let mut end = end_angle as f64;
+    if end_angle - start_angle >= 360 {
+        return (start, start + 360.0);
+    }
+    end = end.rem_euclid(360.0);
+    if end <= start {
+        end += 360.0;
+    }
+    (start, end)
+}
+
+fn point_angle(x: i32, y: i32, rx: i32, ry: i32) -> f64 {
+    let a = (y as f64 / ry.max(1) as f64)
+        .atan2(x as f64 / rx.max(1) as f64)
+        .to_degrees();
+    a.rem_euclid(360.0)
+}
+
+fn angle_in_range(angle: f64, start: f64, end: f64) -> bool {
+    (start..=end).contains(&angle) || (start..=end).contains(&(angle + 360.0))
+}
+
+fn arc_point(x: i32, y: i32, angle: f64, rx: i32, ry: i32) -> Position {
+    let a = angle.to_radians();
+    Position::new(
+        x + (rx as f64 * a.cos()).round() as i32,
+        y - (ry as f64 * a.sin()).round() as i32,
+    )
+}
+
+fn arc_polygon(x: i32, y: i32, start: f64, end: f64, rx: i32, ry: i32) -> Vec<Position> {
+    let steps = ((end - start) / 2.0).ceil().max(1.0) as i32;
+    (0..=steps)
+        .map(|i| {
+            arc_point(
+                x,
+                y,
+                start + (end - start) * i as f64 / steps as f64,
+                rx,
+                ry,
+            )
+        })
+        .collect()
+}
+
+/// Midpoint ellipse algorithm, gives back every outline pixel relative to the center.
+/// Y is pointing up.
+fn ellipse_points(rx: i32, ry: i32) -> Vec<Position> {
+    let mut quadrant = Vec::new();
+    if rx == 0 || ry == 0 {
+        for x in 0..=rx {
+            quadrant.push(Position::new(x, 0));
+        }
+        for y in 1..=ry {
+            quadrant.push(Position::new(0, y));
+        }
+    } else {
+        let rx2 = (rx as f64) * (rx as f64);
+        let ry2 = (ry as f64) * (ry as f64);
+        let (mut x, mut y) = (0, ry);
+        let mut px = 0.0;
+        let mut py = 2.0 * rx2 * y as f64;
+        let mut p = ry2 - rx2 * ry as f64 + 0.25 * rx2;
+        while px < py {
+            quadrant.push(Position::new(x, y));
+            x += 1;
+            px += 2.0 * ry2;
+            if p < 0.0 {
+                p += ry2 + px;
+            } else {
+                y -= 1;
+                py -= 2.0 * rx2;
+                p += ry2 + px - py;
+            }
+        }
+        p = ry2 * (x as f64 + 0.5).powi(2) + rx2 * (y as f64 - 1.0).powi(2) - rx2 * ry2;
+        while y >= 0 {
+            quadrant.push(Position::new(x, y));
+            y -= 1;
+            py -= 2.0 * rx2;
+            if p > 0.0 {
+                p += rx2 - py;
+            } else {
+                x += 1;
+                px += 2.0 * ry2;
+                p += rx2 - py + px;
+            }
+        }
+    }
+
+    let mut res = Vec::with_capacity(quadrant.len() * 4);
+    for p in quadrant {
+        res.push(p);
+        if p.x != 0 {
+            res.push(Position::new(-p.x, p.y));
+        }
+        if p.y != 0 {
+            res.push(Position::new(p.x, -p.y));
+            if p.x != 0 {
+                res.push(Position::new(-p.x, -p.y));
+            }
+        }
+    }
+    res.sort_unstable_by_key(|p| (p.x, p.y));
+    res.dedup();
+    res
+}
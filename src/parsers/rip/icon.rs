@@ -0,0 +1,87 @@
+use crate::{EngineResult, ParserError};
+
+/// A block of 16 color pixels as produced by `RIP_GET_IMAGE` or loaded from an icon file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RipImage {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u8>,
+}
+
+impl RipImage {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    pub fn get_pixel(&self, x: i32, y: i32) -> u8 {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return 0;
+        }
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Parses a BGI `getimage` dump (`.ICN` file).
+    ///
+    /// The header is width - 1 and height - 1 as little endian u16 followed by
+    /// 4 bit planes per scan line, plane 0 holding bit 0 of the color.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the data is truncated.
+    pub fn from_icn(data: &[u8]) -> EngineResult<Self> {
+        if data.len() < 4 {
+            return Err(Box::new(ParserError::Error(
+                "icon header is truncated".to_string(),
+            )));
+        }
+        let width = u16::from_le_bytes([data[0], data[1]]) as i32 + 1;
+        let height = u16::from_le_bytes([data[2], data[3]]) as i32 + 1;
+        let bytes_per_plane = ((width + 7) / 8) as usize;
+        let bytes_per_line = bytes_per_plane * 4;
+        if data.len() < 4 + bytes_per_line * height as usize {
+            return Err(Box::new(ParserError::Error(
+                "icon data is truncated".to_string(),
+            )));
+        }
+
+        let mut res = RipImage::new(width, height);
+        for y in 0..height {
+            let line = &data[4 + y as usize * bytes_per_line..];
+            for x in 0..width {
+                let mask = 0x80 >> (x & 7);
+                let mut color = 0;
+                for plane in 0..4 {
+                    if line[plane * bytes_per_plane + (x / 8) as usize] & mask != 0 {
+                        color |= 1 << plane;
+                    }
+                }
+                res.pixels[(y * width + x) as usize] = color;
+            }
+        }
+        Ok(res)
+    }
+
+    /// Converts the image to the `.ICN` format, see [`RipImage::from_icn`].
+    pub fn to_icn(&self) -> Vec<u8> {
+        let bytes_per_plane = ((self.width + 7) / 8) as usize;
+        let mut res = Vec::with_capacity(4 + bytes_per_plane * 4 * self.height as usize);
+        res.extend(((self.width - 1) as u16).to_le_bytes());
+        res.extend(((self.height - 1) as u16).to_le_bytes());
+        for y in 0..self.height {
+            for plane in 0..4 {
+                let mut plane_data = vec![0u8; bytes_per_plane];
+                for x in 0..self.width {
+                    if self.get_pixel(x, y) & (1 << plane) != 0 {
+                        plane_data[(x / 8) as usize] |= 0x80 >> (x & 7);
+                    }
+                }
+                res.extend(plane_data);
+            }
+        }
+        res
+    }
+}
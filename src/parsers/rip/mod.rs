@@ -1,38 +1,810 @@
+use std::{collections::HashMap, path::PathBuf};
+
 use super::{ansi, BufferParser};
 use crate::{
-    ansi::EngineState, Buffer, CallbackAction, Caret, EngineResult, ParserError, Rectangle,
+    ansi::EngineState, Buffer, CallbackAction, Caret, EngineResult, OriginMode, ParserError,
+    Position, Rectangle,
 };
 
+mod bgi;
+pub use bgi::*;
+
+mod icon;
+pub use icon::*;
+
+mod mouse;
+pub use mouse::*;
+
+#[cfg(test)]
+mod tests;
+
 #[derive(Default)]
 enum State {
     #[default]
     Default,
     GotRipStart,
-    ReadCommand,
+    ReadCommand(u8),
+    ReadParams(u8, char),
+    GotBackslash(u8, char),
+    Continuation(u8, char),
+    /// A command that got too long is dropped up to its end, `true` after a backslash.
+    SkipCommand(bool),
+    SkipLineEnd,
 }
 
-#[derive(Default)]
+/// Parameter length of the longest command, a polygon with 512 points.
+const MAX_PARAMS_LEN: usize = 2 + 512 * 4;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum WriteMode {
     #[default]
     Normal,
     Xor,
 }
 
-#[derive(Default)]
+/// `RIPscrip` 1.54 parser, see <http://www.bbsdocumentary.com/library/PROGRAMS/GRAPHICS/RIP/RIPSCRIP.TXT>
+///
+/// Graphics are drawn to [`Buffer::rip_surface`], text output is handled by the ansi parser.
 pub struct Parser {
     ansi_parser: ansi::Parser,
     enable_rip: bool,
     state: State,
+    params: String,
+    at_line_start: bool,
 
     text_window: Option<Rectangle>,
     viewport: Option<Rectangle>,
-    _current_write_mode: WriteMode,
+    current_write_mode: WriteMode,
+
+    mouse_fields: Vec<MouseField>,
+    /// Region of the mouse field that is inverted while the button is held down.
+    inverted_field: Option<Rectangle>,
+    button_style: ButtonStyle,
+    text_variables: HashMap<String, String>,
+    clipboard: Option<RipImage>,
+    icon_path: Option<PathBuf>,
+    icon_cache: HashMap<String, RipImage>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self {
+            ansi_parser: ansi::Parser::default(),
+            enable_rip: true,
+            state: State::Default,
+            params: String::new(),
+            at_line_start: true,
+            text_window: None,
+            viewport: None,
+            current_write_mode: WriteMode::Normal,
+            mouse_fields: Vec::new(),
+            inverted_field: None,
+            button_style: ButtonStyle::default(),
+            text_variables: HashMap::new(),
+            clipboard: None,
+            icon_path: None,
+            icon_cache: HashMap::new(),
+        }
+    }
 }
 
 impl Parser {
-    pub fn clear(&mut self) {
-        // clear viewport
+    /// Clears the graphics viewport.
+    pub fn clear(&mut self, buf: &mut Buffer) {
+        get_bgi(buf).clear_viewport();
+    }
+
+    /// Sets the local directory `.ICN` files are loaded from and written to.
+    pub fn set_icon_path(&mut self, path: impl Into<PathBuf>) {
+        self.icon_path = Some(path.into());
+        self.icon_cache.clear();
+    }
+
+    pub fn get_mouse_fields(&self) -> &[MouseField] {
+        &self.mouse_fields
+    }
+
+    /// Gets the topmost mouse field at an absolute pixel position.
+    pub fn get_mouse_field_at(&self, pos: Position) -> Option<&MouseField> {
+        self.mouse_fields
+            .iter()
+            .rev()
+            .find(|field| field.contains(pos))
+    }
+
+    pub fn get_text_variable(&self, name: &str) -> Option<&String> {
+        self.text_variables.get(&name.to_ascii_uppercase())
+    }
+
+    pub fn set_text_variable(&mut self, name: &str, value: impl Into<String>) {
+        self.text_variables
+            .insert(name.to_ascii_uppercase(), value.into());
+    }
+
+    /// Handles the mouse button going down at an absolute pixel position,
+    /// an invertable mouse field is inverted until [`Parser::mouse_click`] releases it.
+    pub fn mouse_press(&mut self, buf: &mut Buffer, pos: Position) {
+        self.release_inverted_field(buf);
+        let Some(field) = self.get_mouse_field_at(pos) else {
+            return;
+        };
+        if field.invert {
+            let region = field.region;
+            get_bgi(buf).invert(region);
+            self.inverted_field = Some(region);
+        }
+    }
+
+    fn release_inverted_field(&mut self, buf: &mut Buffer) {
+        if let Some(region) = self.inverted_field.take() {
+            get_bgi(buf).invert(region);
+        }
+    }
+
+    /// Handles a mouse click (button release) at an absolute pixel position.
+    /// Returns the host command of the clicked mouse field that needs to be sent to the host.
+    pub fn mouse_click(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        pos: Position,
+    ) -> CallbackAction {
+        self.release_inverted_field(buf);
+        let Some(field) = self.get_mouse_field_at(pos) else {
+            return CallbackAction::None;
+        };
+        let reset_screen = field.reset_screen;
+        let cmd = expand_host_command(&field.host_command, &self.text_variables);
+        if reset_screen {
+            self.reset_windows(buf, caret);
+        }
+        if cmd.is_empty() {
+            CallbackAction::None
+        } else {
+            CallbackAction::SendString(cmd)
+        }
+    }
+
+    fn reset_windows(&mut self, buf: &mut Buffer, caret: &mut Caret) {
+        self.set_text_window(buf, caret, None);
+        self.viewport = None;
+        self.mouse_fields.clear();
+        self.inverted_field = None;
+        self.clipboard = None;
+        let bgi = get_bgi(buf);
+        bgi.reset_viewport();
+        bgi.clear_device();
+        bgi.reset_palette();
+        buf.clear_screen(caret);
+    }
+
+    /// Loads an icon from the cache or the icon directory, see [`icon_file_name`].
+    fn load_icon(&mut self, file_name: &str) -> Option<RipImage> {
+        let file_name = icon_file_name(file_name)?;
+        if let Some(icon) = self.icon_cache.get(&file_name) {
+            return Some(icon.clone());
+        }
+        let dir = self.icon_path.as_ref()?;
+        let data = [file_name.clone(), file_name.to_ascii_lowercase()]
+            .iter()
+            .find_map(|name| std::fs::read(dir.join(name)).ok())?;
+        let icon = RipImage::from_icn(&data).ok()?;
+        self.icon_cache.insert(file_name, icon.clone());
+        Some(icon)
+    }
+
+    /// Stores the clipboard as icon, a failed disk write only loses the file, the icon stays cached.
+    fn write_icon(&mut self, file_name: &str) {
+        let (Some(file_name), Some(clipboard)) = (icon_file_name(file_name), &self.clipboard)
+        else {
+            return;
+        };
+        if let Some(dir) = &self.icon_path {
+            let _ = std::fs::write(dir.join(&file_name), clipboard.to_icn());
+        }
+        self.icon_cache.insert(file_name, clipboard.clone());
+    }
+
+    fn is_text_disabled(&self) -> bool {
+        matches!(self.text_window, Some(rect) if rect.size.width == 0)
+    }
+
+    fn set_text_window(&mut self, buf: &mut Buffer, caret: &mut Caret, window: Option<Rectangle>) {
+        self.text_window = window;
+        match window {
+            Some(rect) if rect.size.width > 0 => {
+                let (x0, y0) = (rect.start.x, rect.start.y);
+                buf.terminal_state.margins_left_right = Some((x0, x0 + rect.size.width - 1));
+                buf.terminal_state.margins_up_down = Some((y0, y0 + rect.size.height - 1));
+                buf.terminal_state.origin_mode = OriginMode::WithinMargins;
+                caret.set_position(Position::new(x0, buf.get_first_visible_line() + y0));
+            }
+            _ => {
+                buf.terminal_state.margins_left_right = None;
+                buf.terminal_state.margins_up_down = None;
+                buf.terminal_state.origin_mode = OriginMode::UpperLeftCorner;
+            }
+        }
+    }
+
+    fn erase_text_window(&self, buf: &mut Buffer, caret: &mut Caret) {
+        match self.text_window {
+            Some(rect) if rect.size.width > 0 => {
+                let first = buf.get_first_visible_line();
+                for y in rect.start.y..rect.start.y + rect.size.height {
+                    for x in rect.start.x..rect.start.x + rect.size.width {
+                        buf.set_char(
+                            0,
+                            Position::new(x, first + y),
+                            Some(crate::AttributedChar::default()),
+                        );
+                    }
+                }
+                caret.set_position(Position::new(rect.start.x, first + rect.start.y));
+            }
+            _ => buf.clear_screen(caret),
+        }
+    }
+
+    fn parse_mega_num(&self, idx: &mut usize, digits: usize) -> EngineResult<i32> {
+        let mut res = 0;
+        for _ in 0..digits {
+            let digit = self
+                .params
+                .as_bytes()
+                .get(*idx)
+                .and_then(|b| (*b as char).to_digit(36));
+            let Some(digit) = digit else {
+                return Err(Box::new(ParserError::InvalidRipParameters(
+                    self.params.clone(),
+                )));
+            };
+            res = res * 36 + digit as i32;
+            *idx += 1;
+        }
+        Ok(res)
+    }
+
+    fn parse_mega_nums<const N: usize>(&self, idx: &mut usize) -> EngineResult<[i32; N]> {
+        let mut res = [0; N];
+        for r in &mut res {
+            *r = self.parse_mega_num(idx, 2)?;
+        }
+        Ok(res)
+    }
+
+    fn parse_points(&self, idx: &mut usize) -> EngineResult<Vec<Position>> {
+        let count = self.parse_mega_num(idx, 2)?;
+        let mut points = Vec::new();
+        for _ in 0..count {
+            let [x, y] = self.parse_mega_nums(idx)?;
+            points.push(Position::new(x, y));
+        }
+        Ok(points)
+    }
+
+    fn get_text(&self, idx: usize) -> &str {
+        self.params.get(idx..).unwrap_or_default()
+    }
+
+    fn execute_command(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        level: u8,
+        cmd: char,
+    ) -> EngineResult<CallbackAction> {
+        if level == 1 {
+            return self.execute_level1_command(buf, cmd);
+        }
+        if level != 0 {
+            // level 2 and above commands are not supported.
+            return Ok(CallbackAction::None);
+        }
+        let mut i = 0;
+        match cmd {
+            'w' => {
+                // RIP_TEXT_WINDOW
+                let [x0, y0, x1, y1] = self.parse_mega_nums(&mut i)?;
+                let wrap = self.parse_mega_num(&mut i, 1)?;
+                // font size parameter is ignored, the buffer font is used.
+                let _size = self.parse_mega_num(&mut i, 1)?;
+                let window = if x0 == 0 && y0 == 0 && x1 == 0 && y1 == 0 {
+                    // a 0,0,0,0 text window disables text output
+                    Rectangle::from(0, 0, 0, 0)
+                } else {
+                    Rectangle::from_coords(x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1))
+                };
+                buf.terminal_state.auto_wrap_mode = if wrap == 0 {
+                    crate::AutoWrapMode::NoWrap
+                } else {
+                    crate::AutoWrapMode::AutoWrap
+                };
+                self.set_text_window(buf, caret, Some(window));
+            }
+            'v' => {
+                // RIP_VIEWPORT
+                let [x0, y0, x1, y1] = self.parse_mega_nums(&mut i)?;
+                let rect = Rectangle::from_coords(x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1));
+                self.viewport = Some(rect);
+                get_bgi(buf).set_viewport(rect);
+            }
+            '*' => {
+                // RIP_RESET_WINDOWS
+                self.reset_windows(buf, caret);
+            }
+            'e' => {
+                // RIP_ERASE_VIEW
+                self.clear(buf);
+            }
+            'E' => {
+                // RIP_ERASE_WINDOW
+                self.erase_text_window(buf, caret);
+            }
+            'g' => {
+                // RIP_GOTOXY
+                let [x, y] = self.parse_mega_nums(&mut i)?;
+                let origin = match self.text_window {
+                    Some(rect) => rect.start,
+                    None => Position::default(),
+                };
+                caret.set_position(Position::new(
+                    origin.x + x,
+                    buf.get_first_visible_line() + origin.y + y,
+                ));
+                buf.terminal_state.limit_caret_pos(buf, caret);
+            }
+            'H' => {
+                // RIP_HOME
+                match self.text_window {
+                    Some(rect) if rect.size.width > 0 => caret.set_position(Position::new(
+                        rect.start.x,
+                        buf.get_first_visible_line() + rect.start.y,
+                    )),
+                    _ => caret.home(buf),
+                }
+            }
+            '>' => {
+                // RIP_ERASE_EOL
+                buf.clear_line_end(caret);
+            }
+            'c' => {
+                // RIP_COLOR
+                let color = self.parse_mega_num(&mut i, 2)?;
+                get_bgi(buf).color = (color & 0x0F) as u8;
+            }
+            'Q' => {
+                // RIP_SET_PALETTE
+                let colors: [i32; 16] = self.parse_mega_nums(&mut i)?;
+                let bgi = get_bgi(buf);
+                for (c, ega) in colors.iter().enumerate() {
+                    bgi.set_palette(c, *ega as usize);
+                }
+            }
+            'a' => {
+                // RIP_ONE_PALETTE
+                let [color, ega] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).set_palette(color as usize, ega as usize);
+            }
+            'W' => {
+                // RIP_WRITE_MODE
+                let mode = self.parse_mega_num(&mut i, 2)?;
+                self.current_write_mode = if mode == 1 {
+                    WriteMode::Xor
+                } else {
+                    WriteMode::Normal
+                };
+                get_bgi(buf).write_mode = self.current_write_mode;
+            }
+            'm' => {
+                // RIP_MOVE
+                let [x, y] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).move_to(x, y);
+            }
+            'T' => {
+                // RIP_TEXT
+                let text = self.get_text(i).to_string();
+                get_bgi(buf).out_text(&text);
+            }
+            '@' => {
+                // RIP_TEXT_XY
+                let [x, y] = self.parse_mega_nums(&mut i)?;
+                let text = self.get_text(i).to_string();
+                let bgi = get_bgi(buf);
+                bgi.move_to(x, y);
+                bgi.out_text(&text);
+            }
+            'Y' => {
+                // RIP_FONT_STYLE
+                let [font, direction, size, _reserved] = self.parse_mega_nums(&mut i)?;
+                let bgi = get_bgi(buf);
+                bgi.font = font;
+                bgi.direction = if direction == 0 {
+                    Direction::Horizontal
+                } else {
+                    Direction::Vertical
+                };
+                bgi.char_size = size.clamp(1, 10);
+            }
+            'X' => {
+                // RIP_PIXEL
+                let [x, y] = self.parse_mega_nums(&mut i)?;
+                let bgi = get_bgi(buf);
+                bgi.put_pixel(x, y, bgi.color);
+            }
+            'L' => {
+                // RIP_LINE
+                let [x0, y0, x1, y1] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).line(x0, y0, x1, y1);
+            }
+            'R' => {
+                // RIP_RECTANGLE
+                let [x0, y0, x1, y1] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).rectangle(x0, y0, x1, y1);
+            }
+            'B' => {
+                // RIP_BAR
+                let [x0, y0, x1, y1] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).bar(x0, y0, x1, y1);
+            }
+            'C' => {
+                // RIP_CIRCLE
+                let [x, y, radius] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).circle(x, y, radius);
+            }
+            'O' | 'V' => {
+                // RIP_OVAL / RIP_OVAL_ARC
+                let [x, y, start, end, rx, ry] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).ellipse(x, y, start, end, rx, ry);
+            }
+            'o' => {
+                // RIP_FILLED_OVAL
+                let [x, y, rx, ry] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).fill_ellipse(x, y, rx, ry);
+            }
+            'A' => {
+                // RIP_ARC
+                let [x, y, start, end, radius] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).arc(x, y, start, end, radius);
+            }
+            'I' => {
+                // RIP_PIE_SLICE
+                let [x, y, start, end, radius] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).pie_slice(x, y, start, end, radius);
+            }
+            'i' => {
+                // RIP_OVAL_PIE_SLICE
+                let [x, y, start, end, rx, ry] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).sector(x, y, start, end, rx, ry);
+            }
+            'Z' => {
+                // RIP_BEZIER
+                let [x1, y1, x2, y2, x3, y3, x4, y4, count] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).bezier(
+                    &[
+                        Position::new(x1, y1),
+                        Position::new(x2, y2),
+                        Position::new(x3, y3),
+                        Position::new(x4, y4),
+                    ],
+                    count,
+                );
+            }
+            'P' => {
+                // RIP_POLYGON
+                let points = self.parse_points(&mut i)?;
+                get_bgi(buf).draw_poly(&points, true);
+            }
+            'p' => {
+                // RIP_FILL_POLYGON
+                let points = self.parse_points(&mut i)?;
+                get_bgi(buf).fill_poly(&points);
+            }
+            'l' => {
+                // RIP_POLYLINE
+                let points = self.parse_points(&mut i)?;
+                get_bgi(buf).draw_poly(&points, false);
+            }
+            'F' => {
+                // RIP_FILL
+                let [x, y, border] = self.parse_mega_nums(&mut i)?;
+                get_bgi(buf).flood_fill(x, y, (border & 0x0F) as u8);
+            }
+            '=' => {
+                // RIP_LINE_STYLE
+                let style = self.parse_mega_num(&mut i, 2)?;
+                let user_pattern = self.parse_mega_num(&mut i, 4)?;
+                let thickness = self.parse_mega_num(&mut i, 2)?;
+                let bgi = get_bgi(buf);
+                bgi.line_style = LineStyle::from(style);
+                bgi.user_line_pattern = user_pattern as u16;
+                bgi.line_thickness = if thickness >= 3 { 3 } else { 1 };
+            }
+            'S' => {
+                // RIP_FILL_STYLE
+                let [pattern, color] = self.parse_mega_nums(&mut i)?;
+                let bgi = get_bgi(buf);
+                bgi.fill_style = FillStyle::from(pattern);
+                bgi.fill_color = (color & 0x0F) as u8;
+            }
+            's' => {
+                // RIP_FILL_PATTERN
+                let pattern: [i32; 8] = self.parse_mega_nums(&mut i)?;
+                let color = self.parse_mega_num(&mut i, 2)?;
+                let bgi = get_bgi(buf);
+                for (dst, src) in bgi.user_fill_pattern.iter_mut().zip(pattern) {
+                    *dst = src as u8;
+                }
+                bgi.fill_style = FillStyle::User;
+                bgi.fill_color = (color & 0x0F) as u8;
+            }
+            _ => {
+                // RIP_NO_MORE & unknown commands
+            }
+        }
+        Ok(CallbackAction::None)
     }
+
+    fn execute_level1_command(
+        &mut self,
+        buf: &mut Buffer,
+        cmd: char,
+    ) -> EngineResult<CallbackAction> {
+        let mut i = 0;
+        match cmd {
+            'M' => {
+                // RIP_MOUSE
+                let [_num, x0, y0, x1, y1] = self.parse_mega_nums(&mut i)?;
+                let invert = self.parse_mega_num(&mut i, 1)?;
+                let reset_screen = self.parse_mega_num(&mut i, 1)?;
+                let _reserved = self.parse_mega_num(&mut i, 5)?;
+                self.mouse_fields.push(MouseField {
+                    region: Rectangle::from_coords(x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)),
+                    host_command: self.get_text(i).to_string(),
+                    hotkey: None,
+                    invert: invert != 0,
+                    reset_screen: reset_screen != 0,
+                });
+            }
+            'K' => {
+                // RIP_KILL_MOUSE_FIELDS
+                self.mouse_fields.clear();
+            }
+            'C' => {
+                // RIP_GET_IMAGE
+                let [x0, y0, x1, y1] = self.parse_mega_nums(&mut i)?;
+                let _reserved = self.parse_mega_num(&mut i, 1)?;
+                self.clipboard = Some(get_bgi(buf).get_image(x0, y0, x1, y1));
+            }
+            'P' => {
+                // RIP_PUT_IMAGE
+                let [x, y, mode] = self.parse_mega_nums(&mut i)?;
+                let _reserved = self.parse_mega_num(&mut i, 1)?;
+                if let Some(image) = &self.clipboard {
+                    get_bgi(buf).put_image(x, y, image, ImageOp::from(mode));
+                }
+            }
+            'W' => {
+                // RIP_WRITE_ICON
+                let _reserved = self.parse_mega_num(&mut i, 1)?;
+                let file_name = self.get_text(i).to_string();
+                self.write_icon(&file_name);
+            }
+            'I' => {
+                // RIP_LOAD_ICON
+                let [x, y, mode] = self.parse_mega_nums(&mut i)?;
+                let clipboard = self.parse_mega_num(&mut i, 1)?;
+                let _reserved = self.parse_mega_num(&mut i, 2)?;
+                let file_name = self.get_text(i).to_string();
+                if let Some(icon) = self.load_icon(&file_name) {
+                    get_bgi(buf).put_image(x, y, &icon, ImageOp::from(mode));
+                    if clipboard == 1 {
+                        self.clipboard = Some(icon);
+                    }
+                }
+            }
+            'B' => {
+                // RIP_BUTTON_STYLE
+                let [width, height, orientation] = self.parse_mega_nums(&mut i)?;
+                let flags = self.parse_mega_num(&mut i, 4)?;
+                let [bevel_size, label, shadow, bright, dark, surface, group, flags2, underline, corner] =
+                    self.parse_mega_nums(&mut i)?;
+                self.button_style = ButtonStyle {
+                    width,
+                    height,
+                    orientation,
+                    flags,
+                    bevel_size,
+                    label_color: (label & 0x0F) as u8,
+                    shadow_color: (shadow & 0x0F) as u8,
+                    bright_color: (bright & 0x0F) as u8,
+                    dark_color: (dark & 0x0F) as u8,
+                    surface_color: (surface & 0x0F) as u8,
+                    group,
+                    flags2,
+                    underline_color: (underline & 0x0F) as u8,
+                    corner_color: (corner & 0x0F) as u8,
+                };
+            }
+            'U' => {
+                // RIP_BUTTON
+                let [x0, y0, x1, y1, hotkey] = self.parse_mega_nums(&mut i)?;
+                let _flags = self.parse_mega_num(&mut i, 1)?;
+                let _reserved = self.parse_mega_num(&mut i, 1)?;
+                let text = self.get_text(i).to_string();
+                self.draw_button(buf, x0, y0, x1, y1, hotkey, &text);
+            }
+            'D' => {
+                // RIP_DEFINE
+                let _flags = self.parse_mega_num(&mut i, 3)?;
+                let _reserved = self.parse_mega_num(&mut i, 2)?;
+                let text = self.get_text(i).to_string();
+                // name,width:question?default - the question isn't asked, the variable is set to its default value
+                let (name, rest) = text.split_once(':').unwrap_or((&text, ""));
+                let name = name.split(',').next().unwrap_or_default();
+                let default = rest.rsplit('?').next().unwrap_or_default();
+                self.set_text_variable(name, default);
+            }
+            _ => {}
+        }
+        Ok(CallbackAction::None)
+    }
+
+    /// Draws a `RIP_BUTTON` in the current button style and registers its mouse field.
+    /// `text` has the form `icon<>label<>host command`, all parts are optional.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_button(
+        &mut self,
+        buf: &mut Buffer,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        hotkey: i32,
+        text: &str,
+    ) {
+        let style = self.button_style.clone();
+        let mut parts = text.split("<>");
+        let icon_name = parts.next().unwrap_or_default();
+        let label = parts.next().unwrap_or_default();
+        let host_command = parts.next().unwrap_or_default();
+
+        let image = if style.has_flag(ButtonStyle::ICON) {
+            self.load_icon(icon_name)
+        } else if style.has_flag(ButtonStyle::CLIPBOARD) {
+            self.clipboard.clone()
+        } else {
+            None
+        };
+
+        // the lower right corner is only used if the size isn't given by the image or style
+        let (x0, y0, width, height) = if let Some(image) = &image {
+            (x0, y0, image.width, image.height)
+        } else if style.width > 0 && style.height > 0 {
+            (x0, y0, style.width, style.height)
+        } else {
+            (
+                x0.min(x1),
+                y0.min(y1),
+                (x1 - x0).abs() + 1,
+                (y1 - y0).abs() + 1,
+            )
+        };
+        let (x1, y1) = (x0 + width - 1, y0 + height - 1);
+
+        let bgi = get_bgi(buf);
+        let saved_color = bgi.color;
+        let saved_fill = (bgi.fill_style, bgi.fill_color);
+        let saved_line = (bgi.line_style, bgi.line_thickness, bgi.write_mode);
+        bgi.line_style = LineStyle::Solid;
+        bgi.line_thickness = 1;
+        bgi.write_mode = WriteMode::Normal;
+
+        if let Some(image) = &image {
+            bgi.put_image(x0, y0, image, ImageOp::Copy);
+        } else if !style.has_flag(ButtonStyle::PLAIN) || style.has_flag(ButtonStyle::BEVEL) {
+            bgi.fill_style = FillStyle::Solid;
+            bgi.fill_color = style.surface_color;
+            bgi.bar(x0, y0, x1, y1);
+        }
+
+        if style.has_flag(ButtonStyle::BEVEL) {
+            for b in 1..=style.bevel_size {
+                bgi.color = style.bright_color;
+                bgi.line(x0 - b, y0 - b, x1 + b, y0 - b);
+                bgi.line(x0 - b, y0 - b, x0 - b, y1 + b);
+                bgi.color = style.dark_color;
+                bgi.line(x1 + b, y0 - b + 1, x1 + b, y1 + b);
+                bgi.line(x0 - b + 1, y1 + b, x1 + b, y1 + b);
+            }
+        }
+        if style.has_flag(ButtonStyle::RECESSED) {
+            let b = style.bevel_size + 1;
+            bgi.color = 0;
+            bgi.rectangle(x0 - b, y0 - b, x1 + b, y1 + b);
+        }
+
+        if !label.is_empty() {
+            let tw = bgi.get_text_width(label);
+            let th = bgi.get_text_height();
+            let margin = style.bevel_size + 2;
+            let (tx, ty) = match style.orientation {
+                0 => (x0 + (width - tw) / 2, y0 - th - margin),
+                1 => (x0 - tw - margin, y0 + (height - th) / 2),
+                3 => (x1 + margin + 1, y0 + (height - th) / 2),
+                4 => (x0 + (width - tw) / 2, y1 + margin + 1),
+                _ => (x0 + (width - tw) / 2, y0 + (height - th) / 2),
+            };
+            let saved_direction = bgi.direction;
+            bgi.direction = Direction::Horizontal;
+            if style.has_flag(ButtonStyle::DROP_SHADOW) {
+                bgi.color = style.shadow_color;
+                bgi.out_text_xy(tx + 1, ty + 1, label);
+            }
+            bgi.color = style.label_color;
+            bgi.out_text_xy(tx, ty, label);
+            bgi.direction = saved_direction;
+        }
+
+        bgi.color = saved_color;
+        (bgi.fill_style, bgi.fill_color) = saved_fill;
+        (bgi.line_style, bgi.line_thickness, bgi.write_mode) = saved_line;
+        let offset = bgi.get_viewport().start;
+
+        if style.has_flag(ButtonStyle::MOUSE) {
+            self.mouse_fields.push(MouseField {
+                region: Rectangle::from(offset.x + x0, offset.y + y0, width, height),
+                host_command: host_command.to_string(),
+                hotkey: char::from_u32(hotkey as u32).filter(|_| hotkey > 0),
+                invert: style.has_flag(ButtonStyle::INVERTABLE),
+                reset_screen: style.has_flag(ButtonStyle::RESET_SCREEN),
+            });
+        }
+    }
+
+    /// Adds a char to the parameters of the current command, commands that get too long are dropped.
+    fn push_param(&mut self, ch: char) {
+        if self.params.len() >= MAX_PARAMS_LEN {
+            self.params.clear();
+            self.state = State::SkipCommand(false);
+            return;
+        }
+        self.params.push(ch);
+    }
+
+    fn finish_command(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        level: u8,
+        cmd: char,
+    ) -> EngineResult<CallbackAction> {
+        let res = self.execute_command(buf, caret, level, cmd);
+        self.params.clear();
+        res
+    }
+}
+
+/// Normalizes an icon file name to an upper case 8.3 name with the extension `.ICN`.
+/// Directory components and other extensions are stripped to keep the host
+/// from writing anything but icons into the icon directory.
+fn icon_file_name(file_name: &str) -> Option<String> {
+    let name = file_name.trim().rsplit(['/', '\\', ':']).next()?;
+    let stem: String = name
+        .split('.')
+        .next()?
+        .chars()
+        .filter(|ch| ch.is_ascii_alphanumeric() || "_-$~!#".contains(*ch))
+        .take(8)
+        .collect();
+    if stem.is_empty() {
+        return None;
+    }
+    Some(format!("{}.ICN", stem.to_ascii_uppercase()))
+}
+
+fn get_bgi(buf: &mut Buffer) -> &mut Bgi {
+    buf.rip_surface.get_or_insert_with(Bgi::default)
 }
 
 static RIP_TERMINAL_ID: &str = "RIPSCRIP01540\0";
@@ -53,209 +825,86 @@ impl BufferParser for Parser {
         ch: char,
     ) -> EngineResult<CallbackAction> {
         match self.state {
-            State::ReadCommand => {
+            State::SkipLineEnd => {
+                self.state = State::Default;
+                self.at_line_start = true;
+                if ch == '\r' || ch == '\n' {
+                    return Ok(CallbackAction::None);
+                }
+                return self.print_char(buf, caret, ch);
+            }
+            State::Continuation(level, cmd) => {
+                if ch == '\r' || ch == '\n' {
+                    return Ok(CallbackAction::None);
+                }
+                self.state = State::ReadParams(level, cmd);
+                return self.print_char(buf, caret, ch);
+            }
+            State::GotBackslash(level, cmd) => {
+                if ch == '\r' || ch == '\n' {
+                    // line continuation
+                    self.state = State::Continuation(level, cmd);
+                } else {
+                    self.state = State::ReadParams(level, cmd);
+                    self.push_param(ch);
+                }
+                return Ok(CallbackAction::None);
+            }
+            State::SkipCommand(got_backslash) => {
+                self.state = match ch {
+                    '\\' => State::SkipCommand(true),
+                    // line continuation
+                    '\r' if got_backslash => State::SkipCommand(true),
+                    '\n' if got_backslash => State::SkipCommand(false),
+                    '|' if !got_backslash => State::ReadCommand(0),
+                    '\r' | '\n' => State::SkipLineEnd,
+                    _ => State::SkipCommand(false),
+                };
+                return Ok(CallbackAction::None);
+            }
+            State::ReadParams(level, cmd) => {
                 match ch {
-                    'w' => {
-                        // RIP_TEXT_WINDOW
-                        todo!();
-                    }
-                    'v' => {
-                        // RIP_VIEWPORT
-                        todo!();
-                    }
-                    '*' => {
-                        // RIP_RESET_WINDOWS
-                        self.state = State::Default;
-                        self.text_window = None;
-                        self.viewport = None;
-                        return Ok(CallbackAction::None);
-                    }
-                    'e' => {
-                        // RIP_ERASE_VIEW
-                        self.state = State::Default;
-                        self.clear();
-                        return Ok(CallbackAction::None);
-                    }
-                    'E' => {
-                        // RIP_ERASE_WINDOW
-                        // level1: RIP_END_TEXT
-                        self.state = State::Default;
-                        buf.clear();
-                        return Ok(CallbackAction::None);
-                    }
-                    'g' => {
-                        // RIP_GOTOXY
-                        todo!();
-                    }
-                    'H' => {
-                        // RIP_HOME
-                        self.state = State::Default;
-                        caret.home(buf);
-                        return Ok(CallbackAction::None);
-                    }
-                    '>' => {
-                        // RIP_ERASE_EOL
-                        self.state = State::Default;
-                        buf.clear_line_end(caret);
-                        return Ok(CallbackAction::None);
-                    }
-                    'c' => {
-                        // RIP_COLOR
-                        todo!();
-                    }
-                    'Q' => {
-                        // RIP_SET_PALETTE
-                        todo!();
-                    }
-                    'a' => {
-                        // RIP_ONE_PALETTE
-                        todo!();
-                    }
-                    'W' => {
-                        // RIP_WRITE_MODE
-                        // level 1: RIP_WRITE_ICON
-                        todo!();
-                    }
-                    'm' => {
-                        // RIP_MOVE
-                        todo!();
-                    }
-                    'T' => {
-                        // RIP_TEXT
-                        // level1: RIP_REGION_TEXT
-                        todo!();
-                    }
-                    '@' => {
-                        // RIP_TEXT_XY
-                        todo!();
-                    }
-                    'Y' => {
-                        // RIP_FONT_STYLE
-                        todo!();
-                    }
-                    'X' => {
-                        // RIP_PIXEL
-                        todo!();
-                    }
-                    'L' => {
-                        // RIP_LINE
-                        todo!();
-                    }
-                    'R' => {
-                        // RIP_RECTANGLE
-                        // RIP_READ_SCENE level 1
-                        todo!();
-                    }
-                    'B' => {
-                        // RIP_BAR
-                        // level 1: RIP_BUTTON_STYLE
-                        todo!();
-                    }
-                    'C' => {
-                        // RIP_CIRCLE
-                        // level 1: RIP_GET_IMAGE
-                        todo!();
-                    }
-                    'O' => {
-                        // RIP_OVAL
-                        todo!();
-                    }
-                    'o' => {
-                        // RIP_FILLED_OVAL
-                        todo!();
+                    '\\' => self.state = State::GotBackslash(level, cmd),
+                    '|' => {
+                        self.state = State::ReadCommand(0);
+                        return self.finish_command(buf, caret, level, cmd);
                     }
-                    'A' => {
-                        // RIP_ARC
-                        todo!();
+                    '\r' | '\n' => {
+                        self.state = State::SkipLineEnd;
+                        return self.finish_command(buf, caret, level, cmd);
                     }
-                    'V' => {
-                        // RIP_OVAL_ARC
-                        todo!();
-                    }
-                    'I' => {
-                        // RIP_PIE_SLICE
-                        // level 1: RIP_LOAD_ICON
-                        todo!();
-                    }
-                    'i' => {
-                        // RIP_OVAL_PIE_SLICE
-                        todo!();
-                    }
-                    'Z' => {
-                        // RIP_BEZIER
-                        todo!();
-                    }
-                    'P' => {
-                        // RIP_POLYGON
-                        // level 1: RIP_PUT_IMAGE
-                        todo!();
-                    }
-                    'p' => {
-                        // RIP_FILL_POLYGON
-                        todo!();
-                    }
-                    'l' => {
-                        // RIP_POLYLINE
-                        todo!();
-                    }
-                    'F' => {
-                        // RIP_FILL
-                        // level 1: RIP_FILE_QUERY
-                        todo!();
-                    }
-                    '=' => {
-                        // RIP_LINE_STYLE
-                        todo!();
-                    }
-                    'S' => {
-                        // RIP_FILL_STYLE
-                        todo!();
-                    }
-                    's' => {
-                        // RIP_FILL_PATTERN
-                        todo!();
-                    }
-                    'M' => {
-                        // RIP_MOUSE
-                        todo!();
-                    }
-                    'K' => {
-                        // RIP_KILL_MOUSE_FIELDS
-                        todo!();
-                    }
-                    't' => {
-                        // RIP_REGION_TEXT
-                        todo!();
-                    }
-                    'U' => {
-                        // RIP_BUTTON level 1
-                        todo!();
-                    }
-                    'D' => {
-                        // RIP_DEFINE level 1
-                        todo!();
-                    }
-                    '\x1B' => {
-                        // RIP_QUERY level 1
-                        // level 9: RIP_ENTER_BLOCK_MODE
-                        todo!();
+                    _ => self.push_param(ch),
+                }
+                return Ok(CallbackAction::None);
+            }
+            State::ReadCommand(level) => {
+                match ch {
+                    '\r' | '\n' => {
+                        self.state = State::SkipLineEnd;
                     }
-                    'G' => {
-                        // RIP_COPY_REGION level 1
-                        todo!();
+                    '1'..='9' if level == 0 => {
+                        self.state = State::ReadCommand(ch as u8 - b'0');
                     }
-                    '#' => {
-                        // RIP_NO_MORE
-                        self.state = State::Default;
-                        return Ok(CallbackAction::None);
+                    'w' | 'v' | '*' | 'e' | 'E' | 'g' | 'H' | '>' | 'c' | 'Q' | 'a' | 'W' | 'm'
+                    | 'T' | '@' | 'Y' | 'X' | 'L' | 'R' | 'B' | 'C' | 'O' | 'o' | 'A' | 'V'
+                    | 'I' | 'i' | 'Z' | 'P' | 'p' | 'l' | 'F' | '=' | 'S' | 's' | '#' => {
+                        self.params.clear();
+                        self.state = State::ReadParams(level, ch);
                     }
                     _ => {
+                        if level != 0 {
+                            // unknown higher level command, skip it
+                            self.params.clear();
+                            self.state = State::ReadParams(level, ch);
+                            return Ok(CallbackAction::None);
+                        }
                         self.state = State::Default;
                         self.ansi_parser.print_char(buf, caret, '!')?;
                         self.ansi_parser.print_char(buf, caret, '|')?;
                         return self.ansi_parser.print_char(buf, caret, ch);
                     }
                 }
+                return Ok(CallbackAction::None);
             }
             State::GotRipStart => {
                 // got !
@@ -264,7 +913,7 @@ impl BufferParser for Parser {
                     self.ansi_parser.print_char(buf, caret, '!')?;
                     return self.ansi_parser.print_char(buf, caret, ch);
                 }
-                self.state = State::ReadCommand;
+                self.state = State::ReadCommand(0);
                 return Ok(CallbackAction::None);
             }
             State::Default => {
@@ -303,8 +952,21 @@ impl BufferParser for Parser {
                             return self.ansi_parser.print_char(buf, caret, ch);
                         }
 
-                        if let '!' = ch {
-                            self.state = State::GotRipStart;
+                        let at_line_start = self.at_line_start;
+                        self.at_line_start = ch == '\r' || ch == '\n';
+                        match ch {
+                            // RIP commands start at the beginning of a line or after ^A/^B
+                            '!' if at_line_start || caret.get_position().x == 0 => {
+                                self.state = State::GotRipStart;
+                                return Ok(CallbackAction::None);
+                            }
+                            '\x01' | '\x02' => {
+                                self.state = State::GotRipStart;
+                                return Ok(CallbackAction::None);
+                            }
+                            _ => {}
+                        }
+                        if self.is_text_disabled() && ch >= ' ' && ch != '\x1B' && ch != '\x7F' {
                             return Ok(CallbackAction::None);
                         }
                     }
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::{Position, Rectangle};
+
+/// A clickable screen region defined by `RIP_MOUSE` or a mouse button.
+#[derive(Debug, Clone)]
+pub struct MouseField {
+    /// Region in absolute screen coordinates.
+    pub region: Rectangle,
+    pub host_command: String,
+    pub hotkey: Option<char>,
+    /// The region should be inverted while it's clicked.
+    pub invert: bool,
+    /// The screen should be reset before the host command is sent.
+    pub reset_screen: bool,
+}
+
+impl MouseField {
+    pub fn contains(&self, pos: Position) -> bool {
+        let r = &self.region;
+        r.start.x <= pos.x
+            && pos.x < r.start.x + r.size.width
+            && r.start.y <= pos.y
+            && pos.y < r.start.y + r.size.height
+    }
+}
+
+/// Button appearance set by `RIP_BUTTON_STYLE`.
+#[derive(Debug, Clone, Default)]
+pub struct ButtonStyle {
+    pub width: i32,
+    pub height: i32,
+    pub orientation: i32,
+    pub flags: i32,
+    pub bevel_size: i32,
+    pub label_color: u8,
+    pub shadow_color: u8,
+    pub bright_color: u8,
+    pub dark_color: u8,
+    pub surface_color: u8,
+    pub group: i32,
+    pub flags2: i32,
+    pub underline_color: u8,
+    pub corner_color: u8,
+}
+
+impl ButtonStyle {
+    pub const CLIPBOARD: i32 = 1;
+    pub const INVERTABLE: i32 = 2;
+    pub const RESET_SCREEN: i32 = 4;
+    pub const CHISEL: i32 = 8;
+    pub const RECESSED: i32 = 16;
+    pub const DROP_SHADOW: i32 = 32;
+    pub const STAMP_CLIPBOARD: i32 = 64;
+    pub const ICON: i32 = 128;
+    pub const PLAIN: i32 = 256;
+    pub const BEVEL: i32 = 512;
+    pub const MOUSE: i32 = 1024;
+
+    pub fn has_flag(&self, flag: i32) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+/// Expands a host command: `^X` is sent as control character and `$VAR$` is replaced by the text variable.
+/// Unknown variables are kept as they are.
+pub(crate) fn expand_host_command(cmd: &str, variables: &HashMap<String, String>) -> String {
+    let mut res = String::new();
+    let mut chars = cmd.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '^' | '`' => match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() || "@[\\]^_".contains(c) => {
+                    res.push(char::from(c.to_ascii_uppercase() as u8 & 0x1F));
+                }
+                Some(c) => {
+                    res.push(ch);
+                    res.push(c);
+                }
+                None => res.push(ch),
+            },
+            '$' => {
+                let rest: String = chars.clone().collect();
+                if let Some(end) = rest.find('$') {
+                    let name = &rest[..end];
+                    if let Some(value) = lookup_variable(name, variables) {
+                        res.push_str(&value);
+                        for _ in 0..=name.chars().count() {
+                            chars.next();
+                        }
+                        continue;
+                    }
+                }
+                res.push(ch);
+            }
+            _ => res.push(ch),
+        }
+    }
+    res
+}
+
+fn lookup_variable(name: &str, variables: &HashMap<String, String>) -> Option<String> {
+    if let Some(value) = variables.get(&name.to_ascii_uppercase()) {
+        return Some(value.clone());
+    }
+    match name.to_ascii_uppercase().as_str() {
+        "RIPVER" => Some("RIPSCRIP015400".to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    parsers::{create_buffer, update_buffer},
+    rip::{self, RIP_SCREEN_HEIGHT, RIP_SCREEN_WIDTH},
+    Buffer, CallbackAction, Position,
+};
+
+fn surface(buf: &Buffer) -> &rip::Bgi {
+    buf.rip_surface.as_ref().unwrap()
+}
+
+#[test]
+fn test_rip_line() {
+    let (buf, _) = create_buffer(&mut rip::Parser::default(), b"!|c04|L00000A0A\n");
+    let bgi = surface(&buf);
+    assert_eq!(RIP_SCREEN_WIDTH, bgi.width());
+    assert_eq!(RIP_SCREEN_HEIGHT, bgi.height());
+    for i in 0..=10 {
+        assert_eq!(4, bgi.get_pixel(i, i));
+    }
+    assert_eq!(0, bgi.get_pixel(11, 11));
+    assert_eq!(0, bgi.get_pixel(1, 0));
+}
+
+#[test]
+fn test_rip_bar() {
+    let (buf, _) = create_buffer(&mut rip::Parser::default(), b"!|S0102|B01010303\n");
+    let bgi = surface(&buf);
+    for y in 1..=3 {
+        for x in 1..=3 {
+            assert_eq!(2, bgi.get_pixel(x, y));
+        }
+    }
+    assert_eq!(0, bgi.get_pixel(0, 0));
+    assert_eq!(0, bgi.get_pixel(4, 4));
+}
+
+#[test]
+fn test_rip_flood_fill() {
+    let (buf, _) = create_buffer(
+        &mut rip::Parser::default(),
+        b"!|c0F|R00000A0A|S0103|F05050F\n",
+    );
+    let bgi = surface(&buf);
+    assert_eq!(15, bgi.get_pixel(0, 0));
+    assert_eq!(3, bgi.get_pixel(5, 5));
+    assert_eq!(3, bgi.get_pixel(1, 9));
+    assert_eq!(0, bgi.get_pixel(11, 5));
+}
+
+#[test]
+fn test_rip_xor_mode() {
+    let (buf, _) = create_buffer(&mut rip::Parser::default(), b"!|W01|L00000A00|L00000A00\n");
+    let bgi = surface(&buf);
+    for x in 0..=10 {
+        assert_eq!(0, bgi.get_pixel(x, 0));
+    }
+}
+
+#[test]
+fn test_rip_viewport() {
+    let (buf, _) = create_buffer(&mut rip::Parser::default(), b"!|v0A0A1414|X0000|X2020\n");
+    let bgi = surface(&buf);
+    // coordinates are relative to the viewport and clipped to it
+    assert_eq!(15, bgi.get_pixel(10, 10));
+    assert_eq!(0, bgi.get_pixel(0, 0));
+    assert_eq!(0, bgi.get_pixel(82, 82));
+}
+
+#[test]
+fn test_rip_palette() {
+    let (buf, _) = create_buffer(&mut rip::Parser::default(), b"!|a011R\n");
+    let bgi = surface(&buf);
+    assert_eq!(bgi.palette.colors[1], crate::EGA_PALETTE[63]);
+}
+
+#[test]
+fn test_rip_text_passthrough() {
+    let (buf, _) = create_buffer(
+        &mut rip::Parser::default(),
+        b"Hello\r\n!|c04|L00000505\r\nWorld! |c",
+    );
+    assert_eq!('H', buf.get_char(Position::new(0, 0)).unwrap().ch);
+    assert_eq!('W', buf.get_char(Position::new(0, 1)).unwrap().ch);
+    assert_eq!('!', buf.get_char(Position::new(5, 1)).unwrap().ch);
+    assert_eq!('|', buf.get_char(Position::new(7, 1)).unwrap().ch);
+}
+
+#[test]
+fn test_rip_line_continuation() {
+    let (buf, _) = create_buffer(&mut rip::Parser::default(), b"!|L0000\\\r\n0A00\n");
+    let bgi = surface(&buf);
+    assert_eq!(15, bgi.get_pixel(10, 0));
+}
+
+#[test]
+fn test_rip_text_window() {
+    let mut parser = rip::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"!|w0A05141400\n");
+    assert_eq!(Position::new(10, 5), caret.get_position());
+    update_buffer(&mut buf, &mut caret, &mut parser, b"Foo");
+    assert_eq!('F', buf.get_char(Position::new(10, 5)).unwrap().ch);
+
+    update_buffer(&mut buf, &mut caret, &mut parser, b"\r\n!|g0101\n");
+    assert_eq!(Position::new(11, 6), caret.get_position());
+}
+
+#[test]
+fn test_rip_invalid_parameters() {
+    let mut parser = rip::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"!|L00");
+    assert!(crate::BufferParser::print_char(&mut parser, &mut buf, &mut caret, '\n').is_err());
+    // parser recovers after an error
+    update_buffer(&mut buf, &mut caret, &mut parser, b"!|X0101\n");
+    assert_eq!(15, surface(&buf).get_pixel(1, 1));
+}
+
+#[test]
+fn test_rip_params_limit() {
+    let mut parser = rip::Parser::default();
+    let mut input = b"!|L".to_vec();
+    input.extend(std::iter::repeat_n(b'0', 10_000));
+    input.extend(b"\\\r\n0000|X0101\nFoo");
+    let (buf, _) = create_buffer(&mut parser, &input);
+    // the long command is dropped without printing its parameters, the next one is executed
+    assert_eq!(15, surface(&buf).get_pixel(1, 1));
+    assert_eq!(0, surface(&buf).get_pixel(0, 0));
+    assert_eq!('F', buf.get_char(Position::new(0, 0)).unwrap().ch);
+}
+
+#[test]
+fn test_rip_shapes_dont_panic() {
+    create_buffer(
+        &mut rip::Parser::default(),
+        b"!|=00000003|C5050ZZ|OZZZZ00B4ZZZZ|o0505ZZZZ|AZZZZ005A10|IZZ10005A10|i0A0A002D0505\n\
+          !|Z000010102020303004|P03000010102000|p03000010102000|l03000010102000\n\
+          !|s55AA55AA55AA55AA05|F10100F|Y02010400|@1010Hello|1K|#|#|#\n",
+    );
+}
+
+#[test]
+fn test_rip_mouse_field() {
+    let mut parser = rip::Parser::default();
+    let (mut buf, mut caret) = create_buffer(&mut parser, b"!|1M000A0A14140000000help^M\n");
+    assert_eq!(1, parser.get_mouse_fields().len());
+    assert!(parser.get_mouse_field_at(Position::new(9, 10)).is_none());
+    assert!(parser.get_mouse_field_at(Position::new(20, 20)).is_some());
+    assert_eq!(
+        CallbackAction::SendString("help\r".to_string()),
+        parser.mouse_click(&mut buf, &mut caret, Position::new(15, 15))
+    );
+    assert_eq!(
+        CallbackAction::None,
+        parser.mouse_click(&mut buf, &mut caret, Position::new(50, 50))
+    );
+
+    update_buffer(&mut buf, &mut caret, &mut parser, b"!|1K\n");
+    assert!(parser.get_mouse_fields().is_empty());
+}
+
+#[test]
+fn test_rip_mouse_field_invert() {
+    let mut parser = rip::Parser::default();
+    let (mut buf, mut caret) =
+        create_buffer(&mut parser, b"!|S0102|B0A0A1414|1M000A0A14141000000help\n");
+    assert_eq!(2, surface(&buf).get_pixel(15, 15));
+    parser.mouse_press(&mut buf, Position::new(15, 15));
+    assert_eq!(13, surface(&buf).get_pixel(15, 15));
+    assert_eq!(0, surface(&buf).get_pixel(45, 45));
+    parser.mouse_click(&mut buf, &mut caret, Position::new(50, 50));
+    assert_eq!(2, surface(&buf).get_pixel(15, 15));
+}
+
+#[test]
+fn test_rip_button() {
+    let mut parser = rip::Parser::default();
+    let (mut buf, mut caret) = create_buffer(
+        &mut parser,
+        b"!|1D00000NAME,08:?Your name?sysop|1B0A0A02016O020F080F0807000000000000000000|1U101000000000<>Ok<>hello $NAME$\n",
+    );
+    let field = parser.get_mouse_field_at(Position::new(37, 37)).unwrap();
+    assert_eq!(36, field.region.start.x);
+    assert_eq!(10, field.region.size.width);
+    assert!(parser.get_mouse_field_at(Position::new(46, 46)).is_none());
+    // surface is drawn, bevel around it
+    assert_eq!(7, surface(&buf).get_pixel(36, 36));
+    assert_eq!(15, surface(&buf).get_pixel(34, 34));
+    assert_eq!(8, surface(&buf).get_pixel(47, 47));
+
+    assert_eq!(
+        CallbackAction::SendString("hello sysop".to_string()),
+        parser.mouse_click(&mut buf, &mut caret, Position::new(40, 40))
+    );
+}
+
+#[test]
+fn test_rip_get_put_image() {
+    let (buf, _) = create_buffer(
+        &mut rip::Parser::default(),
+        b"!|S0104|B00000101|1C0000010100|1P0A0A0000|1P0A0A0100\n",
+    );
+    let bgi = surface(&buf);
+    assert_eq!(4, bgi.get_pixel(1, 1));
+    // put with copy & xor
+    assert_eq!(0, bgi.get_pixel(10, 10));
+    assert_eq!(0, bgi.get_pixel(11, 11));
+}
+
+#[test]
+fn test_icn_round_trip() {
+    let mut image = rip::RipImage::new(11, 3);
+    for (i, p) in image.pixels.iter_mut().enumerate() {
+        *p = (i % 16) as u8;
+    }
+    let data = image.to_icn();
+    assert_eq!(&[10, 0, 2, 0], &data[0..4]);
+    assert_eq!(4 + 3 * 4 * 2, data.len());
+    assert_eq!(image, rip::RipImage::from_icn(&data).unwrap());
+    assert!(rip::RipImage::from_icn(&data[..10]).is_err());
+}
+
+#[test]
+fn test_rip_icon_files() {
+    let dir = std::env::temp_dir().join(format!("icy_engine_rip_icons_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut parser = rip::Parser::default();
+    parser.set_icon_path(&dir);
+    let (mut buf, mut caret) =
+        create_buffer(&mut parser, b"!|S0109|B00000302|1C0000030200|1W0../test\n");
+    assert!(dir.join("TEST.ICN").exists());
+
+    // a fresh parser loads the icon from disk
+    let mut parser = rip::Parser::default();
+    parser.set_icon_path(&dir);
+    update_buffer(&mut buf, &mut caret, &mut parser, b"!|1I0K0K00000test\n");
+    let bgi = surface(&buf);
+    assert_eq!(9, bgi.get_pixel(20, 20));
+    assert_eq!(9, bgi.get_pixel(23, 22));
+    assert_eq!(0, bgi.get_pixel(24, 22));
+
+    // only icons are written, other extensions are replaced
+    update_buffer(
+        &mut buf,
+        &mut caret,
+        &mut parser,
+        b"!|1C0000030200|1W0AUTOEXEC.BAT|1W0LONGFILENAME.EXE\n",
+    );
+    assert!(dir.join("AUTOEXEC.ICN").exists());
+    assert!(dir.join("LONGFILE.ICN").exists());
+    assert!(!dir.join("AUTOEXEC.BAT").exists());
+    assert!(!dir.join("LONGFILENAME.EXE").exists());
+
+    // a failed write doesn't stop the parser
+    parser.set_icon_path(dir.join("missing"));
+    update_buffer(&mut buf, &mut caret, &mut parser, b"!|1W0FOO|X0101\n");
+    assert_eq!(15, surface(&buf).get_pixel(1, 1));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
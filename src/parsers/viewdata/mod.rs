@@ -332,4 +332,22 @@ impl BufferParser for Parser {
         self.got_esc = false;
         Ok(CallbackAction::None)
     }
+
+    fn print_bytes(
+        &mut self,
+        buf: &mut Buffer,
+        caret: &mut Caret,
+        data: &[u8],
+    ) -> EngineResult<Vec<CallbackAction>> {
+        let mut actions = Vec::new();
+        for b in data {
+            let action = if *b >= 0x20 {
+                self.interpret_char(buf, caret, *b)
+            } else {
+                BufferParser::print_char(self, buf, caret, char::from(*b))?
+            };
+            super::push_action(&mut actions, action);
+        }
+        Ok(actions)
+    }
 }
@@ -0,0 +1,574 @@
+use crate::{
+    AttributedChar, Buffer, Color, EngineResult, KittyPlacement, LineAttribute, Position, Sixel,
+    KITTY_BELOW_BACKGROUND_Z_INDEX,
+};
+
+/// Options for [`render_to_rgba`].
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// Renders 9 pixel wide cells like VGA text mode, the line drawing chars `0xC0..=0xDF`
+    /// repeat their 8th column.
+    pub use_letter_spacing: bool,
+    /// Blink phase, in the off phase blinking text isn't drawn. Ignored with ice colors.
+    pub blink_on: bool,
+    pub render_sixels: bool,
+    /// Renders the images placed with the kitty graphics protocol.
+    pub render_kitty_images: bool,
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        RenderOptions {
+            use_letter_spacing: false,
+            blink_on: true,
+            render_sixels: true,
+            render_kitty_images: true,
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An image with 4 bytes per pixel in RGBA order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let o = (y as usize * self.width as usize + x as usize) * 4;
+        Some([
+            self.pixels[o],
+            self.pixels[o + 1],
+            self.pixels[o + 2],
+            self.pixels[o + 3],
+        ])
+    }
+
+    /// Index of a pixel, `None` if it's outside of the image.
+    fn get_offset(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return None;
+        }
+        Some(y as usize * self.width as usize + x as usize)
+    }
+
+    fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        let Some(o) = self.get_offset(x, y) else {
+            return;
+        };
+        let rgb = color.get_rgb();
+        self.pixels[o * 4..o * 4 + 4].copy_from_slice(&[rgb.0, rgb.1, rgb.2, 0xFF]);
+    }
+
+    /// Draws a pixel over the image, blended by its alpha.
+    fn blend_pixel(&mut self, o: usize, pixel: &[u8]) {
+        let alpha = u32::from(pixel[3]);
+        for (dst, src) in self.pixels[o * 4..o * 4 + 3].iter_mut().zip(pixel) {
+            *dst = ((u32::from(*src) * alpha + u32::from(*dst) * (255 - alpha)) / 255) as u8;
+        }
+        self.pixels[o * 4 + 3] = 0xFF;
+    }
+
+    /// Encodes the image as png.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the png encoder fails.
+    pub fn to_png(&self) -> EngineResult<Vec<u8>> {
+        let mut result = Vec::new();
+        let mut encoder = png::Encoder::new(&mut result, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(result)
+    }
+}
+
+/// Rasterizes the visible layers of the buffer with its fonts & palette.
+/// The cell size is taken from font 0, chars using other font pages are drawn with their font.
+pub fn render_to_rgba(buf: &Buffer, options: &RenderOptions) -> RgbaImage {
+    let font_size = buf.get_font_dimensions();
+    let cell_width = font_size.width as i32 + i32::from(options.use_letter_spacing);
+    let cell_height = font_size.height as i32;
+    let width = buf.get_buffer_width();
+    let height = buf.get_real_buffer_height();
+    let mut image = RgbaImage::new((width * cell_width) as u32, (height * cell_height) as u32);
+    // what the text put into each pixel, images below the text are only drawn where it's background
+    let mut text_mask =
+        vec![TextPixel::DefaultBackground; image.width as usize * image.height as usize];
+
+    // viewdata double height chars of the previous line, their lower half replaces the cell below
+    let mut upper_halves = vec![None; width as usize];
+    for y in 0..height {
+        let line_attribute = buf.get_line_attribute(y);
+        let scale_x = if line_attribute.is_double_width() {
+            2
+        } else {
+            1
+        };
+        let line_part = match line_attribute {
+            LineAttribute::DoubleHeightTop => GlyphPart::TopHalf,
+            LineAttribute::DoubleHeightBottom => GlyphPart::BottomHalf,
+            _ => GlyphPart::Full,
+        };
+        for x in 0..buf.get_line_width(y) {
+            let mut ch = buf.get_char(Position::new(x, y)).unwrap_or_default();
+            let mut part = line_part;
+            if line_part == GlyphPart::Full {
+                if let Some(upper) = upper_halves[x as usize].take() {
+                    ch = upper;
+                    part = GlyphPart::BottomHalf;
+                } else if ch.attribute.is_double_height() {
+                    upper_halves[x as usize] = Some(ch);
+                    part = GlyphPart::TopHalf;
+                }
+            }
+            let cell = Cell {
+                pos: Position::new(x * cell_width * scale_x, y * cell_height),
+                width: cell_width,
+                scale_x,
+                part,
+            };
+            render_char(buf, options, &mut image, &mut text_mask, &ch, &cell);
+        }
+    }
+
+    let placements = if options.render_kitty_images {
+        get_kitty_placements(buf, cell_width, cell_height)
+    } else {
+        Vec::new()
+    };
+    for (pos, placement) in placements.iter().filter(|(_, p)| p.z_index < 0) {
+        render_kitty_image(buf, &mut image, Some(&text_mask), placement, *pos);
+    }
+
+    if options.render_sixels {
+        for layer in buf.layers.iter().rev().filter(|layer| layer.is_visible) {
+            for sixel in &layer.sixels {
+                let pos = sixel.position + layer.offset;
+                render_sixel(
+                    &mut image,
+                    sixel,
+                    Position::new(pos.x * cell_width, pos.y * cell_height),
+                );
+            }
+        }
+    }
+
+    for (pos, placement) in placements.iter().filter(|(_, p)| p.z_index >= 0) {
+        render_kitty_image(buf, &mut image, None, placement, *pos);
+    }
+    image
+}
+
+/// Renders the buffer and encodes it as png.
+///
+/// # Errors
+///
+/// This function will return an error if the png encoder fails.
+pub fn convert_to_png(buf: &Buffer, options: &RenderOptions) -> EngineResult<Vec<u8>> {
+    render_to_rgba(buf, options).to_png()
+}
+
+/// Gets the visible kitty image placements ordered by z-index with their position in pixels.
+fn get_kitty_placements(
+    buf: &Buffer,
+    cell_width: i32,
+    cell_height: i32,
+) -> Vec<(Position, KittyPlacement)> {
+    let mut placements: Vec<(Position, KittyPlacement)> = buf
+        .layers
+        .iter()
+        .rev()
+        .filter(|layer| layer.is_visible)
+        .flat_map(|layer| {
+            layer.kitty_placements.iter().map(|placement| {
+                let pos = placement.position + layer.offset;
+                (
+                    Position::new(
+                        pos.x * cell_width + placement.cell_offset.x,
+                        pos.y * cell_height + placement.cell_offset.y,
+                    ),
+                    *placement,
+                )
+            })
+        })
+        .collect();
+    // stable, placements with the same z-index are drawn in the order they were placed
+    placements.sort_by_key(|(_, placement)| placement.z_index);
+    placements
+}
+
+fn get_color(buf: &Buffer, color: u32) -> Color {
+    buf.palette
+        .colors
+        .get(color as usize)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// What the text rendered into a pixel.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TextPixel {
+    DefaultBackground,
+    Background,
+    Foreground,
+}
+
+/// Part of the glyph drawn into a cell, double height chars span two cells.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum GlyphPart {
+    Full,
+    TopHalf,
+    BottomHalf,
+}
+
+struct Cell {
+    pos: Position,
+    width: i32,
+    /// Horizontal scaling for double width lines.
+    scale_x: i32,
+    part: GlyphPart,
+}
+
+fn render_char(
+    buf: &Buffer,
+    options: &RenderOptions,
+    image: &mut RgbaImage,
+    text_mask: &mut [TextPixel],
+    ch: &AttributedChar,
+    cell: &Cell,
+) {
+    let attr = ch.attribute;
+    let mut fg = attr.get_foreground();
+    let mut bg = attr.get_background();
+    if attr.is_bold() && fg < 8 && !buf.buffer_type.use_extended_font() {
+        fg += 8;
+    }
+    let use_ice = buf.buffer_type.use_ice_colors() || buf.terminal_state.use_ice_colors();
+    let mut draw_fg = !attr.is_concealed();
+    if attr.is_blinking() {
+        if use_ice {
+            if bg < 8 {
+                bg += 8;
+            }
+        } else if !options.blink_on {
+            draw_fg = false;
+        }
+    }
+    let bg_pixel = if bg == 0 {
+        TextPixel::DefaultBackground
+    } else {
+        TextPixel::Background
+    };
+    let fg = get_color(buf, fg);
+    let bg = get_color(buf, bg);
+
+    let font = buf.get_font(ch.get_font_page()).or_else(|| buf.get_font(0));
+    let glyph = font.and_then(|font| font.get_glyph(ch.ch));
+    let font_width = font.map_or(8, |font| font.size.width as i32).min(8);
+    let cell_height = buf.get_font_dimensions().height as i32;
+    let repeat_last_column = options.use_letter_spacing && ('\u{C0}'..='\u{DF}').contains(&ch.ch);
+
+    for y in 0..cell_height {
+        let glyph_y = match cell.part {
+            GlyphPart::Full => y,
+            GlyphPart::TopHalf => y / 2,
+            GlyphPart::BottomHalf => cell_height / 2 + y / 2,
+        };
+        let row = glyph
+            .and_then(|glyph| glyph.data.get(glyph_y as usize))
+            .copied()
+            .unwrap_or_default();
+        let underline = glyph_y == cell_height - 1 && attr.is_underlined();
+        let crossed_out = glyph_y == cell_height / 2 && attr.is_crossed_out();
+        for pixel_x in 0..cell.width * cell.scale_x {
+            let x = pixel_x / cell.scale_x;
+            let bit = if x < font_width {
+                row & (0x80 >> x) != 0
+            } else {
+                repeat_last_column && row & 0x01 != 0
+            };
+            let (color, pixel) = if draw_fg && (bit || underline || crossed_out) {
+                (fg, TextPixel::Foreground)
+            } else {
+                (bg, bg_pixel)
+            };
+            let (x, y) = (cell.pos.x + pixel_x, cell.pos.y + y);
+            image.set_pixel(x, y, color);
+            if let Some(o) = image.get_offset(x, y) {
+                text_mask[o] = pixel;
+            }
+        }
+    }
+}
+
+fn render_sixel(image: &mut RgbaImage, sixel: &Sixel, pos: Position) {
+    let width = sixel.width() as i32;
+    // pixels are stretched to the aspect ratio of the picture
+    let scale_y = sixel.get_screen_height() / (sixel.height() as i32).max(1);
+    for y in 0..sixel.get_screen_height() {
+        for x in 0..width {
+            let o = (((y / scale_y) * width + x) * 4) as usize;
+            let Some(pixel) = sixel.picture_data.get(o..o + 4) else {
+                continue;
+            };
+            if pixel[3] == 0 {
+                continue;
+            }
+            image.set_pixel(
+                pos.x + x,
+                pos.y + y,
+                Color::new(pixel[0], pixel[1], pixel[2]),
+            );
+        }
+    }
+}
+
+/// Draws a placement scaled to its display size, `text_mask` limits it to the text background.
+fn render_kitty_image(
+    buf: &Buffer,
+    image: &mut RgbaImage,
+    text_mask: Option<&[TextPixel]>,
+    placement: &KittyPlacement,
+    pos: Position,
+) {
+    let Some(kitty_image) = buf.get_kitty_image(placement.image_id) else {
+        return;
+    };
+    let source = placement.get_source_rect(kitty_image);
+    let size = placement.get_display_size(kitty_image, buf.get_font_dimensions());
+    let below_background = placement.z_index < KITTY_BELOW_BACKGROUND_Z_INDEX;
+    for y in 0..size.height {
+        let source_y = source.start.y + y * source.size.height / size.height;
+        for x in 0..size.width {
+            let Some(o) = image.get_offset(pos.x + x, pos.y + y) else {
+                continue;
+            };
+            let visible = match text_mask.map(|mask| mask[o]) {
+                None | Some(TextPixel::DefaultBackground) => true,
+                Some(TextPixel::Background) => !below_background,
+                Some(TextPixel::Foreground) => false,
+            };
+            if !visible {
+                continue;
+            }
+            let source_x = source.start.x + x * source.size.width / size.width;
+            let i = (source_y as usize * kitty_image.width as usize + source_x as usize) * 4;
+            if let Some(pixel) = kitty_image.pixels.get(i..i + 4) {
+                image.blend_pixel(o, pixel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        render_to_rgba, AttributedChar, BitFont, Buffer, BufferType, KittyImage, KittyPlacement,
+        LineAttribute, Position, RenderOptions, Sixel, TextAttribute,
+        KITTY_BELOW_BACKGROUND_Z_INDEX,
+    };
+
+    fn create_buffer(ch: char, attr: TextAttribute) -> Buffer {
+        let mut buf = Buffer::new();
+        buf.set_buffer_width(1);
+        buf.set_buffer_height(1);
+        buf.set_char(0, Position::new(0, 0), Some(AttributedChar::new(ch, attr)));
+        buf
+    }
+
+    #[test]
+    fn test_render_glyph() {
+        let buf = create_buffer('\u{DB}', TextAttribute::new(1, 4));
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(8, image.width);
+        assert_eq!(16, image.height);
+        let (r, g, b) = buf.palette.colors[1].get_rgb();
+        assert_eq!(Some([r, g, b, 0xFF]), image.get_pixel(0, 0));
+        assert_eq!(Some([r, g, b, 0xFF]), image.get_pixel(7, 15));
+    }
+
+    #[test]
+    fn test_letter_spacing() {
+        let options = RenderOptions {
+            use_letter_spacing: true,
+            ..Default::default()
+        };
+        let buf = create_buffer('\u{DB}', TextAttribute::new(7, 0));
+        let image = render_to_rgba(&buf, &options);
+        assert_eq!(9, image.width);
+        assert_eq!(Some([0xAA, 0xAA, 0xAA, 0xFF]), image.get_pixel(8, 0));
+
+        // the 9th column of a shaded block isn't a line drawing char
+        let buf = create_buffer('\u{B2}', TextAttribute::new(7, 0));
+        let image = render_to_rgba(&buf, &options);
+        for y in 0..16 {
+            assert_eq!(Some([0, 0, 0, 0xFF]), image.get_pixel(8, y));
+        }
+    }
+
+    #[test]
+    fn test_bold_and_blink() {
+        let mut attr = TextAttribute::new(1, 1);
+        attr.set_is_bold(true);
+        attr.set_is_blinking(true);
+        let mut buf = create_buffer('\u{DB}', attr);
+        let bright_blue = buf.palette.colors[9].get_rgb();
+        let blue = buf.palette.colors[1].get_rgb();
+
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(
+            Some([bright_blue.0, bright_blue.1, bright_blue.2, 0xFF]),
+            image.get_pixel(0, 0)
+        );
+
+        let options = RenderOptions {
+            blink_on: false,
+            ..Default::default()
+        };
+        let image = render_to_rgba(&buf, &options);
+        assert_eq!(Some([blue.0, blue.1, blue.2, 0xFF]), image.get_pixel(0, 0));
+
+        // with ice colors the blink bit selects the bright background
+        buf.buffer_type = BufferType::LegacyIce;
+        buf.set_char(0, Position::new(0, 0), Some(AttributedChar::new(' ', attr)));
+        let image = render_to_rgba(&buf, &options);
+        assert_eq!(
+            Some([bright_blue.0, bright_blue.1, bright_blue.2, 0xFF]),
+            image.get_pixel(0, 0)
+        );
+    }
+
+    #[test]
+    fn test_font_page() {
+        let mut buf = create_buffer('A', TextAttribute::default());
+        let mut ch = AttributedChar::new('A', TextAttribute::default());
+        ch.set_font_page(1);
+        buf.set_font(1, BitFont::from_name("Amiga Topaz 1").unwrap());
+        buf.set_char(0, Position::new(0, 0), Some(ch));
+        let topaz = render_to_rgba(&buf, &RenderOptions::default());
+
+        buf.remove_font(1);
+        let vga = render_to_rgba(&buf, &RenderOptions::default());
+        assert_ne!(topaz, vga);
+    }
+
+    #[test]
+    fn test_sixel() {
+        let mut buf = create_buffer(' ', TextAttribute::default());
+        let sixel =
+            Sixel::parse_from(Position::default(), 1, 1, [0; 4], "#1;2;100;0;0#1~").unwrap();
+        buf.layers[0].sixels.push(sixel);
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(Some([0xFF, 0, 0, 0xFF]), image.get_pixel(0, 5));
+        assert_eq!(Some([0, 0, 0, 0xFF]), image.get_pixel(0, 6));
+        assert_eq!(Some([0, 0, 0, 0xFF]), image.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn test_kitty_image_z_index() {
+        let red = Some([0xFF, 0, 0, 0xFF]);
+        let gray = Some([0xAA, 0xAA, 0xAA, 0xFF]);
+        let mut buf = create_buffer('\u{DF}', TextAttribute::new(7, 0));
+        buf.add_kitty_image(KittyImage::new(1, 1, 1, vec![0xFF, 0, 0, 0xFF]));
+        let mut placement = KittyPlacement::new(1, Position::default());
+        placement.columns = 1;
+        placement.rows = 1;
+        buf.add_kitty_placement(placement);
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(red, image.get_pixel(0, 0));
+        assert_eq!(red, image.get_pixel(7, 15));
+
+        // below the text the upper half block stays visible
+        buf.layers[0].kitty_placements[0].z_index = -1;
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(gray, image.get_pixel(0, 0));
+        assert_eq!(red, image.get_pixel(0, 15));
+
+        // a cell background only covers images far below
+        buf.set_char(
+            0,
+            Position::new(0, 0),
+            Some(AttributedChar::new('\u{DF}', TextAttribute::new(7, 1))),
+        );
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(red, image.get_pixel(0, 15));
+        buf.layers[0].kitty_placements[0].z_index = KITTY_BELOW_BACKGROUND_Z_INDEX - 1;
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        let (r, g, b) = buf.palette.colors[1].get_rgb();
+        assert_eq!(Some([r, g, b, 0xFF]), image.get_pixel(0, 15));
+
+        let options = RenderOptions {
+            render_kitty_images: false,
+            ..Default::default()
+        };
+        buf.layers[0].kitty_placements[0].z_index = 0;
+        assert_eq!(gray, render_to_rgba(&buf, &options).get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_double_width_line() {
+        let mut buf = create_buffer('\u{DB}', TextAttribute::new(7, 0));
+        buf.set_buffer_width(2);
+        buf.set_line_attribute(0, LineAttribute::DoubleWidth);
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(16, image.width);
+        assert_eq!(Some([0xAA, 0xAA, 0xAA, 0xFF]), image.get_pixel(15, 0));
+    }
+
+    #[test]
+    fn test_double_height() {
+        // upper half block, the bottom line shows the empty lower half
+        let mut buf = create_buffer('\u{DF}', TextAttribute::new(7, 0));
+        buf.set_line_attribute(0, LineAttribute::DoubleHeightTop);
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(Some([0xAA, 0xAA, 0xAA, 0xFF]), image.get_pixel(0, 8));
+        buf.set_line_attribute(0, LineAttribute::DoubleHeightBottom);
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(Some([0, 0, 0, 0xFF]), image.get_pixel(0, 0));
+
+        // viewdata double height chars span the cell below
+        let mut attr = TextAttribute::new(7, 0);
+        attr.set_is_double_height(true);
+        let mut buf = create_buffer('\u{DB}', attr);
+        buf.set_buffer_height(2);
+        buf.set_char(
+            0,
+            Position::new(0, 1),
+            Some(AttributedChar::new(' ', TextAttribute::new(7, 0))),
+        );
+        let image = render_to_rgba(&buf, &RenderOptions::default());
+        assert_eq!(Some([0xAA, 0xAA, 0xAA, 0xFF]), image.get_pixel(0, 31));
+    }
+
+    #[test]
+    fn test_png() {
+        let buf = create_buffer('A', TextAttribute::default());
+        let png = render_to_rgba(&buf, &RenderOptions::default())
+            .to_png()
+            .unwrap();
+        assert_eq!(b"\x89PNG\r\n\x1A\n", &png[0..8]);
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use crate::{Line, Sixel};
+
+pub const DEFAULT_SCROLLBACK_LINES: usize = 2000;
+
+/// Lines that were scrolled off the top of a terminal screen, oldest line first.
+/// Once the maximum line count is reached the oldest lines are dropped.
+#[derive(Debug, Clone)]
+pub struct Scrollback {
+    lines: VecDeque<Line>,
+    /// Sixels scrolled off the screen, the y position is the index of their top line.
+    sixels: Vec<Sixel>,
+    max_lines: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCROLLBACK_LINES)
+    }
+}
+
+impl Scrollback {
+    pub fn new(max_lines: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            sixels: Vec::new(),
+            max_lines,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn get_max_lines(&self) -> usize {
+        self.max_lines
+    }
+
+    /// Sets the maximum number of lines kept, excess lines are dropped starting with the oldest.
+    pub fn set_max_lines(&mut self, max_lines: usize) {
+        self.max_lines = max_lines;
+        self.truncate();
+    }
+
+    /// Gets a line, 0 is the oldest line.
+    pub fn get_line(&self, index: usize) -> Option<&Line> {
+        self.lines.get(index)
+    }
+
+    /// Iterates from the oldest to the most recent line.
+    pub fn lines(&self) -> impl DoubleEndedIterator<Item = &Line> {
+        self.lines.iter()
+    }
+
+    pub fn push(&mut self, line: Line) {
+        if self.max_lines == 0 {
+            return;
+        }
+        self.lines.push_back(line);
+        self.truncate();
+    }
+
+    /// Removes the most recent line, sixels starting on it are removed as well.
+    pub fn pop(&mut self) -> Option<Line> {
+        let line = self.lines.pop_back();
+        let len = self.lines.len() as i32;
+        self.sixels.retain(|sixel| sixel.position.y < len);
+        line
+    }
+
+    /// Sixels that were scrolled off the screen, their y position is the line index.
+    pub fn get_sixels(&self) -> &[Sixel] {
+        &self.sixels
+    }
+
+    /// Adds a sixel, its y position has to be the index of its top line.
+    pub fn push_sixel(&mut self, sixel: Sixel) {
+        if sixel.position.y >= 0 && sixel.position.y < self.lines.len() as i32 {
+            self.sixels.push(sixel);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.sixels.clear();
+    }
+
+    fn truncate(&mut self) {
+        while self.lines.len() > self.max_lines {
+            self.lines.pop_front();
+            // sixels are dropped together with their top line
+            self.sixels.retain_mut(|sixel| {
+                sixel.position.y -= 1;
+                sixel.position.y >= 0
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AttributedChar, Line, Position, Sixel, TextAttribute};
+
+    use super::Scrollback;
+
+    fn line(ch: char) -> Line {
+        let mut line = Line::new();
+        line.set_char(0, Some(AttributedChar::new(ch, TextAttribute::default())));
+        line
+    }
+
+    #[test]
+    fn test_max_lines() {
+        let mut scrollback = Scrollback::new(3);
+        for ch in ['a', 'b', 'c', 'd'] {
+            scrollback.push(line(ch));
+        }
+        assert_eq!(3, scrollback.len());
+        assert_eq!('b', scrollback.get_line(0).unwrap().chars[0].unwrap().ch);
+        assert_eq!('d', scrollback.get_line(2).unwrap().chars[0].unwrap().ch);
+        assert!(scrollback.get_line(3).is_none());
+
+        scrollback.set_max_lines(1);
+        assert_eq!(1, scrollback.len());
+        assert_eq!('d', scrollback.get_line(0).unwrap().chars[0].unwrap().ch);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut scrollback = Scrollback::new(0);
+        scrollback.push(line('a'));
+        assert!(scrollback.is_empty());
+    }
+
+    #[test]
+    fn test_sixels() {
+        let mut scrollback = Scrollback::new(3);
+        for ch in ['a', 'b', 'c'] {
+            scrollback.push(line(ch));
+        }
+        scrollback.push_sixel(Sixel::new(Position::new(0, 0)));
+        scrollback.push_sixel(Sixel::new(Position::new(0, 2)));
+        // lines that aren't in the scrollback can't hold sixels
+        scrollback.push_sixel(Sixel::new(Position::new(0, 3)));
+        assert_eq!(2, scrollback.get_sixels().len());
+
+        scrollback.push(line('d'));
+        assert_eq!(1, scrollback.get_sixels().len());
+        assert_eq!(Position::new(0, 1), scrollback.get_sixels()[0].position);
+
+        scrollback.pop();
+        scrollback.pop();
+        assert!(scrollback.get_sixels().is_empty());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::Position;
+use std::cmp::Ordering;
+
+use crate::{Position, Rectangle};
 
 #[derive(Debug, Clone)]
 pub struct Selection {
@@ -39,4 +41,23 @@ impl Selection {
         self.lead = lead;
         self.lead_pos = Position::new(lead.0 as i32, lead.1 as i32);
     }
+
+    /// Gets the selected block, anchor & lead are both inclusive.
+    pub fn as_rectangle(&self) -> Rectangle {
+        Rectangle::from_coords(
+            self.anchor_pos.x.min(self.lead_pos.x),
+            self.anchor_pos.y.min(self.lead_pos.y),
+            self.anchor_pos.x.max(self.lead_pos.x),
+            self.anchor_pos.y.max(self.lead_pos.y),
+        )
+    }
+
+    /// Gets start & end position of a stream selection in reading order.
+    pub fn get_stream_range(&self) -> (Position, Position) {
+        let (a, l) = (self.anchor_pos, self.lead_pos);
+        match a.y.cmp(&l.y).then(a.x.cmp(&l.x)) {
+            Ordering::Greater => (l, a),
+            _ => (a, l),
+        }
+    }
 }
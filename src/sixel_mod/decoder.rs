@@ -0,0 +1,331 @@
+use std::{
+    collections::BTreeMap,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{ParserError, Position};
+
+use super::Sixel;
+
+/// How the sixels received by the parser are decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SixelDecoding {
+    /// Pictures are decoded while parsing and errors are returned by the parser.
+    Synchronous,
+    /// Only pictures that don't move the caret - non-scrolling ones (DECSDM) with private color
+    /// registers - are decoded by a pool of `workers` threads, scrolling pictures are still decoded while parsing.
+    /// If `queue_size` pictures are pending the parser waits for the oldest one.
+    WorkerPool { workers: usize, queue_size: usize },
+}
+
+impl Default for SixelDecoding {
+    fn default() -> Self {
+        if cfg!(target_arch = "wasm32") {
+            SixelDecoding::Synchronous
+        } else {
+            SixelDecoding::WorkerPool {
+                workers: 2,
+                queue_size: 16,
+            }
+        }
+    }
+}
+
+/// A sixel waiting to be decoded, the arguments of [`Sixel::parse_from`].
+pub(crate) struct SixelJob {
+    pub position: Position,
+    pub vertical_scale: i32,
+    pub horizontal_scale: i32,
+    pub background: [u8; 4],
+    pub data: String,
+}
+
+impl SixelJob {
+    pub fn decode(&self) -> Result<Sixel, ParserError> {
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            Sixel::parse_from(
+                self.position,
+                self.vertical_scale,
+                self.horizontal_scale,
+                self.background,
+                &self.data,
+            )
+            .map_err(|err| match err.downcast::<ParserError>() {
+                Ok(err) => *err,
+                Err(err) => ParserError::Error(err.to_string()),
+            })
+        }));
+        result.unwrap_or(Err(ParserError::ErrorInSixelEngine(
+            "sixel decoder panicked",
+        )))
+    }
+}
+
+type DecodedSixel = (u64, Result<Sixel, ParserError>);
+
+struct WorkerPool {
+    jobs: Sender<(u64, SixelJob)>,
+    results: Receiver<DecodedSixel>,
+    _workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    fn new(workers: usize) -> Self {
+        let (jobs, job_receiver) = channel::<(u64, SixelJob)>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                thread::spawn(move || loop {
+                    // the workers end when the pool is dropped
+                    let Ok(Ok((id, job))) = job_receiver.lock().map(|receiver| receiver.recv())
+                    else {
+                        break;
+                    };
+                    if result_sender.send((id, job.decode())).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs,
+            results,
+            _workers: workers,
+        }
+    }
+}
+
+/// Decodes sixels in the background, the results are handed out in the order the pictures were submitted.
+#[derive(Default)]
+pub struct SixelDecoder {
+    decoding: SixelDecoding,
+    pool: Option<WorkerPool>,
+    /// Id of the next submitted picture.
+    next_id: u64,
+    /// Id of the next picture handed out.
+    next_result: u64,
+    finished: BTreeMap<u64, Result<Sixel, ParserError>>,
+}
+
+impl SixelDecoder {
+    pub fn new(decoding: SixelDecoding) -> Self {
+        Self {
+            decoding,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_decoding(&self) -> SixelDecoding {
+        self.decoding
+    }
+
+    /// Changes the decoding, pictures that are already pending are still decoded by the pool.
+    /// If the number of workers changes the pool is rebuilt once the pending pictures are decoded.
+    pub fn set_decoding(&mut self, decoding: SixelDecoding) {
+        if Self::get_workers(decoding) != Self::get_workers(self.decoding) {
+            if let Some(pool) = self.pool.take() {
+                while self.pending() > self.finished.len() {
+                    let Ok((id, result)) = pool.results.recv() else {
+                        // a worker died, the remaining pictures are lost
+                        self.clear();
+                        break;
+                    };
+                    if id >= self.next_result {
+                        self.finished.insert(id, result);
+                    }
+                }
+            }
+        }
+        self.decoding = decoding;
+    }
+
+    fn get_workers(decoding: SixelDecoding) -> usize {
+        match decoding {
+            SixelDecoding::WorkerPool { workers, .. } => workers.max(1),
+            SixelDecoding::Synchronous => 1,
+        }
+    }
+
+    /// Number of pictures submitted but not handed out yet.
+    pub fn pending(&self) -> usize {
+        (self.next_id - self.next_result) as usize
+    }
+
+    pub(crate) fn submit(&mut self, job: SixelJob) {
+        let workers = Self::get_workers(self.decoding);
+        let pool = self.pool.get_or_insert_with(|| WorkerPool::new(workers));
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Err(err) = pool.jobs.send((id, job)) {
+            // the workers are gone, decode it here
+            self.finished.insert(id, err.0 .1.decode());
+        }
+    }
+
+    /// Gets the next picture if it's decoded, doesn't block.
+    pub fn try_next(&mut self) -> Option<Result<Sixel, ParserError>> {
+        if let Some(pool) = &self.pool {
+            while let Ok((id, result)) = pool.results.try_recv() {
+                if id >= self.next_result {
+                    self.finished.insert(id, result);
+                }
+            }
+        }
+        self.take_next()
+    }
+
+    /// Waits for the next picture, `None` if nothing is pending.
+    pub fn wait_next(&mut self) -> Option<Result<Sixel, ParserError>> {
+        while self.pending() > 0 && !self.finished.contains_key(&self.next_result) {
+            let pool = self.pool.as_ref()?;
+            let Ok((id, result)) = pool.results.recv() else {
+                // a worker died, the remaining pictures are lost
+                self.next_result = self.next_id;
+                return None;
+            };
+            if id >= self.next_result {
+                self.finished.insert(id, result);
+            }
+        }
+        self.take_next()
+    }
+
+    /// Waits for the pending pictures and hands out the decoded ones,
+    /// the errors stay pending and are handed out by the next [`SixelDecoder::try_next`].
+    pub fn take_decoded(&mut self) -> Vec<Sixel> {
+        let mut sixels = Vec::new();
+        let mut errors = Vec::new();
+        while let Some(result) = self.wait_next() {
+            match result {
+                Ok(sixel) => sixels.push(sixel),
+                Err(err) => errors.push(err),
+            }
+        }
+        for err in errors {
+            self.finished.insert(self.next_id, Err(err));
+            self.next_id += 1;
+        }
+        sixels
+    }
+
+    /// Discards the pending pictures.
+    pub fn clear(&mut self) {
+        self.next_result = self.next_id;
+        self.finished.clear();
+    }
+
+    fn take_next(&mut self) -> Option<Result<Sixel, ParserError>> {
+        let result = self.finished.remove(&self.next_result)?;
+        self.next_result += 1;
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SixelDecoder, SixelDecoding, SixelJob};
+    use crate::{ParserError, Position};
+
+    fn job(y: i32, data: &str) -> SixelJob {
+        SixelJob {
+            position: Position::new(0, y),
+            vertical_scale: 1,
+            horizontal_scale: 1,
+            background: [0; 4],
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_results_in_order() {
+        let mut decoder = SixelDecoder::new(SixelDecoding::WorkerPool {
+            workers: 3,
+            queue_size: 16,
+        });
+        decoder.submit(job(0, &"!500~-".repeat(200)));
+        for y in 1..5 {
+            decoder.submit(job(y, "#1~"));
+        }
+        assert_eq!(5, decoder.pending());
+        for y in 0..5 {
+            let sixel = decoder.wait_next().unwrap().unwrap();
+            assert_eq!(y, sixel.position.y);
+        }
+        assert_eq!(0, decoder.pending());
+        assert!(decoder.wait_next().is_none());
+        assert!(decoder.try_next().is_none());
+    }
+
+    #[test]
+    fn test_error() {
+        let mut decoder = SixelDecoder::default();
+        decoder.submit(job(0, "#1;2;3~"));
+        decoder.submit(job(1, "#1~"));
+        assert!(matches!(
+            decoder.wait_next(),
+            Some(Err(ParserError::InvalidColorInSixelSequence))
+        ));
+        assert_eq!(1, decoder.wait_next().unwrap().unwrap().position.y);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut decoder = SixelDecoder::default();
+        decoder.submit(job(0, "#1~"));
+        decoder.clear();
+        assert_eq!(0, decoder.pending());
+        decoder.submit(job(1, "#1~"));
+        assert_eq!(1, decoder.wait_next().unwrap().unwrap().position.y);
+        assert!(decoder.wait_next().is_none());
+    }
+
+    #[test]
+    fn test_take_decoded() {
+        let mut decoder = SixelDecoder::default();
+        decoder.submit(job(0, "#1~"));
+        decoder.submit(job(1, "#1;2;3~"));
+        decoder.submit(job(2, "#1~"));
+        let sixels = decoder.take_decoded();
+        assert_eq!(2, sixels.len());
+        assert_eq!(2, sixels[1].position.y);
+        assert_eq!(1, decoder.pending());
+        assert!(matches!(
+            decoder.try_next(),
+            Some(Err(ParserError::InvalidColorInSixelSequence))
+        ));
+        assert_eq!(0, decoder.pending());
+    }
+
+    #[test]
+    fn test_change_workers() {
+        let mut decoder = SixelDecoder::new(SixelDecoding::WorkerPool {
+            workers: 1,
+            queue_size: 16,
+        });
+        for y in 0..3 {
+            decoder.submit(job(y, "#1~"));
+        }
+        decoder.set_decoding(SixelDecoding::WorkerPool {
+            workers: 1,
+            queue_size: 4,
+        });
+        assert!(decoder.pool.is_some());
+        decoder.set_decoding(SixelDecoding::WorkerPool {
+            workers: 3,
+            queue_size: 4,
+        });
+        assert!(decoder.pool.is_none());
+        decoder.submit(job(3, "#1~"));
+        for y in 0..4 {
+            assert_eq!(y, decoder.wait_next().unwrap().unwrap().position.y);
+        }
+    }
+}
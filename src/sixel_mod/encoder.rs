@@ -0,0 +1,425 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use crate::{
+    render_to_rgba, Buffer, EngineResult, ParserError, Rectangle, RenderOptions, RgbaImage,
+};
+
+use super::Sixel;
+
+/// Options for the sixel encoder.
+#[derive(Clone, Debug)]
+pub struct SixelEncoderOptions {
+    /// Number of color registers the image is quantized to.
+    pub max_colors: usize,
+    /// Pixels with an alpha below 128 aren't drawn and the background is kept (P2 = 1).
+    /// Otherwise alpha is ignored.
+    pub transparent: bool,
+}
+
+impl SixelEncoderOptions {
+    pub fn new() -> Self {
+        SixelEncoderOptions {
+            max_colors: 256,
+            transparent: false,
+        }
+    }
+}
+
+impl Default for SixelEncoderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Rgb = [u8; 3];
+
+/// Reduces the colors to `max_colors` with median cut, weighted by the pixel count.
+fn quantize(histogram: &HashMap<Rgb, usize>, max_colors: usize) -> Vec<Rgb> {
+    let mut colors: Vec<(Rgb, usize)> = histogram.iter().map(|(c, n)| (*c, *n)).collect();
+    colors.sort_unstable();
+    if colors.len() <= max_colors {
+        return colors.into_iter().map(|(c, _)| c).collect();
+    }
+
+    let channel_range = |colors: &[(Rgb, usize)], channel: usize| {
+        let (min, max) = colors.iter().fold((255, 0), |(min, max), (c, _)| {
+            (c[channel].min(min), c[channel].max(max))
+        });
+        max.saturating_sub(min)
+    };
+    let widest_channel = |colors: &[(Rgb, usize)]| {
+        (0..3)
+            .map(|channel| (channel_range(colors, channel), channel))
+            .max()
+            .unwrap_or_default()
+    };
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        let Some((i, (_, channel))) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| (i, widest_channel(colors)))
+            .max_by_key(|(_, (range, _))| *range)
+        else {
+            break;
+        };
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|(c, _)| c[channel]);
+        let total: usize = colors.iter().map(|(_, n)| n).sum();
+        let mut count = 0;
+        let mut split = 1;
+        for (j, (_, n)) in colors.iter().enumerate() {
+            count += n;
+            if count * 2 >= total {
+                split = j + 1;
+                break;
+            }
+        }
+        let upper = colors.split_off(split.clamp(1, colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: usize = colors.iter().map(|(_, n)| n).sum();
+            let mut sum = [0; 3];
+            for (c, n) in colors {
+                for channel in 0..3 {
+                    sum[channel] += c[channel] as usize * n;
+                }
+            }
+            sum.map(|s| ((s + total / 2) / total) as u8)
+        })
+        .collect()
+}
+
+fn find_nearest(palette: &[Rgb], color: Rgb) -> usize {
+    let distance = |c: &Rgb| -> i32 {
+        (0..3)
+            .map(|i| (i32::from(c[i]) - i32::from(color[i])).pow(2))
+            .sum()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, c)| distance(c))
+        .map_or(0, |(i, _)| i)
+}
+
+fn push_run(result: &mut String, ch: char, count: usize) {
+    if count > 3 {
+        let _ = write!(result, "!{count}{ch}");
+    } else {
+        (0..count).for_each(|_| result.push(ch));
+    }
+}
+
+/// Gets the color register of every pixel, `None` for transparent pixels.
+fn map_pixels(
+    image: &RgbaImage,
+    options: &SixelEncoderOptions,
+) -> EngineResult<(Vec<Rgb>, Vec<Option<usize>>)> {
+    if options.max_colors == 0 {
+        return Err(Box::new(ParserError::Error(
+            "sixel encoder needs at least 1 color".to_string(),
+        )));
+    }
+    if image.pixels.len() != image.width as usize * image.height as usize * 4 {
+        return Err(Box::new(ParserError::InvalidPictureSize));
+    }
+    let is_drawn = |pixel: &[u8]| !options.transparent || pixel[3] >= 128;
+
+    let mut histogram = HashMap::new();
+    for pixel in image.pixels.chunks_exact(4).filter(|p| is_drawn(p)) {
+        *histogram.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0) += 1;
+    }
+    let palette = quantize(&histogram, options.max_colors);
+    let mut registers: HashMap<Rgb, usize> = HashMap::new();
+    let indices = image
+        .pixels
+        .chunks_exact(4)
+        .map(|pixel| {
+            if !is_drawn(pixel) {
+                return None;
+            }
+            let color = [pixel[0], pixel[1], pixel[2]];
+            Some(
+                *registers
+                    .entry(color)
+                    .or_insert_with(|| find_nearest(&palette, color)),
+            )
+        })
+        .collect();
+    Ok((palette, indices))
+}
+
+impl RgbaImage {
+    /// Encodes the image as DCS sixel string with raster attributes & RGB color registers.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pixel data doesn't match the size or `max_colors` is 0.
+    pub fn to_sixel(&self, options: &SixelEncoderOptions) -> EngineResult<String> {
+        let (palette, indices) = map_pixels(self, options)?;
+        let width = self.width as usize;
+        let height = self.height as usize;
+
+        let mut result = String::new();
+        let _ = write!(
+            result,
+            "\x1BP0;{};0q\"1;1;{width};{height}",
+            u8::from(options.transparent)
+        );
+        for (i, color) in palette.iter().enumerate() {
+            let [r, g, b] = color.map(|c| (c as u32 * 100 + 127) / 255);
+            let _ = write!(result, "#{i};2;{r};{g};{b}");
+        }
+
+        for band in 0..height.div_ceil(6) {
+            if band > 0 {
+                result.push('-');
+            }
+            // sixel bits of each column by color register
+            let mut columns: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+            for row in 0..6.min(height - band * 6) {
+                let y = band * 6 + row;
+                for x in 0..width {
+                    if let Some(color) = indices[y * width + x] {
+                        columns.entry(color).or_insert_with(|| vec![0; width])[x] |= 1 << row;
+                    }
+                }
+            }
+            for (i, (color, bits)) in columns.iter().enumerate() {
+                if i > 0 {
+                    result.push('$');
+                }
+                let _ = write!(result, "#{color}");
+                let used = bits.iter().rposition(|b| *b != 0).map_or(0, |x| x + 1);
+                let mut run = 0;
+                let mut last = None;
+                for b in &bits[..used] {
+                    let ch = char::from(b'?' + b);
+                    if last != Some(ch) {
+                        if let Some(last) = last {
+                            push_run(&mut result, last, run);
+                        }
+                        last = Some(ch);
+                        run = 0;
+                    }
+                    run += 1;
+                }
+                if let Some(last) = last {
+                    push_run(&mut result, last, run);
+                }
+            }
+        }
+        result.push_str("\x1B\\");
+        Ok(result)
+    }
+
+    /// Gets a part of the image, the rectangle is clipped at the image bounds.
+    pub fn crop(&self, rect: Rectangle) -> RgbaImage {
+        let x0 = rect.start.x.clamp(0, self.width as i32) as u32;
+        let y0 = rect.start.y.clamp(0, self.height as i32) as u32;
+        let x1 = (rect.start.x + rect.size.width).clamp(x0 as i32, self.width as i32) as u32;
+        let y1 = (rect.start.y + rect.size.height).clamp(y0 as i32, self.height as i32) as u32;
+        let mut result = RgbaImage::new(x1 - x0, y1 - y0);
+        for y in y0..y1 {
+            let src = (y as usize * self.width as usize + x0 as usize) * 4;
+            let dst = (y - y0) as usize * result.width as usize * 4;
+            let len = result.width as usize * 4;
+            result.pixels[dst..dst + len].copy_from_slice(&self.pixels[src..src + len]);
+        }
+        result
+    }
+}
+
+impl Sixel {
+    /// Encodes the picture as DCS sixel string.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the picture data is invalid or `max_colors` is 0.
+    pub fn to_sixel(&self, options: &SixelEncoderOptions) -> EngineResult<String> {
+        RgbaImage {
+            width: self.width,
+            height: self.height,
+            pixels: self.picture_data.clone(),
+        }
+        .to_sixel(options)
+    }
+}
+
+/// Renders a region of the buffer, given in cells, and encodes it as DCS sixel string.
+///
+/// # Errors
+///
+/// This function will return an error if `max_colors` is 0.
+pub fn convert_to_sixel(
+    buf: &Buffer,
+    region: Rectangle,
+    render_options: &RenderOptions,
+    options: &SixelEncoderOptions,
+) -> EngineResult<String> {
+    let font_size = buf.get_font_dimensions();
+    let cell_width = font_size.width as i32 + i32::from(render_options.use_letter_spacing);
+    let cell_height = font_size.height as i32;
+    let image = render_to_rgba(buf, render_options);
+    let region = Rectangle::from(
+        region.start.x * cell_width,
+        region.start.y * cell_height,
+        region.size.width * cell_width,
+        region.size.height * cell_height,
+    );
+    image.crop(region).to_sixel(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{convert_to_sixel, quantize, SixelEncoderOptions};
+    use crate::{
+        AttributedChar, Buffer, Position, Rectangle, RenderOptions, RgbaImage, Sixel, TextAttribute,
+    };
+
+    fn decode(sixel: &str) -> Sixel {
+        let data = sixel
+            .split_once('q')
+            .unwrap()
+            .1
+            .strip_suffix("\x1B\\")
+            .unwrap();
+        Sixel::parse_from(Position::default(), 1, 1, [0; 4], data).unwrap()
+    }
+
+    fn create_image(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let o = ((y * width + x) * 4) as usize;
+                image.pixels[o..o + 4].copy_from_slice(&pixel(x, y));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let image = create_image(13, 14, |x, y| match (x + y) % 3 {
+            0 => [255, 0, 0, 255],
+            1 => [0, 255, 0, 255],
+            _ => [255, 255, 255, 255],
+        });
+        let sixel = image.to_sixel(&SixelEncoderOptions::default()).unwrap();
+        assert!(sixel.starts_with("\x1BP0;0;0q\"1;1;13;14#0;2;0;100;0"));
+        let decoded = decode(&sixel);
+        assert_eq!(13, decoded.width());
+        assert_eq!(14, decoded.height());
+        assert_eq!(image.pixels, decoded.picture_data);
+    }
+
+    #[test]
+    fn test_run_length() {
+        let image = create_image(20, 6, |x, _| {
+            if x < 10 {
+                [0, 0, 0, 255]
+            } else {
+                [255, 255, 255, 255]
+            }
+        });
+        let sixel = image.to_sixel(&SixelEncoderOptions::default()).unwrap();
+        assert!(sixel.ends_with("#0!10~$#1!10?!10~\x1B\\"), "{sixel}");
+        assert_eq!(image.pixels, decode(&sixel).picture_data);
+    }
+
+    #[test]
+    fn test_transparency() {
+        let image = create_image(4, 2, |x, _| {
+            if x % 2 == 0 {
+                [0, 0, 255, 255]
+            } else {
+                [0, 0, 0, 0]
+            }
+        });
+        let options = SixelEncoderOptions {
+            transparent: true,
+            ..Default::default()
+        };
+        let sixel = image.to_sixel(&options).unwrap();
+        assert!(sixel.starts_with("\x1BP0;1;0q"));
+        assert_eq!(image.pixels, decode(&sixel).picture_data);
+    }
+
+    #[test]
+    fn test_quantize() {
+        let mut histogram = HashMap::new();
+        for i in 0..64u8 {
+            histogram.insert([i * 4, 0, 0], 1);
+            histogram.insert([0, 0, i * 4], 1);
+        }
+        let palette = quantize(&histogram, 2);
+        assert_eq!(2, palette.len());
+        // red & blue shades end up in different registers
+        assert!(palette.iter().any(|c| c[0] > 100 && c[2] < 10));
+        assert!(palette.iter().any(|c| c[2] > 100 && c[0] < 10));
+
+        let image = create_image(16, 16, |x, y| [(x * 16) as u8, (y * 16) as u8, 128, 255]);
+        let options = SixelEncoderOptions {
+            max_colors: 16,
+            ..Default::default()
+        };
+        let decoded = decode(&image.to_sixel(&options).unwrap());
+        let mut colors: Vec<_> = decoded.picture_data.chunks(4).collect();
+        colors.sort_unstable();
+        colors.dedup();
+        assert!(colors.len() <= 16);
+        // every pixel is close to the original
+        for (a, b) in image.pixels.iter().zip(&decoded.picture_data) {
+            assert!(a.abs_diff(*b) <= 32);
+        }
+    }
+
+    #[test]
+    fn test_buffer_region() {
+        let mut buf = Buffer::new();
+        buf.set_buffer_width(10);
+        buf.set_buffer_height(5);
+        let attr = TextAttribute::new(1, 4);
+        buf.set_char(
+            0,
+            Position::new(2, 1),
+            Some(AttributedChar::new('\u{DB}', attr)),
+        );
+        let sixel = convert_to_sixel(
+            &buf,
+            Rectangle::from(1, 1, 2, 1),
+            &RenderOptions::default(),
+            &SixelEncoderOptions::default(),
+        )
+        .unwrap();
+        let decoded = decode(&sixel);
+        assert_eq!(16, decoded.width());
+        assert_eq!(16, decoded.height());
+        let (r, g, b) = buf.palette.colors[1].get_rgb();
+        assert_eq!([0, 0, 0, 0xFF], decoded.picture_data[0..4]);
+        assert_eq!([r, g, b, 0xFF], decoded.picture_data[8 * 4..9 * 4]);
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let image = RgbaImage::new(2, 2);
+        let options = SixelEncoderOptions {
+            max_colors: 0,
+            ..Default::default()
+        };
+        assert!(image.to_sixel(&options).is_err());
+    }
+}
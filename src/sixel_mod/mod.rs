@@ -1,5 +1,18 @@
 use crate::{EngineResult, Palette, ParserError, Position, Rectangle, Size};
 
+mod decoder;
+pub use decoder::*;
+mod encoder;
+pub use encoder::*;
+
+/// Largest picture the parser decodes, bigger raster attributes or sixel data fail with
+/// [`ParserError::InvalidPictureSize`] instead of allocating the pixels.
+pub const MAX_SIXEL_WIDTH: usize = 4096;
+pub const MAX_SIXEL_HEIGHT: usize = 4096;
+
+/// Number of color registers a sixel can define, definitions of higher registers are ignored.
+pub const MAX_SIXEL_COLOR_REGISTERS: usize = 1024;
+
 #[derive(Clone, Debug, Copy)]
 pub enum SixelState {
     Read,
@@ -29,14 +42,16 @@ struct SixelParser {
     picture_data: Vec<Vec<u8>>,
     vertical_scale: i32,
     horizontal_scale: i32,
+    /// Color of the pixels that aren't drawn, alpha 0 keeps them transparent (P2 = 1).
+    background: [u8; 4],
 
     height_set: bool,
 }
 
-impl Default for SixelParser {
-    fn default() -> Self {
+impl SixelParser {
+    fn new(palette: Palette, background: [u8; 4]) -> Self {
         Self {
-            current_sixel_palette: Palette::default(),
+            current_sixel_palette: palette,
             current_sixel_color: 0,
             sixel_cursor: Position::default(),
             parsed_numbers: Vec::new(),
@@ -45,25 +60,25 @@ impl Default for SixelParser {
             picture_data: Vec::new(),
             vertical_scale: 1,
             horizontal_scale: 1,
+            background,
         }
     }
-}
 
-impl SixelParser {
-    pub fn parse_from(
-        &mut self,
-        sixel: &mut Sixel,
-        _default_bg_color: [u8; 4],
-        data: &str,
-    ) -> EngineResult<bool> {
+    pub fn parse_from(&mut self, sixel: &mut Sixel, data: &str) -> EngineResult<bool> {
+        self.vertical_scale = sixel.vertical_scale;
+        self.horizontal_scale = sixel.horizontal_scale;
         for ch in data.chars() {
             self.parse_char(ch)?;
         }
         self.parse_char('#')?;
 
-        for y in 0..self.height() {
-            let line = &self.picture_data[y as usize];
-            sixel.picture_data.extend(line);
+        // lines only grow as far as they're drawn, the picture is as wide as the longest line
+        let width = self.width() as usize;
+        for line in &mut self.picture_data {
+            while line.len() < width * 4 {
+                line.extend_from_slice(&self.background);
+            }
+            sixel.picture_data.extend(line.iter());
         }
         sixel.width = self.width();
         sixel.height = self.height();
@@ -73,11 +88,11 @@ impl SixelParser {
     }
 
     pub fn width(&self) -> u32 {
-        if let Some(first_line) = self.picture_data.get(0) {
-            first_line.len() as u32 / 4
-        } else {
-            0
-        }
+        self.picture_data
+            .iter()
+            .map(|line| line.len() as u32 / 4)
+            .max()
+            .unwrap_or(0)
     }
 
     pub fn height(&self) -> u32 {
@@ -95,34 +110,43 @@ impl SixelParser {
                         Some(number) => number,
                         _ => 0,
                     };
-                    self.parsed_numbers.push(d * 10 + ch as i32 - b'0' as i32);
+                    self.parsed_numbers
+                        .push(d.saturating_mul(10).saturating_add(ch as i32 - b'0' as i32));
                 } else if ch == ';' {
                     self.parsed_numbers.push(0);
                 } else {
                     if let Some(color) = self.parsed_numbers.first() {
                         self.current_sixel_color = *color;
                     }
+                    let valid_register =
+                        (self.current_sixel_color as usize) < MAX_SIXEL_COLOR_REGISTERS;
                     if self.parsed_numbers.len() > 1 {
                         if self.parsed_numbers.len() != 5 {
                             return Err(Box::new(ParserError::InvalidColorInSixelSequence));
                         }
 
                         match self.parsed_numbers.get(1).unwrap() {
+                            // growing the palette to any register would allocate unbounded memory
+                            _ if !valid_register => {}
                             2 => {
+                                let percent = |i: usize| {
+                                    (self.parsed_numbers[i].clamp(0, 100) * 255 / 100) as u8
+                                };
                                 self.current_sixel_palette.set_color_rgb(
                                     self.current_sixel_color as usize,
-                                    (self.parsed_numbers[2] * 255 / 100) as u8,
-                                    (self.parsed_numbers[3] * 255 / 100) as u8,
-                                    (self.parsed_numbers[4] * 255 / 100) as u8,
+                                    percent(2),
+                                    percent(3),
+                                    percent(4),
                                 );
                             }
                             1 => {
+                                // #n;1;h;l;s - DEC hues start with blue at 0°, red is at 120°
+                                let hue = (self.parsed_numbers[2].clamp(0, 360) + 240) % 360;
                                 self.current_sixel_palette.set_color_hsl(
                                     self.current_sixel_color as usize,
-                                    self.parsed_numbers[2] as f32 * 360.0
-                                        / (2.0 * std::f32::consts::PI),
-                                    self.parsed_numbers[4] as f32 / 100.0, // sixel is hls
-                                    self.parsed_numbers[3] as f32 / 100.0,
+                                    hue as f32 / 360.0,
+                                    self.parsed_numbers[4].clamp(0, 100) as f32 / 100.0,
+                                    self.parsed_numbers[3].clamp(0, 100) as f32 / 100.0,
                                 );
                             }
                             n => {
@@ -130,6 +154,7 @@ impl SixelParser {
                             }
                         }
                     }
+                    self.state = SixelState::Read;
                     self.parse_sixel_data(ch)?;
                 }
             }
@@ -139,27 +164,26 @@ impl SixelParser {
                         Some(number) => number,
                         _ => 0,
                     };
-                    self.parsed_numbers.push(d * 10 + ch as i32 - b'0' as i32);
+                    self.parsed_numbers
+                        .push(d.saturating_mul(10).saturating_add(ch as i32 - b'0' as i32));
                 } else if ch == ';' {
                     self.parsed_numbers.push(0);
                 } else {
+                    // "Pan;Pad;Ph;Pv - pixel aspect ratio Pan:Pad and the size of the background area
                     if self.parsed_numbers.len() < 2 || self.parsed_numbers.len() > 4 {
                         return Err(Box::new(ParserError::InvalidPictureSize));
                     }
-                    self.vertical_scale = self.parsed_numbers[0];
-                    self.horizontal_scale = self.parsed_numbers[1];
-                    if self.parsed_numbers.len() == 3 {
-                        let height = self.parsed_numbers[2];
-                        self.picture_data.resize(height as usize, Vec::new());
-                        self.height_set = true;
-                    }
-
+                    self.vertical_scale = self.parsed_numbers[0].max(1);
+                    self.horizontal_scale = self.parsed_numbers[1].max(1);
                     if self.parsed_numbers.len() == 4 {
-                        let height = self.parsed_numbers[3];
-                        let width = self.parsed_numbers[2];
-                        self.picture_data
-                            .resize(height as usize, vec![0; 4 * width as usize]);
-                        self.height_set = true;
+                        let width = self.parsed_numbers[2].max(0) as usize;
+                        let height = self.parsed_numbers[3].max(0) as usize;
+                        if width > MAX_SIXEL_WIDTH || height > MAX_SIXEL_HEIGHT {
+                            return Err(Box::new(ParserError::InvalidPictureSize));
+                        }
+                        let line = self.background.repeat(width);
+                        self.picture_data.resize(height, line);
+                        self.height_set = height > 0;
                     }
                     self.state = SixelState::Read;
                     self.parse_sixel_data(ch)?;
@@ -171,10 +195,12 @@ impl SixelParser {
                         Some(number) => number,
                         _ => 0,
                     };
-                    self.parsed_numbers.push(d * 10 + ch as i32 - '0' as i32);
+                    self.parsed_numbers
+                        .push(d.saturating_mul(10).saturating_add(ch as i32 - '0' as i32));
                 } else {
                     if let Some(i) = self.parsed_numbers.first() {
-                        for _ in 0..*i {
+                        // repeating beyond the maximum width fails anyway
+                        for _ in 0..(*i).min(MAX_SIXEL_WIDTH as i32 + 1) {
                             self.parse_sixel_data(ch)?;
                         }
                     } else {
@@ -188,9 +214,6 @@ impl SixelParser {
     }
 
     fn translate_sixel_to_pixel(&mut self, ch: char) -> EngineResult<()> {
-        /*let current_sixel = buf.layers[0].sixels.len() - 1;
-
-        let sixel = &mut buf.layers[0].sixels[current_sixel];*/
         if ch < '?' {
             return Err(Box::new(ParserError::InvalidSixelChar(ch)));
         }
@@ -200,6 +223,9 @@ impl SixelParser {
             [(self.current_sixel_color as usize) % self.current_sixel_palette.colors.len()];
         let x_pos = self.sixel_cursor.x as usize;
         let y_pos = self.sixel_cursor.y as usize * 6;
+        if x_pos >= MAX_SIXEL_WIDTH || y_pos >= MAX_SIXEL_HEIGHT {
+            return Err(Box::new(ParserError::InvalidPictureSize));
+        }
 
         let mut last_line = y_pos + 6;
         if self.height_set && last_line > self.height() as usize {
@@ -207,8 +233,7 @@ impl SixelParser {
         }
 
         if self.picture_data.len() < last_line {
-            self.picture_data
-                .resize(last_line, vec![0; self.width() as usize * 4]);
+            self.picture_data.resize(last_line, Vec::new());
         }
 
         for i in 0..6 {
@@ -221,8 +246,8 @@ impl SixelParser {
                 let cur_line = &mut self.picture_data[translated_line];
 
                 let offset = x_pos * 4;
-                if cur_line.len() <= offset {
-                    cur_line.resize((x_pos + 1) * 4, 0);
+                while cur_line.len() <= offset {
+                    cur_line.extend_from_slice(&self.background);
                 }
 
                 let (r, g, b) = fg_color.get_rgb();
@@ -258,7 +283,8 @@ impl SixelParser {
                 self.state = SixelState::ReadSize;
             }
             _ => {
-                if ch > '\x7F' {
+                // characters that aren't sixel data are ignored
+                if !('?'..='~').contains(&ch) {
                     return Ok(());
                 }
                 self.translate_sixel_to_pixel(ch)?;
@@ -293,26 +319,95 @@ impl Sixel {
         let y = self.position.y * 16;
         Rectangle {
             start: Position::new(x, y),
-            size: Size::new(self.width as i32, self.height as i32),
+            size: Size::new(self.width as i32, self.get_screen_height()),
+        }
+    }
+
+    /// Height of the picture on screen, pixels are `vertical_scale:horizontal_scale` high.
+    pub fn get_screen_height(&self) -> i32 {
+        let aspect =
+            (self.vertical_scale.max(1) as f32 / self.horizontal_scale.max(1) as f32).round();
+        self.height as i32 * (aspect as i32).max(1)
+    }
+
+    /// Cuts the picture to the part within `rect`, given in picture pixels.
+    /// Returns `false` if nothing is left.
+    pub fn crop(&mut self, rect: Rectangle) -> bool {
+        let x0 = rect.start.x.clamp(0, self.width as i32) as usize;
+        let y0 = rect.start.y.clamp(0, self.height as i32) as usize;
+        let x1 = (rect.start.x + rect.size.width).clamp(x0 as i32, self.width as i32) as usize;
+        let y1 = (rect.start.y + rect.size.height).clamp(y0 as i32, self.height as i32) as usize;
+        let width = self.width as usize;
+        let mut picture_data = Vec::with_capacity((x1 - x0) * (y1 - y0) * 4);
+        for y in y0..y1 {
+            picture_data
+                .extend_from_slice(&self.picture_data[(y * width + x0) * 4..(y * width + x1) * 4]);
         }
+        self.picture_data = picture_data;
+        self.width = (x1 - x0) as u32;
+        self.height = (y1 - y0) as u32;
+        !self.picture_data.is_empty()
     }
 
-    /// .
+    /// Makes the pixels within `rect`, given in picture pixels, transparent.
+    /// Returns `false` if the whole picture is transparent afterwards.
+    pub fn erase(&mut self, rect: Rectangle) -> bool {
+        let x0 = rect.start.x.clamp(0, self.width as i32) as usize;
+        let y0 = rect.start.y.clamp(0, self.height as i32) as usize;
+        let x1 = (rect.start.x + rect.size.width).clamp(x0 as i32, self.width as i32) as usize;
+        let y1 = (rect.start.y + rect.size.height).clamp(y0 as i32, self.height as i32) as usize;
+        let width = self.width as usize;
+        for y in y0..y1 {
+            self.picture_data[(y * width + x0) * 4..(y * width + x1) * 4].fill(0);
+        }
+        self.picture_data.chunks_exact(4).any(|pixel| pixel[3] != 0)
+    }
+
+    /// Decodes the sixel data following the `q` of the DCS, every picture has its own color registers.
+    ///
+    /// `default_bg_color` fills the pixels that aren't drawn, use an alpha of 0 to keep them transparent.
+    /// The pixel aspect ratio `vertical_scale:horizontal_scale` is replaced by the raster attributes.
     ///
     /// # Errors
     ///
-    /// This function will return an error if .
+    /// This function will return an error if the sixel data is invalid.
     pub fn parse_from(
         pos: Position,
         vertical_scale: i32,
         horizontal_scale: i32,
         default_bg_color: [u8; 4],
         data: &str,
+    ) -> EngineResult<Self> {
+        Self::parse_with_palette(
+            pos,
+            vertical_scale,
+            horizontal_scale,
+            default_bg_color,
+            &mut Palette::default(),
+            data,
+        )
+    }
+
+    /// Like [`Sixel::parse_from`] but the color registers are read from & written to `palette`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the sixel data is invalid.
+    pub fn parse_with_palette(
+        pos: Position,
+        vertical_scale: i32,
+        horizontal_scale: i32,
+        default_bg_color: [u8; 4],
+        palette: &mut Palette,
+        data: &str,
     ) -> EngineResult<Self> {
         let mut sixel = Self::new(pos);
         sixel.vertical_scale = vertical_scale;
         sixel.horizontal_scale = horizontal_scale;
-        SixelParser::default().parse_from(&mut sixel, default_bg_color, data)?;
+        let mut parser = SixelParser::new(std::mem::take(palette), default_bg_color);
+        let result = parser.parse_from(&mut sixel, data);
+        *palette = parser.current_sixel_palette;
+        result?;
         Ok(sixel)
     }
 }
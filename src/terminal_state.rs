@@ -38,6 +38,19 @@ pub struct TerminalState {
     pub margins_up_down: Option<(i32, i32)>,
     pub margins_left_right: Option<(i32, i32)>,
     pub mouse_mode: MouseMode,
+    pub mouse_encoding: MouseEncoding,
+    /// Report focus in & out (1004)
+    pub focus_events: bool,
+    /// Wheel events are sent as cursor keys on the alternate screen (1007)
+    pub alternate_scroll: bool,
+    /// Cursor keys send `ESC O` sequences (DECCKM)
+    pub application_cursor_keys: bool,
+    /// Keypad sends `ESC O` sequences (DECKPAM)
+    pub application_keypad: bool,
+    /// Sixels are drawn at the upper left corner of the screen (DECSDM, 80)
+    pub sixel_display_mode: bool,
+    /// Every sixel starts with its own color registers (1070)
+    pub sixel_private_color_registers: bool,
     pub dec_margin_mode_left_right: bool,
 
     pub font_selection_state: FontSelectionState,
@@ -65,16 +78,43 @@ pub enum MouseMode {
     #[allow(non_camel_case_types)]
     VT200_Highlight,
 
+    /// Button event tracking (1002)
     ButtonEvents,
+    /// Any event tracking (1003)
     AnyEvents,
+
+    #[deprecated(note = "focus reporting is set independently, use `TerminalState::focus_events`")]
     FocusEvent,
+    #[deprecated(
+        note = "alternate scroll is set independently, use `TerminalState::alternate_scroll`"
+    )]
     AlternateScroll,
+    #[deprecated(note = "the encoding is set independently, use `MouseEncoding::Utf8`")]
     ExtendedMode,
+    #[deprecated(note = "the encoding is set independently, use `MouseEncoding::SGR`")]
     SGRExtendedMode,
+    #[deprecated(note = "the encoding is set independently, use `MouseEncoding::URXVT`")]
     URXVTExtendedMode,
+    #[deprecated(note = "the encoding is set independently, use `MouseEncoding::SGRPixels`")]
     PixelPosition,
 }
 
+/// Encoding of the mouse reports, set independently of the [`MouseMode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MouseEncoding {
+    /// `ESC [ M` followed by 3 bytes
+    #[default]
+    Default,
+    /// UTF-8 encoded coordinates (1005)
+    Utf8,
+    /// `ESC [ < b ; x ; y M/m` (1006)
+    SGR,
+    /// `ESC [ b ; x ; y M` (1015)
+    URXVT,
+    /// SGR with pixel coordinates (1016)
+    SGRPixels,
+}
+
 impl TerminalState {
     pub fn from(width: i32, height: i32) -> Self {
         let mut ret = Self {
@@ -84,6 +124,13 @@ impl TerminalState {
             origin_mode: OriginMode::UpperLeftCorner,
             auto_wrap_mode: AutoWrapMode::AutoWrap,
             mouse_mode: MouseMode::Default,
+            mouse_encoding: MouseEncoding::Default,
+            focus_events: false,
+            alternate_scroll: false,
+            application_cursor_keys: false,
+            application_keypad: false,
+            sixel_display_mode: false,
+            sixel_private_color_registers: true,
             margins_up_down: None,
             margins_left_right: None,
             use_ice: false,
@@ -169,6 +216,14 @@ impl TerminalState {
         self.origin_mode = OriginMode::UpperLeftCorner;
         self.scroll_state = TerminalScrolling::Smooth;
         self.auto_wrap_mode = AutoWrapMode::AutoWrap;
+        self.mouse_mode = MouseMode::Default;
+        self.mouse_encoding = MouseEncoding::Default;
+        self.focus_events = false;
+        self.alternate_scroll = false;
+        self.application_cursor_keys = false;
+        self.application_keypad = false;
+        self.sixel_display_mode = false;
+        self.sixel_private_color_registers = true;
         self.reset_tabs();
     }
 
@@ -179,6 +234,14 @@ impl TerminalState {
         self.use_ice = use_ice;
     }
 
+    fn get_line_width(&self, buf: &Buffer, line: i32) -> i32 {
+        if buf.get_line_attribute(line).is_double_width() {
+            (self.width + 1) / 2
+        } else {
+            self.width
+        }
+    }
+
     pub fn limit_caret_pos(&self, buf: &Buffer, caret: &mut Caret) {
         match self.origin_mode {
             crate::OriginMode::UpperLeftCorner => {
@@ -188,14 +251,16 @@ impl TerminalState {
                     println!("limit! {}", Backtrace::force_capture());
                 }
                 caret.pos.y = n;*/
-                caret.pos.x = caret.pos.x.clamp(0, max(0, self.width - 1));
+                let width = self.get_line_width(buf, caret.pos.y);
+                caret.pos.x = caret.pos.x.clamp(0, max(0, width - 1));
             }
             crate::OriginMode::WithinMargins => {
                 let first = buf.get_first_editable_line();
                 let height = buf.get_last_editable_line() - first;
                 let n = caret.pos.y.clamp(first, max(first, first + height - 1));
                 caret.pos.y = n;
-                caret.pos.x = caret.pos.x.clamp(0, max(0, self.width - 1));
+                let width = self.get_line_width(buf, caret.pos.y);
+                caret.pos.x = caret.pos.x.clamp(0, max(0, width - 1));
             }
         }
     }
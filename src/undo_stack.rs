@@ -0,0 +1,466 @@
+use std::error::Error;
+
+use crate::{
+    AttributedChar, BitFont, Buffer, EngineResult, Layer, Palette, Position, SauceString, Size,
+};
+
+pub const DEFAULT_UNDO_HISTORY: usize = 1000;
+
+/// A reversible change to a [`Buffer`].
+///
+/// Operations are executed with [`Buffer::execute`] which calls `redo` for the first time,
+/// afterwards `undo` and `redo` are called alternating.
+pub trait UndoOperation {
+    fn get_description(&self) -> String;
+
+    /// Reverts the operation.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the buffer is not in the state the operation left it.
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()>;
+
+    /// Applies the operation.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the operation can't be applied to the buffer.
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()>;
+}
+
+/// Groups operations to be undone & redone as one step.
+pub struct AtomicUndo {
+    description: String,
+    operations: Vec<Box<dyn UndoOperation>>,
+}
+
+impl AtomicUndo {
+    pub fn new(description: impl Into<String>) -> Self {
+        Self {
+            description: description.into(),
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, operation: Box<dyn UndoOperation>) {
+        self.operations.push(operation);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl UndoOperation for AtomicUndo {
+    fn get_description(&self) -> String {
+        self.description.clone()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        for op in self.operations.iter_mut().rev() {
+            op.undo(buffer)?;
+        }
+        Ok(())
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        for op in &mut self.operations {
+            op.redo(buffer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sets a char, the replaced char is kept for undo.
+pub struct UndoSetChar {
+    pub layer: usize,
+    pub pos: Position,
+    pub ch: Option<AttributedChar>,
+}
+
+impl UndoSetChar {
+    fn swap(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        let Some(layer) = buffer.layers.get_mut(self.layer) else {
+            return Err(Box::new(UndoError::LayerOutOfRange(self.layer)));
+        };
+        let old = layer.get_char(self.pos);
+        layer.set_char(self.pos, self.ch);
+        self.ch = old;
+        Ok(())
+    }
+}
+
+impl UndoOperation for UndoSetChar {
+    fn get_description(&self) -> String {
+        "Set char".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer)
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer)
+    }
+}
+
+/// Inserts a layer at `index`.
+pub struct UndoAddLayer {
+    pub index: usize,
+    pub layer: Option<Layer>,
+}
+
+impl UndoOperation for UndoAddLayer {
+    fn get_description(&self) -> String {
+        "Add layer".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        if self.index >= buffer.layers.len() {
+            return Err(Box::new(UndoError::LayerOutOfRange(self.index)));
+        }
+        self.layer = Some(buffer.layers.remove(self.index));
+        Ok(())
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        if self.index > buffer.layers.len() {
+            return Err(Box::new(UndoError::LayerOutOfRange(self.index)));
+        }
+        if let Some(layer) = self.layer.take() {
+            buffer.layers.insert(self.index, layer);
+        }
+        Ok(())
+    }
+}
+
+/// Removes the layer at `index`.
+pub struct UndoRemoveLayer {
+    pub index: usize,
+    layer: Option<Layer>,
+}
+
+impl UndoRemoveLayer {
+    pub fn new(index: usize) -> Self {
+        Self { index, layer: None }
+    }
+}
+
+impl UndoOperation for UndoRemoveLayer {
+    fn get_description(&self) -> String {
+        "Remove layer".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        if self.index > buffer.layers.len() {
+            return Err(Box::new(UndoError::LayerOutOfRange(self.index)));
+        }
+        if let Some(layer) = self.layer.take() {
+            buffer.layers.insert(self.index, layer);
+        }
+        Ok(())
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        if self.index >= buffer.layers.len() {
+            return Err(Box::new(UndoError::LayerOutOfRange(self.index)));
+        }
+        self.layer = Some(buffer.layers.remove(self.index));
+        Ok(())
+    }
+}
+
+/// Moves a layer from one position in the layer list to another.
+pub struct UndoMoveLayer {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl UndoMoveLayer {
+    fn move_layer(buffer: &mut Buffer, from: usize, to: usize) -> EngineResult<()> {
+        let len = buffer.layers.len();
+        if from >= len || to >= len {
+            return Err(Box::new(UndoError::LayerOutOfRange(from.max(to))));
+        }
+        let layer = buffer.layers.remove(from);
+        buffer.layers.insert(to, layer);
+        Ok(())
+    }
+}
+
+impl UndoOperation for UndoMoveLayer {
+    fn get_description(&self) -> String {
+        "Move layer".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        UndoMoveLayer::move_layer(buffer, self.to, self.from)
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        UndoMoveLayer::move_layer(buffer, self.from, self.to)
+    }
+}
+
+/// Changes the buffer size, the content is kept so undo restores it.
+pub struct UndoResizeBuffer {
+    pub size: Size<i32>,
+}
+
+impl UndoResizeBuffer {
+    fn swap(&mut self, buffer: &mut Buffer) {
+        let old = Size::new(buffer.get_buffer_width(), buffer.get_buffer_height());
+        buffer.set_buffer_size(self.size);
+        self.size = old;
+    }
+}
+
+impl UndoOperation for UndoResizeBuffer {
+    fn get_description(&self) -> String {
+        "Resize buffer".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer);
+        Ok(())
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer);
+        Ok(())
+    }
+}
+
+/// Replaces the whole palette.
+pub struct UndoSetPalette {
+    pub palette: Palette,
+}
+
+impl UndoOperation for UndoSetPalette {
+    fn get_description(&self) -> String {
+        "Change palette".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        std::mem::swap(&mut buffer.palette, &mut self.palette);
+        Ok(())
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        std::mem::swap(&mut buffer.palette, &mut self.palette);
+        Ok(())
+    }
+}
+
+/// Sets or removes (`None`) a font slot.
+pub struct UndoSetFont {
+    pub font_number: usize,
+    pub font: Option<BitFont>,
+}
+
+impl UndoSetFont {
+    fn swap(&mut self, buffer: &mut Buffer) {
+        let old = buffer.remove_font(self.font_number);
+        if let Some(font) = self.font.take() {
+            buffer.set_font(self.font_number, font);
+        }
+        self.font = old;
+    }
+}
+
+impl UndoOperation for UndoSetFont {
+    fn get_description(&self) -> String {
+        "Change font".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer);
+        Ok(())
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer);
+        Ok(())
+    }
+}
+
+/// Changes the SAUCE meta data of the buffer.
+pub struct UndoSetSauce {
+    pub title: SauceString<35, b' '>,
+    pub author: SauceString<20, b' '>,
+    pub group: SauceString<20, b' '>,
+    pub comments: Vec<SauceString<64, 0>>,
+}
+
+impl UndoSetSauce {
+    fn swap(&mut self, buffer: &mut Buffer) {
+        std::mem::swap(&mut buffer.title, &mut self.title);
+        std::mem::swap(&mut buffer.author, &mut self.author);
+        std::mem::swap(&mut buffer.group, &mut self.group);
+        std::mem::swap(&mut buffer.comments, &mut self.comments);
+    }
+}
+
+impl UndoOperation for UndoSetSauce {
+    fn get_description(&self) -> String {
+        "Edit SAUCE".to_string()
+    }
+
+    fn undo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer);
+        Ok(())
+    }
+
+    fn redo(&mut self, buffer: &mut Buffer) -> EngineResult<()> {
+        self.swap(buffer);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum UndoError {
+    LayerOutOfRange(usize),
+    NoAtomicUndo,
+}
+
+impl std::fmt::Display for UndoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UndoError::LayerOutOfRange(layer) => write!(f, "layer {layer} out of range"),
+            UndoError::NoAtomicUndo => write!(f, "no atomic undo started"),
+        }
+    }
+}
+
+impl Error for UndoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AttributedChar, Buffer, Layer, Position, SauceString, TextAttribute, UndoAddLayer,
+        UndoMoveLayer, UndoRemoveLayer, UndoSetChar, UndoSetSauce,
+    };
+
+    fn set_char(buf: &mut Buffer, x: i32, ch: char) {
+        buf.execute(Box::new(UndoSetChar {
+            layer: 0,
+            pos: Position::new(x, 0),
+            ch: Some(AttributedChar::new(ch, TextAttribute::default())),
+        }))
+        .unwrap();
+    }
+
+    fn get_char(buf: &Buffer, x: i32) -> char {
+        buf.get_char_from_layer(0, Position::new(x, 0))
+            .map_or(' ', |ch| ch.ch)
+    }
+
+    #[test]
+    fn test_undo_redo_set_char() {
+        let mut buf = Buffer::create(80, 25);
+        set_char(&mut buf, 0, 'a');
+        set_char(&mut buf, 0, 'b');
+        assert_eq!('b', get_char(&buf, 0));
+
+        assert!(buf.undo().unwrap());
+        assert_eq!('a', get_char(&buf, 0));
+        assert!(buf.undo().unwrap());
+        assert_eq!(' ', get_char(&buf, 0));
+        assert!(!buf.undo().unwrap());
+
+        assert!(buf.redo().unwrap());
+        assert!(buf.redo().unwrap());
+        assert_eq!('b', get_char(&buf, 0));
+        assert!(!buf.redo().unwrap());
+
+        buf.undo().unwrap();
+        set_char(&mut buf, 1, 'c');
+        assert!(!buf.can_redo());
+    }
+
+    #[test]
+    fn test_atomic_undo() {
+        let mut buf = Buffer::create(80, 25);
+        buf.begin_atomic_undo("Type");
+        set_char(&mut buf, 0, 'a');
+        set_char(&mut buf, 1, 'b');
+        buf.end_atomic_undo().unwrap();
+        assert_eq!(Some("Type".to_string()), buf.get_undo_description());
+
+        buf.undo().unwrap();
+        assert_eq!(' ', get_char(&buf, 0));
+        assert_eq!(' ', get_char(&buf, 1));
+        assert!(!buf.can_undo());
+
+        buf.redo().unwrap();
+        assert_eq!('a', get_char(&buf, 0));
+        assert_eq!('b', get_char(&buf, 1));
+
+        assert!(buf.end_atomic_undo().is_err());
+    }
+
+    #[test]
+    fn test_max_undo_history() {
+        let mut buf = Buffer::create(80, 25);
+        buf.set_max_undo_history(2);
+        for (x, ch) in "abc".chars().enumerate() {
+            set_char(&mut buf, x as i32, ch);
+        }
+        assert!(buf.undo().unwrap());
+        assert!(buf.undo().unwrap());
+        assert!(!buf.undo().unwrap());
+        assert_eq!('a', get_char(&buf, 0));
+    }
+
+    #[test]
+    fn test_undo_layers() {
+        let mut buf = Buffer::create(80, 25);
+        let mut layer = Layer::new();
+        layer.title = "New".to_string();
+        buf.execute(Box::new(UndoAddLayer {
+            index: 0,
+            layer: Some(layer),
+        }))
+        .unwrap();
+        assert_eq!(3, buf.layers.len());
+        assert_eq!("New", buf.layers[0].title);
+
+        buf.execute(Box::new(UndoMoveLayer { from: 0, to: 2 }))
+            .unwrap();
+        assert_eq!("New", buf.layers[2].title);
+
+        buf.execute(Box::new(UndoRemoveLayer::new(2))).unwrap();
+        assert_eq!(2, buf.layers.len());
+
+        buf.undo().unwrap();
+        assert_eq!("New", buf.layers[2].title);
+        buf.undo().unwrap();
+        assert_eq!("New", buf.layers[0].title);
+        buf.undo().unwrap();
+        assert_eq!(2, buf.layers.len());
+
+        assert!(buf.execute(Box::new(UndoRemoveLayer::new(5))).is_err());
+        assert!(buf.can_redo());
+    }
+
+    #[test]
+    fn test_undo_sauce() {
+        let mut buf = Buffer::create(80, 25);
+        buf.execute(Box::new(UndoSetSauce {
+            title: SauceString::from("Title"),
+            author: SauceString::from("Author"),
+            group: SauceString::new(),
+            comments: Vec::new(),
+        }))
+        .unwrap();
+        assert_eq!("Title", buf.title.to_string());
+        buf.undo().unwrap();
+        assert!(buf.title.is_empty());
+        buf.redo().unwrap();
+        assert_eq!("Author", buf.author.to_string());
+    }
+}